@@ -0,0 +1,211 @@
+use crate::{Context, Flow, Output, RTError, Runtime};
+use std::fmt::{Display, Formatter};
+use wd_tools::PFErr;
+
+//预算上限，None表示不限制
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Budget {
+    pub max_tokens: Option<u64>,
+    pub max_tool_calls: Option<u64>,
+    //费用由服务按模型单价计算后上报
+    pub max_cost: Option<f64>,
+}
+
+//一次服务调用产生的消耗，由服务自行上报
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Usage {
+    pub tokens: u64,
+    pub tool_calls: u64,
+    pub cost: f64,
+}
+
+#[derive(Debug, Default)]
+pub struct ContextBudget {
+    pub limit: Budget,
+    pub used: Usage,
+}
+
+impl Budget {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn max_tokens(mut self, max: u64) -> Self {
+        self.max_tokens = Some(max);
+        self
+    }
+    pub fn max_tool_calls(mut self, max: u64) -> Self {
+        self.max_tool_calls = Some(max);
+        self
+    }
+    pub fn max_cost(mut self, max: f64) -> Self {
+        self.max_cost = Some(max);
+        self
+    }
+}
+
+impl Usage {
+    pub fn tokens(tokens: u64) -> Self {
+        Self {
+            tokens,
+            ..Default::default()
+        }
+    }
+    pub fn tool_call() -> Self {
+        Self {
+            tool_calls: 1,
+            ..Default::default()
+        }
+    }
+    pub fn set_cost(mut self, cost: f64) -> Self {
+        self.cost = cost;
+        self
+    }
+    pub fn add(&mut self, other: &Usage) {
+        self.tokens += other.tokens;
+        self.tool_calls += other.tool_calls;
+        self.cost += other.cost;
+    }
+}
+
+impl Display for Usage {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "tokens[{}] tool_calls[{}] cost[{:.6}]",
+            self.tokens, self.tool_calls, self.cost
+        )
+    }
+}
+
+impl ContextBudget {
+    pub fn check(&self) -> Result<(), RTError> {
+        let Budget {
+            max_tokens,
+            max_tool_calls,
+            max_cost,
+        } = &self.limit;
+        if let Some(max) = max_tokens {
+            if self.used.tokens > *max {
+                return Err(RTError::BudgetExceeded(format!(
+                    "tokens used[{}] > max[{}]",
+                    self.used.tokens, max
+                )));
+            }
+        }
+        if let Some(max) = max_tool_calls {
+            if self.used.tool_calls > *max {
+                return Err(RTError::BudgetExceeded(format!(
+                    "tool calls used[{}] > max[{}]",
+                    self.used.tool_calls, max
+                )));
+            }
+        }
+        if let Some(max) = max_cost {
+            if self.used.cost > *max {
+                return Err(RTError::BudgetExceeded(format!(
+                    "cost used[{:.6}] > max[{:.6}]",
+                    self.used.cost, max
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Context {
+    //预算与子context共享，子任务的消耗计入同一个预算
+    pub fn set_budget(&self, budget: Budget) {
+        let mut lock = self.budget.lock().unwrap();
+        lock.limit = budget;
+    }
+    pub fn used_budget(&self) -> Usage {
+        let lock = self.budget.lock().unwrap();
+        lock.used.clone()
+    }
+    pub fn check_budget(&self) -> anyhow::Result<()> {
+        let lock = self.budget.lock().unwrap();
+        match lock.check() {
+            Ok(_) => Ok(()),
+            Err(e) => anyhow::Error::from(e).err(),
+        }
+    }
+    //上报消耗，超出预算时返回错误
+    pub fn report_usage(&self, usage: Usage) -> anyhow::Result<()> {
+        let mut lock = self.budget.lock().unwrap();
+        lock.used.add(&usage);
+        wd_log::log_debug_ln!(
+            "ctx[{}] report usage:{} total:{}",
+            self.code,
+            usage,
+            lock.used
+        );
+        match lock.check() {
+            Ok(_) => Ok(()),
+            Err(e) => anyhow::Error::from(e).err(),
+        }
+    }
+}
+
+impl Runtime {
+    pub async fn middle_handle_budget_check(flow: Flow) -> anyhow::Result<Output> {
+        flow.ctx.check_budget()?;
+        let ctx = flow.ctx.clone();
+        let out = flow.call().await?;
+        //节点本身未处理上报错误时，在这里兜底终止
+        ctx.check_budget()?;
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{Budget, Output, PlanBuilder, Runtime, Usage, END_NODE_CODE};
+    use wd_tools::PFArc;
+
+    //cargo test budget::test::test_budget_exceeded -- --nocapture
+    #[tokio::test]
+    pub async fn test_budget_exceeded() {
+        let rt = Runtime::default()
+            .register_service_fn("llm", |f| async move {
+                f.ctx.report_usage(Usage::tokens(100).set_cost(0.01))?;
+                Ok(Output::new("llm".to_string()).raw_to_ctx())
+            })
+            .register_service_fn("sub", |f| async move {
+                let plan = PlanBuilder::start(("n1", "llm"), vec![END_NODE_CODE])
+                    .end(Vec::<String>::new(), (END_NODE_CODE, "llm"))
+                    .check_and_build()?;
+                let code = format!("{}.{}", f.ctx.code, f.code);
+                let out = f
+                    .ctx
+                    .sub_ctx(code, plan)
+                    .arc()
+                    .block_on::<String, _>(())
+                    .await?;
+                Ok(Output::new(out).raw_to_ctx())
+            })
+            .launch();
+
+        let plan = PlanBuilder::start(("A", "llm"), vec![END_NODE_CODE])
+            .end(Vec::<String>::new(), (END_NODE_CODE, "llm"))
+            .check_and_build()
+            .unwrap();
+        let ctx = rt.ctx("test_budget_ok", plan);
+        ctx.set_budget(Budget::new().max_tokens(200));
+        let ctx = ctx.arc();
+        let res = ctx.clone().block_on::<String, _>(()).await;
+        assert_eq!("llm", res.unwrap().as_str());
+        assert_eq!(200, ctx.used_budget().tokens);
+
+        let plan = PlanBuilder::start(("A", "llm"), vec!["B"])
+            .sequence(vec![("B", "sub"), (END_NODE_CODE, "llm")], "")
+            .check_and_build()
+            .unwrap();
+        let ctx = rt.ctx("test_budget_exceeded", plan);
+        ctx.set_budget(Budget::new().max_tokens(250));
+        let ctx = ctx.arc();
+        let res = ctx.clone().block_on::<String, _>(()).await;
+        assert!(res.is_err());
+        assert!(format!("{:?}", res).contains("budget exceeded"));
+        assert_eq!(300, ctx.used_budget().tokens);
+    }
+}
@@ -1,4 +1,7 @@
-use crate::{Node, Output, Plan, RTError, Runtime, Service, END_NODE_CODE, END_RESULT_ERROR};
+use crate::{
+    ContextBudget, Node, Output, Plan, RTError, Runtime, Service, END_NODE_CODE, END_RESULT_ERROR,
+};
+use serde_json::Value;
 use std::any::Any;
 use std::collections::{HashMap, VecDeque};
 use std::error::Error;
@@ -15,15 +18,20 @@ pub struct Context {
     pub status: AtomicU8, //0:init 1:running, 2:success, 3:error
     //堆栈信息
     pub stack: Arc<Mutex<ContextStack>>,
+    //预算及消耗，与子context共享
+    pub budget: Arc<Mutex<ContextBudget>>,
     //执行计划
     pub plan: Arc<dyn Plan>,
     //全局扩展字段
     pub extend: Mutex<HashMap<String, Box<dyn Any + Send + Sync + 'static>>>,
+    //rust原生输出的json视图
+    pub json_view: Mutex<HashMap<String, Value>>,
     //结束时回调
     pub over_callback: Option<Mutex<Vec<Box<dyn FnOnce(Arc<Context>) + Send + Sync + 'static>>>>,
     //可能存在父亲流程
     // pub(crate) parent_ctx:Option<Arc<Context>>,
-    // pub(crate) middle:VecDeque<Arc<dyn Service>>,
+    //context级别的中间件，子context会继承
+    pub(crate) middle: VecDeque<Arc<dyn Service>>,
     // pub(crate) nodes:Arc<dyn ServiceLoader>,
     // pub(crate) waker:Arc<dyn WakerWaitPool>,
     pub(crate) runtime: Arc<Runtime>,
@@ -116,18 +124,25 @@ impl Context {
             code: code.into(),
             status: AtomicU8::default(),
             stack: Arc::new(Mutex::new(Default::default())),
+            budget: Arc::new(Mutex::new(Default::default())),
             plan: Arc::new(plan),
             extend: Mutex::new(Default::default()),
+            json_view: Mutex::new(Default::default()),
             over_callback: None,
+            middle: VecDeque::default(),
             runtime,
         }
     }
     pub fn sub_ctx<C: Into<String>, P: Plan + 'static>(&self, code: C, plan: P) -> Self {
         let parent_code = self.code.clone();
         let stack = self.stack.clone();
+        let budget = self.budget.clone();
+        let middle = self.middle.clone();
         Self::new(code, plan, self.runtime.clone()).updates(|x| {
             x.parent_code = Some(parent_code);
             x.stack = stack;
+            x.budget = budget;
+            x.middle = middle;
         })
     }
     pub fn updates(mut self, f: impl FnOnce(&mut Self)) -> Self {
@@ -169,6 +184,35 @@ impl Context {
         let out = function(input);
         Some(out)
     }
+    pub fn set_json_view<S: Into<String>>(&self, key: S, value: Value) {
+        let mut lock = self.json_view.lock().unwrap();
+        lock.insert(key.into(), value);
+    }
+    //按json访问节点输出: 原始Value > Output中的Value或json视图 > json_view
+    pub fn get_json<Out, F: FnOnce(Option<&mut Value>) -> Out>(
+        &self,
+        key: &str,
+        function: F,
+    ) -> Out {
+        {
+            let mut lock = self.extend.lock().unwrap();
+            if let Some(val) = lock.get_mut(key) {
+                if val.is::<Value>() {
+                    return function(val.downcast_mut::<Value>());
+                }
+                if let Some(out) = val.downcast_mut::<Output>() {
+                    if out.any.is::<Value>() {
+                        return function(out.any.downcast_mut::<Value>());
+                    }
+                    if out.json.is_some() {
+                        return function(out.json.as_mut());
+                    }
+                }
+            }
+        }
+        let mut lock = self.json_view.lock().unwrap();
+        function(lock.get_mut(key))
+    }
     pub fn set_box<S: Into<String>>(&self, key: S, value: Box<dyn Any + Send + Sync + 'static>) {
         let mut lock = self.extend.lock().unwrap();
         lock.insert(key.into(), value);
@@ -9,6 +9,7 @@ pub enum RTError {
     RuntimeDisable,
     UnknownNodeId(String),
     FlowLastNodeNil,
+    BudgetExceeded(String),
 
     UNKNOWN(String),
 }
@@ -38,6 +39,9 @@ impl Display for RTError {
             RTError::ContextStatusAbnormal(s) => {
                 write!(f, "ctx status abnormal:{}", s)
             }
+            RTError::BudgetExceeded(s) => {
+                write!(f, "budget exceeded:{}", s)
+            }
             RTError::ContextAbort => {
                 write!(f, "context abort running")
             }
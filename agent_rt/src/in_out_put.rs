@@ -1,9 +1,13 @@
+use serde::Serialize;
+use serde_json::Value;
 use std::any::Any;
 
 #[derive(Debug)]
 pub struct Output {
     pub raw_to_ctx: bool,
     pub any: Box<dyn Any + Send + Sync + 'static>,
+    //rust原生类型输出的json视图，下游可以通过{{code.field}}访问
+    pub json: Option<Value>,
 }
 impl Default for Output {
     fn default() -> Self {
@@ -11,6 +15,7 @@ impl Default for Output {
         Self {
             raw_to_ctx,
             any: Box::new(()),
+            json: None,
         }
     }
 }
@@ -32,6 +37,25 @@ impl Output {
             ..Default::default()
         }
     }
+    //json原生输出，直接以Value存入上下文
+    pub fn json(value: Value) -> Self {
+        Self::new(value).raw_to_ctx()
+    }
+    //rust原生输出，同时保留一份json视图
+    pub fn serialize<T: Serialize + Any + Send + Sync + 'static>(t: T) -> anyhow::Result<Self> {
+        let json = serde_json::to_value(&t)?;
+        Ok(Self::new(t).with_json(json))
+    }
+    pub fn with_json(mut self, json: Value) -> Self {
+        self.json = Some(json);
+        self
+    }
+    pub fn as_json(&self) -> Option<&Value> {
+        match self.any.downcast_ref::<Value>() {
+            Some(s) => Some(s),
+            None => self.json.as_ref(),
+        }
+    }
     pub fn raw_to_ctx(mut self) -> Self {
         self.raw_to_ctx = true;
         self
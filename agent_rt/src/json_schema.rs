@@ -0,0 +1,315 @@
+use serde_json::{Map, Value};
+use wd_tools::PFErr;
+
+//json schema的常用子集:type,enum,const,properties,required,additionalProperties,items,
+//minItems,maxItems,minLength,maxLength,minimum,maximum,anyOf
+//返回所有不匹配的位置，为空表示校验通过
+pub fn validate_json_schema(schema: &Value, value: &Value) -> Vec<String> {
+    let mut errors = vec![];
+    validate_at("$", schema, value, &mut errors);
+    errors
+}
+
+pub fn check_json_schema(schema: &Value, value: &Value) -> anyhow::Result<()> {
+    let errors = validate_json_schema(schema, value);
+    if errors.is_empty() {
+        return Ok(());
+    }
+    anyhow::anyhow!("json schema mismatch: {}", errors.join("; ")).err()
+}
+
+//按schema中的default补全缺失或为null的值，递归处理properties和items
+pub fn fill_json_schema_default(schema: &Value, value: &mut Value) {
+    let schema = match schema {
+        Value::Object(obj) => obj,
+        _ => return,
+    };
+    if value.is_null() {
+        if let Some(d) = schema.get("default") {
+            *value = d.clone();
+        }
+    }
+    match value {
+        Value::Object(obj) => {
+            if let Some(Value::Object(props)) = schema.get("properties") {
+                for (k, s) in props {
+                    match obj.get_mut(k) {
+                        Some(v) => fill_json_schema_default(s, v),
+                        None => {
+                            if s.get("default").is_some() {
+                                let mut v = Value::Null;
+                                fill_json_schema_default(s, &mut v);
+                                obj.insert(k.clone(), v);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        Value::Array(list) => {
+            if let Some(items) = schema.get("items") {
+                for v in list.iter_mut() {
+                    fill_json_schema_default(items, v);
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+pub fn json_type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(n) => {
+            if n.is_f64() {
+                "number"
+            } else {
+                "integer"
+            }
+        }
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+fn type_match(ty: &str, value: &Value) -> bool {
+    match ty {
+        "null" => value.is_null(),
+        "boolean" => value.is_boolean(),
+        "integer" => {
+            value.is_i64()
+                || value.is_u64()
+                || value.as_f64().map(|x| x.fract() == 0.0).unwrap_or(false)
+        }
+        "number" => value.is_number(),
+        "string" => value.is_string(),
+        "array" => value.is_array(),
+        "object" => value.is_object(),
+        _ => true,
+    }
+}
+
+fn validate_at(path: &str, schema: &Value, value: &Value, errors: &mut Vec<String>) {
+    let schema = match schema {
+        Value::Object(obj) => obj,
+        //true或者空schema匹配任意值
+        Value::Bool(b) => {
+            if !b {
+                errors.push(format!("{}: not allowed", path));
+            }
+            return;
+        }
+        _ => return,
+    };
+    if let Some(ty) = schema.get("type") {
+        let ok = match ty {
+            Value::String(s) => type_match(s, value),
+            Value::Array(list) => list
+                .iter()
+                .any(|x| x.as_str().map(|t| type_match(t, value)).unwrap_or(false)),
+            _ => true,
+        };
+        if !ok {
+            errors.push(format!(
+                "{}: expect type {}, found {}",
+                path,
+                ty,
+                json_type_name(value)
+            ));
+            return;
+        }
+    }
+    if let Some(Value::Array(list)) = schema.get("enum") {
+        if !list.contains(value) {
+            errors.push(format!(
+                "{}: {} not in enum {}",
+                path,
+                value,
+                Value::Array(list.clone())
+            ));
+        }
+    }
+    if let Some(c) = schema.get("const") {
+        if c != value {
+            errors.push(format!("{}: expect const {}, found {}", path, c, value));
+        }
+    }
+    if let Some(Value::Array(list)) = schema.get("anyOf") {
+        let ok = list
+            .iter()
+            .any(|s| validate_json_schema(s, value).is_empty());
+        if !ok {
+            errors.push(format!("{}: not match any of schemas", path));
+        }
+    }
+    match value {
+        Value::Object(obj) => validate_object(path, schema, obj, errors),
+        Value::Array(list) => {
+            if let Some(items) = schema.get("items") {
+                for (i, v) in list.iter().enumerate() {
+                    validate_at(format!("{}[{}]", path, i).as_str(), items, v, errors);
+                }
+            }
+            if let Some(min) = schema.get("minItems").and_then(|x| x.as_u64()) {
+                if (list.len() as u64) < min {
+                    errors.push(format!(
+                        "{}: items count {} < minItems {}",
+                        path,
+                        list.len(),
+                        min
+                    ));
+                }
+            }
+            if let Some(max) = schema.get("maxItems").and_then(|x| x.as_u64()) {
+                if list.len() as u64 > max {
+                    errors.push(format!(
+                        "{}: items count {} > maxItems {}",
+                        path,
+                        list.len(),
+                        max
+                    ));
+                }
+            }
+        }
+        Value::String(s) => {
+            let len = s.chars().count() as u64;
+            if let Some(min) = schema.get("minLength").and_then(|x| x.as_u64()) {
+                if len < min {
+                    errors.push(format!("{}: length {} < minLength {}", path, len, min));
+                }
+            }
+            if let Some(max) = schema.get("maxLength").and_then(|x| x.as_u64()) {
+                if len > max {
+                    errors.push(format!("{}: length {} > maxLength {}", path, len, max));
+                }
+            }
+        }
+        Value::Number(n) => {
+            let n = n.as_f64().unwrap_or(0.0);
+            if let Some(min) = schema.get("minimum").and_then(|x| x.as_f64()) {
+                if n < min {
+                    errors.push(format!("{}: {} < minimum {}", path, n, min));
+                }
+            }
+            if let Some(max) = schema.get("maximum").and_then(|x| x.as_f64()) {
+                if n > max {
+                    errors.push(format!("{}: {} > maximum {}", path, n, max));
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+fn validate_object(
+    path: &str,
+    schema: &Map<String, Value>,
+    obj: &Map<String, Value>,
+    errors: &mut Vec<String>,
+) {
+    if let Some(Value::Array(required)) = schema.get("required") {
+        for key in required.iter().filter_map(|x| x.as_str()) {
+            if !obj.contains_key(key) {
+                errors.push(format!("{}.{}: required field is missing", path, key));
+            }
+        }
+    }
+    let props = match schema.get("properties") {
+        Some(Value::Object(props)) => Some(props),
+        _ => None,
+    };
+    for (k, v) in obj {
+        let sub_path = format!("{}.{}", path, k);
+        if let Some(s) = props.and_then(|p| p.get(k)) {
+            validate_at(sub_path.as_str(), s, v, errors);
+            continue;
+        }
+        match schema.get("additionalProperties") {
+            Some(Value::Bool(false)) => {
+                errors.push(format!("{}: additional property is not allowed", sub_path))
+            }
+            Some(s @ Value::Object(_)) => validate_at(sub_path.as_str(), s, v, errors),
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{fill_json_schema_default, validate_json_schema};
+
+    #[test]
+    fn test_json_schema() {
+        let schema = serde_json::json!({
+            "type":"object",
+            "required":["answer","docs"],
+            "additionalProperties":false,
+            "properties":{
+                "answer":{"type":"string","minLength":1},
+                "score":{"type":"number","minimum":0,"maximum":1},
+                "docs":{"type":"array","items":{"type":"object","required":["title"]}},
+                "level":{"enum":["low","high"]}
+            }
+        });
+        let ok = serde_json::json!({
+            "answer":"yes",
+            "score":0.5,
+            "docs":[{"title":"a"}],
+            "level":"low"
+        });
+        assert_eq!(0, validate_json_schema(&schema, &ok).len());
+
+        let bad = serde_json::json!({
+            "answer":"",
+            "score":2,
+            "docs":[{"name":"a"}],
+            "level":"middle",
+            "other":1
+        });
+        let errors = validate_json_schema(&schema, &bad);
+        println!("{:?}", errors);
+        assert_eq!(5, errors.len());
+        assert_eq!(
+            true,
+            errors.contains(&"$.docs[0].title: required field is missing".to_string())
+        );
+    }
+
+    //cargo test json_schema::test::test_json_schema_default -- --nocapture
+    #[test]
+    fn test_json_schema_default() {
+        let schema = serde_json::json!({
+            "type":"object",
+            "properties":{
+                "query":{"type":"string"},
+                "lang":{"type":"string","default":"zh"},
+                "top_k":{"type":"integer","default":3},
+                "filter":{
+                    "type":"object",
+                    "default":{},
+                    "properties":{"kb":{"type":"string","default":"main"}}
+                },
+                "docs":{"type":"array","items":{"type":"object","properties":{"score":{"default":0}}}}
+            }
+        });
+        let mut value = serde_json::json!({
+            "query":"hi",
+            "top_k":null,
+            "docs":[{"score":1},{}]
+        });
+        fill_json_schema_default(&schema, &mut value);
+        assert_eq!(
+            value,
+            serde_json::json!({
+                "query":"hi",
+                "lang":"zh",
+                "top_k":3,
+                "filter":{"kb":"main"},
+                "docs":[{"score":1},{"score":0}]
+            })
+        );
+        assert!(validate_json_schema(&schema, &value).is_empty());
+    }
+}
@@ -1,18 +1,24 @@
+mod budget;
 mod context;
 mod default_node_loader;
 mod default_waker_pool;
 mod define;
 mod error;
 mod in_out_put;
+mod json_schema;
+mod middle_scope;
 mod plan;
 mod runtime;
 mod runtime_middle;
 mod service_layer;
 
+pub use budget::*;
 pub use context::*;
 pub use define::*;
 pub use error::*;
 pub use in_out_put::*;
+pub use json_schema::*;
+pub use middle_scope::*;
 pub use plan::*;
 pub use runtime::*;
 #[allow(unused_imports)]
@@ -199,4 +205,76 @@ mod tests {
         println!("{:?}", res);
         assert_eq!(true, res.is_err());
     }
+
+    #[derive(Debug, serde::Serialize)]
+    struct Answer {
+        text: String,
+        score: f32,
+    }
+
+    //cargo test tests::test_runtime_json_output -- --nocapture
+    #[tokio::test]
+    pub async fn test_runtime_json_output() {
+        let rt = Runtime::default()
+            .register_service_fn("native", |_| async move {
+                Output::serialize(Answer {
+                    text: "native".into(),
+                    score: 0.5,
+                })
+            })
+            .register_service_fn("json", |_| async move {
+                Ok(Output::new(serde_json::json!({"text":"json"})))
+            })
+            .register_service_fn("read", |f| async move {
+                let native = f.ctx.get_json("A", |x| x.map(|x| x["text"].clone()));
+                let json = f.ctx.get_json("B", |x| x.map(|x| x["text"].clone()));
+                let result = format!("{:?}-{:?}", native, json);
+                Ok(Output::new(result).raw_to_ctx())
+            })
+            .register_output_schema(
+                "json",
+                serde_json::json!({"type":"object","required":["text"]}),
+            )
+            .register_output_schema(
+                "native",
+                serde_json::json!({"type":"object","properties":{"score":{"maximum":1}}}),
+            )
+            .launch();
+
+        let plan = PlanBuilder::start(("A", "native"), vec!["B"])
+            .sequence(vec![("B", "json"), (END_NODE_CODE, "read")], "")
+            .check_and_build()
+            .unwrap();
+        let res = rt
+            .ctx("test_json_output", plan)
+            .arc()
+            .block_on::<String, _>(())
+            .await
+            .unwrap();
+        assert_eq!(
+            r#"Some(String("native"))-Some(String("json"))"#,
+            res.as_str()
+        );
+
+        let rt = Runtime::default()
+            .register_service_fn("json", |_| async move {
+                Ok(Output::json(serde_json::json!({"answer":"json"})))
+            })
+            .register_output_schema(
+                "json",
+                serde_json::json!({"type":"object","required":["text"]}),
+            )
+            .launch();
+        let plan = PlanBuilder::single_node("json", "").build();
+        let res = rt
+            .ctx("test_json_output_schema", plan)
+            .arc()
+            .block_on::<serde_json::Value, _>(())
+            .await;
+        println!("{:?}", res);
+        assert_eq!(
+            true,
+            format!("{:?}", res).contains("$.text: required field is missing")
+        );
+    }
 }
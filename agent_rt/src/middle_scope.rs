@@ -0,0 +1,176 @@
+use crate::{Context, Flow, Output, Runtime, Service, ServiceFn};
+use std::collections::HashSet;
+use std::future::Future;
+use std::sync::Arc;
+use wd_tools::PFArc;
+
+//决定一个中间件是否作用于当前节点
+pub enum MiddleScope {
+    All,
+    NodeTypes(HashSet<String>),
+    Predicate(Box<dyn Fn(&Flow) -> bool + Send + Sync + 'static>),
+}
+
+impl MiddleScope {
+    pub fn node_types<S: Into<String>, I: IntoIterator<Item = S>>(ids: I) -> Self {
+        MiddleScope::NodeTypes(ids.into_iter().map(|x| x.into()).collect())
+    }
+    pub fn predicate(function: impl Fn(&Flow) -> bool + Send + Sync + 'static) -> Self {
+        MiddleScope::Predicate(Box::new(function))
+    }
+    pub fn is_match(&self, flow: &Flow) -> bool {
+        match self {
+            MiddleScope::All => true,
+            MiddleScope::NodeTypes(set) => set.contains(flow.node_type_id.as_str()),
+            MiddleScope::Predicate(function) => function(flow),
+        }
+    }
+}
+
+//不命中范围的节点直接跳过该中间件，继续向下调用
+pub struct ScopedMiddle {
+    scope: MiddleScope,
+    inner: Arc<dyn Service>,
+}
+
+impl ScopedMiddle {
+    pub fn new<S: Service + 'static>(scope: MiddleScope, service: S) -> Self {
+        let inner = service.arc();
+        Self { scope, inner }
+    }
+}
+
+#[async_trait::async_trait]
+impl Service for ScopedMiddle {
+    async fn call(&self, flow: Flow) -> anyhow::Result<Output> {
+        if self.scope.is_match(&flow) {
+            self.inner.call(flow).await
+        } else {
+            flow.call().await
+        }
+    }
+}
+
+impl Runtime {
+    pub fn register_scoped_middle<Mid: Service + 'static>(
+        self,
+        scope: MiddleScope,
+        service: Mid,
+    ) -> Self {
+        self.register_middle(ScopedMiddle::new(scope, service))
+    }
+    pub fn register_middle_by_type<
+        S: Into<String>,
+        I: IntoIterator<Item = S>,
+        T: Future<Output = anyhow::Result<Output>> + Send + 'static,
+        F: Fn(Flow) -> T + Send + Sync + 'static,
+    >(
+        self,
+        node_type_ids: I,
+        service: F,
+    ) -> Self {
+        self.register_scoped_middle(
+            MiddleScope::node_types(node_type_ids),
+            ServiceFn::new(service),
+        )
+    }
+    pub fn register_middle_when<
+        P: Fn(&Flow) -> bool + Send + Sync + 'static,
+        T: Future<Output = anyhow::Result<Output>> + Send + 'static,
+        F: Fn(Flow) -> T + Send + Sync + 'static,
+    >(
+        self,
+        predicate: P,
+        service: F,
+    ) -> Self {
+        self.register_scoped_middle(MiddleScope::predicate(predicate), ServiceFn::new(service))
+    }
+}
+
+impl Context {
+    //只作用于当前context(及其子context)的中间件，在全局中间件之后执行
+    pub fn push_middle<Mid: Service + 'static>(mut self, service: Mid) -> Self {
+        self.middle.push_back(service.arc());
+        self
+    }
+    pub fn push_middle_fn<
+        T: Future<Output = anyhow::Result<Output>> + Send + 'static,
+        F: Fn(Flow) -> T + Send + Sync + 'static,
+    >(
+        self,
+        service: F,
+    ) -> Self {
+        self.push_middle(ServiceFn::new(service))
+    }
+    pub fn push_scoped_middle<Mid: Service + 'static>(
+        self,
+        scope: MiddleScope,
+        service: Mid,
+    ) -> Self {
+        self.push_middle(ScopedMiddle::new(scope, service))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{MiddleScope, Output, PlanBuilder, Runtime, END_NODE_CODE};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use wd_tools::PFArc;
+
+    //cargo test middle_scope::test::test_scoped_middle -- --nocapture
+    #[tokio::test]
+    pub async fn test_scoped_middle() {
+        let by_type = Arc::new(AtomicUsize::new(0));
+        let by_predicate = Arc::new(AtomicUsize::new(0));
+        let by_ctx = Arc::new(AtomicUsize::new(0));
+
+        let count = by_type.clone();
+        let pred_count = by_predicate.clone();
+        let rt = Runtime::default()
+            .register_service_fn(
+                "node1",
+                |_| async move { Ok(Output::new("node1".to_string())) },
+            )
+            .register_service_fn("node2", |_| async move {
+                Ok(Output::new("node2".to_string()).raw_to_ctx())
+            })
+            .register_middle_by_type(vec!["node1"], move |f| {
+                count.fetch_add(1, Ordering::Relaxed);
+                f.call()
+            })
+            .register_middle_when(
+                |f| f.code == END_NODE_CODE,
+                move |f| {
+                    pred_count.fetch_add(1, Ordering::Relaxed);
+                    f.call()
+                },
+            )
+            .launch();
+
+        let plan = PlanBuilder::start(("A", "node1"), vec!["B"])
+            .sequence(vec![("B", "node1"), (END_NODE_CODE, "node2")], "")
+            .check_and_build()
+            .unwrap();
+
+        let ctx_count = by_ctx.clone();
+        let res = rt
+            .ctx("test_scoped_middle", plan)
+            .push_scoped_middle(
+                MiddleScope::node_types(vec!["node2"]),
+                crate::ServiceFn::new(move |f: crate::Flow| {
+                    ctx_count.fetch_add(1, Ordering::Relaxed);
+                    f.call()
+                }),
+            )
+            .arc()
+            .block_on::<String, _>(())
+            .await
+            .unwrap();
+
+        assert_eq!("node2", res.as_str());
+        assert_eq!(2, by_type.load(Ordering::Relaxed));
+        assert_eq!(1, by_predicate.load(Ordering::Relaxed));
+        assert_eq!(1, by_ctx.load(Ordering::Relaxed));
+    }
+}
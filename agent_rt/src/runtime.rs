@@ -4,8 +4,9 @@ use crate::{
     Context, CtxStatus, Flow, NextNodeResult, Output, Plan, RTError, Service, ServiceFn,
     ServiceLoader, WakerCallBack, WakerWaitPool, START_NODE_CODE,
 };
+use serde_json::Value;
 use std::any::Any;
-use std::collections::VecDeque;
+use std::collections::{HashMap, VecDeque};
 use std::future::Future;
 use std::marker::PhantomData;
 use std::ops::Deref;
@@ -22,6 +23,8 @@ pub struct Runtime {
     pub(crate) middle: VecDeque<Arc<dyn Service>>,
     pub(crate) nodes: Arc<dyn ServiceLoader>,
     pub(crate) waker: Arc<dyn WakerWaitPool>,
+    //node_type_id -> 输出的json schema
+    pub(crate) output_schema: HashMap<String, Value>,
 }
 
 impl Runtime {
@@ -30,11 +33,13 @@ impl Runtime {
         let middle = VecDeque::default();
         let nodes = Arc::new(sl);
         let waker = Arc::new(waker);
+        let output_schema = HashMap::new();
         Self {
             status,
             middle,
             nodes,
             waker,
+            output_schema,
         }
     }
     pub fn register_middle<Mid: Service + 'static>(mut self, service: Mid) -> Self {
@@ -58,6 +63,11 @@ impl Runtime {
         self.nodes.set(vec![(id.into(), Arc::new(service))]);
         self
     }
+    //声明节点输出的json schema，输出不匹配时节点报错
+    pub fn register_output_schema<ID: Into<String>>(mut self, id: ID, schema: Value) -> Self {
+        self.output_schema.insert(id.into(), schema);
+        self
+    }
     pub fn register_service_fn<
         ID: Into<String>,
         T: Future<Output = anyhow::Result<Output>> + Send + Sync + 'static,
@@ -127,6 +137,7 @@ impl Runtime {
         };
         for i in nodes {
             let mut middle = ctx.runtime.middle.clone();
+            middle.extend(ctx.middle.iter().cloned());
             match ctx.runtime.nodes.get(i.node_type_id.as_str()) {
                 None => {
                     let err = RTError::UnknownNodeId(i.node_type_id);
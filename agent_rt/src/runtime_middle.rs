@@ -1,4 +1,5 @@
-use crate::{CtxStatus, Flow, Output, RTError, Runtime, END_NODE_CODE};
+use crate::{check_json_schema, CtxStatus, Flow, Output, RTError, Runtime, END_NODE_CODE};
+use serde_json::Value;
 
 impl Runtime {
     // pub async fn middle_handle_ctx_over_callback(flow: Flow) -> anyhow::Result<Output> {
@@ -30,10 +31,36 @@ impl Runtime {
     // }
     pub async fn middle_handle_save_output_to_ctx(flow: Flow) -> anyhow::Result<Output> {
         let code = flow.code.clone();
+        let node_type_id = flow.node_type_id.clone();
         let ctx = flow.ctx.clone();
         let output = flow.call().await?;
+        if let Some(schema) = ctx.runtime.output_schema.get(node_type_id.as_str()) {
+            let json = match output.as_json() {
+                Some(s) => s,
+                None => {
+                    return RTError::UNKNOWN(format!(
+                        "node[{}] type[{}] declared output schema, but output is not json",
+                        code, node_type_id
+                    ))
+                    .anyhow()
+                }
+            };
+            if let Err(e) = check_json_schema(schema, json) {
+                return RTError::UNKNOWN(format!(
+                    "node[{}] type[{}] output error:{}",
+                    code, node_type_id, e
+                ))
+                .anyhow();
+            }
+        }
         if output.raw_to_ctx {
-            ctx.set_box(code, output.any);
+            let Output { any, json, .. } = output;
+            if let Some(json) = json {
+                if !any.is::<Value>() {
+                    ctx.set_json_view(code.clone(), json);
+                }
+            }
+            ctx.set_box(code, any);
         } else {
             ctx.set(code, output);
         }
@@ -76,6 +103,7 @@ impl Runtime {
             //     .register_middle_fn(Runtime::middle_handle_waker_waiter)
             .register_middle_fn(Runtime::middle_handle_status_check)
             .register_middle_fn(Runtime::middle_handle_stack_check)
+            .register_middle_fn(Runtime::middle_handle_budget_check)
             .register_middle_fn(Runtime::middle_handle_save_output_to_ctx)
     }
 }
@@ -63,7 +63,7 @@ where
             Ok(o) => o,
             Err(e) => return anyhow::anyhow!("code[{}],output json error:{}", node_info, e).err(),
         };
-        Output::new(raw).raw_to_ctx().ok()
+        Output::json(raw).ok()
     }
 }
 
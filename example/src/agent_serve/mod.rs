@@ -2,10 +2,15 @@ pub mod common;
 mod serve_entity;
 
 use crate::proto;
+use wd_agent::remote::RemoteService;
+use std::sync::Arc;
 use wd_agent::rt_node_service::{
-    InjectorService, PythonCodeService, SelectorService, WorkflowService,
+    AgentService, ChatMemoryLoadService, ChatMemorySaveService, DocumentLoaderService,
+    EmbeddingService, FileChatStore, HttpRequestService, InjectorService, LLMService,
+    LocalVectorStore, PythonCodeService, RhaiScriptService, SelectorService, ToolBatchService,
+    VectorQueryService, VectorUpsertService, WorkflowService,
 };
-use crate::tools::default_tool_service;
+use crate::tools::{default_tool_event, default_tool_service};
 
 pub async fn start(addr: &str) {
     //create service
@@ -15,16 +20,53 @@ pub async fn start(addr: &str) {
         .await
         .unwrap();
     let tool = default_tool_service();
+    //会话历史，按session_id保存在本地文件
+    let chat_store = Arc::new(FileChatStore::default());
+    //检索用的向量库，按collection保存在本地文件
+    let vector_store = Arc::new(LocalVectorStore::new("./vector_store"));
+    //转发到worker执行的python节点
+    let remote_python = RemoteService::new("http://127.0.0.1:50003")
+        .unwrap()
+        .remote_node_type("python");
 
     //build agent runtime
     let rt = agent_rt::Runtime::default()
         .register_service_layer("openai_llm", openai_llm)
+        .register_service_layer("llm", LLMService::default())
+        .register_service_layer("zhipu-glm", LLMService::default().default_provider("zhipu"))
         .register_service_layer("python", python)
+        .register_service_layer("rhai", RhaiScriptService::default())
+        .register_service("remote_python", remote_python)
         .register_service_layer("flow_chart_selector", SelectorService::default())
         .register_service_layer("flow_chart_injector", InjectorService::default())
         .register_service_layer("workflow", WorkflowService::default())
+        .register_service_layer("http_request", HttpRequestService::default())
         .register_service_layer("tool", tool)
-        .register_service_layer("flow_chart_var", var);
+        .register_service_layer("tool_batch", ToolBatchService::from(default_tool_event()))
+        .register_service_layer(
+            "agent",
+            AgentService::new(LLMService::default(), default_tool_event()),
+        )
+        .register_service_layer("flow_chart_var", var)
+        .register_service_layer(
+            "chat_memory_load",
+            ChatMemoryLoadService::new(chat_store.clone()),
+        )
+        .register_service_layer("chat_memory_save", ChatMemorySaveService::new(chat_store))
+        //按path加载只允许读取./docs下的文件
+        .register_service_layer(
+            "document_loader",
+            DocumentLoaderService::default().root("./docs"),
+        )
+        .register_service_layer("embedding", EmbeddingService::default())
+        .register_service_layer(
+            "vector_upsert",
+            VectorUpsertService::new(vector_store.clone(), EmbeddingService::default()),
+        )
+        .register_service_layer(
+            "retrieve",
+            VectorQueryService::new(vector_store, EmbeddingService::default()),
+        );
 
     //启动rpc服务
     let app = serve_entity::AgentServeEntity::new(rt);
@@ -19,7 +19,7 @@ pub struct AgentServeEntity {
 }
 impl AgentServeEntity {
     pub fn new(rt: agent_rt::Runtime) -> Self {
-        let rt = rt.register_middle_fn(Self::debug_channel_send).launch();
+        let rt = rt.launch();
         Self { rt }
     }
     pub async fn debug_channel_send(flow: Flow) -> anyhow::Result<Output> {
@@ -43,7 +43,7 @@ impl AgentServeEntity {
         match &result {
             Ok(out) => {
                 resp.message = "success".into();
-                if let Some(val) = out.any.downcast_ref::<serde_json::Value>() {
+                if let Some(val) = out.as_json() {
                     if let Some(ref mut s) = resp.result {
                         s.output = super::common::serde_value_to_prost_struct(val)
                     }
@@ -98,6 +98,7 @@ impl proto::agent_service_server::AgentService for AgentServeEntity {
         let ctx = self
             .rt
             .ctx(task_code, plan)
+            .push_middle_fn(Self::debug_channel_send)
             .push_callback(|c| {
                 if c.status() != CtxStatus::SUCCESS {
                     let channel = c.get(
@@ -1,12 +1,18 @@
 use wd_agent::plugin_tools::PluginControlSchedule;
-use wd_agent::rt_node_service::ToolService;
+use wd_agent::rt_node_service::{ToolEvent, ToolService};
 
 pub mod google_search;
 
-pub fn default_tool_service()->ToolService{
-    ToolService::from(PluginControlSchedule::default()
+pub fn default_tool_event()->impl ToolEvent + Sync{
+    PluginControlSchedule::default()
         .register_plugin("google",google_search::GooglePlugin::default())
-        .to_tool_event())
+        //agent_loop示例中的送礼物工具
+        .register_plugin("taobao_shop",|_| async move { Ok("success".to_string()) })
+        .to_tool_event()
+}
+
+pub fn default_tool_service()->ToolService{
+    ToolService::from(default_tool_event())
 }
 
 #[cfg(test)]
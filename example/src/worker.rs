@@ -0,0 +1,24 @@
+mod tools;
+
+use crate::tools::default_tool_service;
+use wd_agent::remote::RemoteWorker;
+use wd_agent::rt_node_service::PythonCodeService;
+
+//托管较重的服务，由serve通过RemoteService转发调用
+#[tokio::main]
+async fn main() {
+    let openai_llm = wd_agent::rt_node_service::OpenaiLLMService::default();
+    let var = wd_agent::rt_node_service::VarFlowChartService::default();
+    let python = PythonCodeService::new("http://127.0.0.1:50001")
+        .await
+        .unwrap();
+    let tool = default_tool_service();
+
+    let rt = agent_rt::Runtime::default()
+        .register_service_layer("openai_llm", openai_llm)
+        .register_service_layer("python", python)
+        .register_service_layer("tool", tool)
+        .register_service_layer("flow_chart_var", var);
+
+    RemoteWorker::new(rt).run("0.0.0.0:50003").await.unwrap();
+}
@@ -1,4 +1,7 @@
+pub mod llm_provider;
 pub mod plugin_tools;
+pub mod remote;
+pub mod rt_middle;
 pub mod rt_node_service;
 
 #[cfg(test)]
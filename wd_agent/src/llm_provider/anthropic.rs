@@ -0,0 +1,365 @@
+use crate::llm_provider::provider::{json_str, json_u64, post_json, read_json, LineReader};
+use crate::llm_provider::{
+    ChatContentPart, ChatDelta, ChatMessage, ChatRequest, ChatResponse, ChatRole, ChatToolCall,
+    ChatUsage, LLMProvider,
+};
+use serde_json::{json, Value};
+use wd_tools::PFErr;
+
+//anthropic messages api
+#[derive(Debug, Clone)]
+pub struct AnthropicProvider {
+    client: reqwest::Client,
+    pub base_url: String,
+    pub api_key: String,
+    pub version: String,
+    pub default_model: String,
+}
+
+impl Default for AnthropicProvider {
+    fn default() -> Self {
+        let base_url =
+            std::env::var("ANTHROPIC_BASE_URL").unwrap_or("https://api.anthropic.com/v1".into());
+        let api_key = std::env::var("ANTHROPIC_API_KEY").unwrap_or_default();
+        Self::new(base_url, api_key)
+    }
+}
+
+impl AnthropicProvider {
+    pub fn new<U: Into<String>, K: Into<String>>(base_url: U, api_key: K) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+            api_key: api_key.into(),
+            version: "2023-06-01".into(),
+            default_model: "claude-3-5-sonnet-latest".into(),
+        }
+    }
+    pub fn default_model<S: Into<String>>(mut self, model: S) -> Self {
+        self.default_model = model.into();
+        self
+    }
+    pub fn client(mut self, client: reqwest::Client) -> Self {
+        self.client = client;
+        self
+    }
+
+    fn part_block(part: &ChatContentPart) -> Value {
+        match part {
+            ChatContentPart::Text { text } => json!({"type":"text","text":text}),
+            ChatContentPart::ImageUrl { url, .. } => json!({
+                "type":"image",
+                "source":{"type":"url","url":url},
+            }),
+            ChatContentPart::ImageBase64 { media_type, data } => json!({
+                "type":"image",
+                "source":{"type":"base64","media_type":media_type,"data":data},
+            }),
+        }
+    }
+    fn content_blocks(msg: &ChatMessage) -> Vec<Value> {
+        let mut blocks = vec![];
+        if !msg.content.is_empty() {
+            blocks.push(json!({"type":"text","text":msg.content}));
+        }
+        blocks.extend(msg.parts.iter().map(Self::part_block));
+        blocks
+    }
+    fn message_blocks(msg: &ChatMessage) -> Vec<Value> {
+        let mut blocks = vec![];
+        match msg.role {
+            ChatRole::Tool => {
+                let content = if msg.parts.is_empty() {
+                    Value::String(msg.content.clone())
+                } else {
+                    Value::Array(Self::content_blocks(msg))
+                };
+                blocks.push(json!({
+                    "type":"tool_result",
+                    "tool_use_id":msg.tool_call_id,
+                    "content":content,
+                }))
+            }
+            //没有对应的function角色，作为user文本发送
+            ChatRole::Function => blocks.push(json!({
+                "type":"text",
+                "text":format!("function[{}] result:{}", msg.name, msg.text()),
+            })),
+            _ => {
+                blocks = Self::content_blocks(msg);
+                for i in msg.tool_calls.iter() {
+                    blocks.push(json!({
+                        "type":"tool_use",
+                        "id":i.id,
+                        "name":i.name,
+                        "input":i.arguments_value(),
+                    }));
+                }
+            }
+        }
+        blocks
+    }
+    //system单独放在顶层，tool结果作为user消息，相邻同角色的消息合并
+    pub fn request_body(req: &ChatRequest, stream: bool) -> Value {
+        let mut system = vec![];
+        let mut messages: Vec<(&str, Vec<Value>)> = vec![];
+        for msg in req.messages.iter() {
+            let role = match msg.role {
+                ChatRole::System => {
+                    system.push(msg.content.clone());
+                    continue;
+                }
+                ChatRole::Assistant => "assistant",
+                ChatRole::User | ChatRole::Tool | ChatRole::Function => "user",
+            };
+            let blocks = Self::message_blocks(msg);
+            match messages.last_mut() {
+                Some((r, list)) if *r == role => list.extend(blocks),
+                _ => messages.push((role, blocks)),
+            }
+        }
+        let mut body = json!({
+            "model":req.model,
+            "max_tokens":req.max_tokens,
+            "temperature":req.temperature,
+            "messages":messages
+                .into_iter()
+                .map(|(role, content)| json!({"role":role,"content":content}))
+                .collect::<Vec<_>>(),
+            "stream":stream,
+        });
+        if !system.is_empty() {
+            body["system"] = Value::String(system.join("\n"));
+        }
+        if !req.tools.is_empty() {
+            body["tools"] = req
+                .tools
+                .iter()
+                .map(|x| {
+                    json!({
+                        "name":x.name,
+                        "description":x.description,
+                        "input_schema":x.parameters,
+                    })
+                })
+                .collect();
+        }
+        body
+    }
+    fn parse_usage(usage: &Value, resp: &mut ChatResponse) {
+        let mut old = resp.usage.clone().unwrap_or_default();
+        if let Some(n) = usage.get("input_tokens").and_then(|x| x.as_u64()) {
+            old.prompt_tokens = n;
+        }
+        if let Some(n) = usage.get("output_tokens").and_then(|x| x.as_u64()) {
+            old.completion_tokens = n;
+        }
+        resp.usage = Some(ChatUsage::new(old.prompt_tokens, old.completion_tokens));
+    }
+    pub fn parse_response(value: Value) -> anyhow::Result<ChatResponse> {
+        let blocks = match value.get("content") {
+            Some(Value::Array(list)) => list,
+            _ => return anyhow::anyhow!("response has no content:{}", value).err(),
+        };
+        let mut resp = ChatResponse {
+            model: json_str(&value, "model"),
+            finish_reason: json_str(&value, "stop_reason"),
+            ..Default::default()
+        };
+        for i in blocks {
+            match i.get("type").and_then(|x| x.as_str()) {
+                Some("text") => resp.content.push_str(json_str(i, "text").as_str()),
+                Some("tool_use") => resp.tool_calls.push(ChatToolCall::new(
+                    json_str(i, "id"),
+                    json_str(i, "name"),
+                    i.get("input").cloned().unwrap_or_default().to_string(),
+                )),
+                _ => {}
+            }
+        }
+        if let Some(usage) = value.get("usage") {
+            Self::parse_usage(usage, &mut resp);
+        }
+        Ok(resp)
+    }
+    async fn post(&self, body: &Value) -> anyhow::Result<reqwest::Response> {
+        let url = format!("{}/messages", self.base_url);
+        let headers = [
+            ("x-api-key", self.api_key.clone()),
+            ("anthropic-version", self.version.clone()),
+        ];
+        post_json(&self.client, url.as_str(), &headers, body).await
+    }
+}
+
+#[async_trait::async_trait]
+impl LLMProvider for AnthropicProvider {
+    fn name(&self) -> &str {
+        "anthropic"
+    }
+
+    fn default_model(&self) -> &str {
+        self.default_model.as_str()
+    }
+
+    async fn chat(&self, req: ChatRequest) -> anyhow::Result<ChatResponse> {
+        let body = Self::request_body(&req, false);
+        let resp = self.post(&body).await?;
+        Self::parse_response(read_json(resp).await?)
+    }
+
+    async fn chat_stream(
+        &self,
+        req: ChatRequest,
+        mut delta: ChatDelta,
+    ) -> anyhow::Result<ChatResponse> {
+        let body = Self::request_body(&req, true);
+        let mut reader = LineReader::new(self.post(&body).await?);
+        let mut resp = ChatResponse::default();
+        //content block index -> tool call index
+        let mut tool_index = std::collections::HashMap::new();
+        while let Some(data) = reader.next_sse_data().await? {
+            let event = serde_json::from_str::<Value>(data.as_str())?;
+            match json_str(&event, "type").as_str() {
+                "message_start" => {
+                    let msg = event.get("message").cloned().unwrap_or_default();
+                    resp.model = json_str(&msg, "model");
+                    if let Some(usage) = msg.get("usage") {
+                        Self::parse_usage(usage, &mut resp);
+                    }
+                }
+                "content_block_start" => {
+                    let block = event.get("content_block").cloned().unwrap_or_default();
+                    if json_str(&block, "type") == "tool_use" {
+                        tool_index.insert(json_u64(&event, "index"), resp.tool_calls.len());
+                        resp.tool_calls.push(ChatToolCall::new(
+                            json_str(&block, "id"),
+                            json_str(&block, "name"),
+                            "",
+                        ));
+                    }
+                }
+                "content_block_delta" => {
+                    let d = event.get("delta").cloned().unwrap_or_default();
+                    match json_str(&d, "type").as_str() {
+                        "text_delta" => {
+                            let text = json_str(&d, "text");
+                            delta(text.as_str());
+                            resp.content.push_str(text.as_str());
+                        }
+                        "input_json_delta" => {
+                            let index = json_u64(&event, "index");
+                            if let Some(i) = tool_index.get(&index) {
+                                resp.tool_calls[*i]
+                                    .arguments
+                                    .push_str(json_str(&d, "partial_json").as_str());
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+                "message_delta" => {
+                    if let Some(reason) = event
+                        .get("delta")
+                        .and_then(|x| x.get("stop_reason"))
+                        .and_then(|x| x.as_str())
+                    {
+                        resp.finish_reason = reason.to_string();
+                    }
+                    if let Some(usage) = event.get("usage") {
+                        Self::parse_usage(usage, &mut resp);
+                    }
+                }
+                "message_stop" => break,
+                "error" => {
+                    return anyhow::anyhow!("anthropic stream error:{}", event).err();
+                }
+                _ => {}
+            }
+        }
+        Ok(resp)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::llm_provider::mock::MockServer;
+    use crate::llm_provider::{
+        AnthropicProvider, ChatContentPart, ChatMessage, ChatRequest, ChatToolCall, LLMProvider,
+    };
+
+    //cargo test llm_provider::anthropic::test::test_anthropic_provider -- --nocapture
+    #[tokio::test]
+    async fn test_anthropic_provider() {
+        let server = MockServer::start(vec![
+            MockServer::json(serde_json::json!({
+                "model":"claude-mock",
+                "stop_reason":"tool_use",
+                "content":[
+                    {"type":"text","text":"let me check"},
+                    {"type":"tool_use","id":"toolu_1","name":"weather","input":{"city":"bj"}}
+                ],
+                "usage":{"input_tokens":12,"output_tokens":8}
+            })),
+            MockServer::sse(vec![
+                r#"{"type":"message_start","message":{"model":"claude-mock","usage":{"input_tokens":12,"output_tokens":1}}}"#,
+                r#"{"type":"content_block_start","index":0,"content_block":{"type":"text","text":""}}"#,
+                r#"{"type":"content_block_delta","index":0,"delta":{"type":"text_delta","text":"sunny"}}"#,
+                r#"{"type":"content_block_delta","index":0,"delta":{"type":"text_delta","text":" day"}}"#,
+                r#"{"type":"message_delta","delta":{"stop_reason":"end_turn"},"usage":{"output_tokens":3}}"#,
+                r#"{"type":"message_stop"}"#,
+            ]),
+        ])
+        .await;
+        let provider = AnthropicProvider::new(server.url.as_str(), "ak-test");
+
+        let req = ChatRequest::new("claude-mock")
+            .message(ChatMessage::system("you are a bot"))
+            .message(ChatMessage::user("weather?"));
+        let resp = provider.chat(req.clone()).await.unwrap();
+        assert_eq!("let me check", resp.content.as_str());
+        assert_eq!(r#"{"city":"bj"}"#, resp.tool_calls[0].arguments.as_str());
+        assert_eq!(20, resp.usage.unwrap().total_tokens);
+
+        let recv = server.requests();
+        assert_eq!("/messages", recv[0].path.as_str());
+        assert_eq!("ak-test", recv[0].header("x-api-key"));
+        assert_eq!("you are a bot", recv[0].body["system"]);
+        assert_eq!(1, recv[0].body["messages"].as_array().unwrap().len());
+
+        let req = req
+            .message(
+                ChatMessage::assistant("").tool_calls(vec![ChatToolCall::new(
+                    "toolu_1",
+                    "weather",
+                    r#"{"city":"bj"}"#,
+                )]),
+            )
+            .message(ChatMessage::tool("toolu_1", "sunny"));
+        let resp = provider
+            .chat_stream(req, Box::new(|s| print!("{}", s)))
+            .await
+            .unwrap();
+        assert_eq!("sunny day", resp.content.as_str());
+        assert_eq!("end_turn", resp.finish_reason.as_str());
+        assert_eq!(15, resp.usage.unwrap().total_tokens);
+
+        let body = &server.requests()[1].body;
+        assert_eq!("tool_use", body["messages"][1]["content"][0]["type"]);
+        assert_eq!("bj", body["messages"][1]["content"][0]["input"]["city"]);
+        assert_eq!("tool_result", body["messages"][2]["content"][0]["type"]);
+
+        let req = ChatRequest::new("claude-mock")
+            .message(ChatMessage::user("look").parts(vec![
+                ChatContentPart::image_url("https://example.com/a.png"),
+                ChatContentPart::image_base64("image/png", "AA=="),
+            ]))
+            .message(ChatMessage::function("search", "a cat"));
+        let body = AnthropicProvider::request_body(&req, false);
+        let blocks = &body["messages"][0]["content"];
+        assert_eq!(4, blocks.as_array().unwrap().len());
+        assert_eq!("url", blocks[1]["source"]["type"]);
+        assert_eq!("image/png", blocks[2]["source"]["media_type"]);
+        assert_eq!("function[search] result:a cat", blocks[3]["text"]);
+    }
+}
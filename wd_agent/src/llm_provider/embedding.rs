@@ -0,0 +1,142 @@
+use crate::llm_provider::ChatUsage;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EmbeddingResponse {
+    pub model: String,
+    //与输入一一对应
+    pub vectors: Vec<Vec<f32>>,
+    pub usage: Option<ChatUsage>,
+}
+
+#[async_trait::async_trait]
+pub trait EmbeddingProvider: Send + Sync {
+    //请求未指定模型时使用
+    fn default_embedding_model(&self) -> &str;
+    async fn embed(&self, model: &str, input: Vec<String>) -> anyhow::Result<EmbeddingResponse>;
+}
+
+//稳定的字符串hash，用于特征哈希和生成文档id
+pub fn fnv1a(text: &str) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for b in text.as_bytes() {
+        hash ^= *b as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+//本地特征哈希向量，不依赖模型服务，用于测试和离线场景
+#[derive(Debug, Clone)]
+pub struct HashEmbeddingProvider {
+    pub dims: usize,
+}
+
+impl Default for HashEmbeddingProvider {
+    fn default() -> Self {
+        Self::new(256)
+    }
+}
+
+impl HashEmbeddingProvider {
+    pub fn new(dims: usize) -> Self {
+        Self { dims: dims.max(1) }
+    }
+    //英文按单词切分，中日韩字符单字切分
+    fn tokens(text: &str) -> Vec<String> {
+        let mut list = vec![];
+        let mut word = String::new();
+        for c in text.to_lowercase().chars() {
+            if c.is_alphanumeric() && c.is_ascii() {
+                word.push(c);
+                continue;
+            }
+            if !word.is_empty() {
+                list.push(std::mem::take(&mut word));
+            }
+            if c.is_alphanumeric() {
+                list.push(c.to_string());
+            }
+        }
+        if !word.is_empty() {
+            list.push(word);
+        }
+        list
+    }
+    pub fn embed_text(&self, text: &str) -> (Vec<f32>, usize) {
+        let tokens = Self::tokens(text);
+        let mut vector = vec![0f32; self.dims];
+        for i in tokens.iter() {
+            let hash = fnv1a(i.as_str());
+            let index = (hash % self.dims as u64) as usize;
+            if hash >> 63 == 0 {
+                vector[index] += 1.0;
+            } else {
+                vector[index] -= 1.0;
+            }
+        }
+        let norm = vector.iter().map(|x| x * x).sum::<f32>().sqrt();
+        if norm > 0.0 {
+            vector.iter_mut().for_each(|x| *x /= norm);
+        }
+        (vector, tokens.len())
+    }
+}
+
+#[async_trait::async_trait]
+impl EmbeddingProvider for HashEmbeddingProvider {
+    fn default_embedding_model(&self) -> &str {
+        "hash"
+    }
+
+    async fn embed(&self, model: &str, input: Vec<String>) -> anyhow::Result<EmbeddingResponse> {
+        let mut resp = EmbeddingResponse {
+            model: model.to_string(),
+            ..Default::default()
+        };
+        let mut tokens = 0;
+        for i in input.iter() {
+            let (vector, n) = self.embed_text(i.as_str());
+            resp.vectors.push(vector);
+            tokens += n as u64;
+        }
+        resp.usage = Some(ChatUsage::new(tokens, 0));
+        Ok(resp)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::llm_provider::{EmbeddingProvider, HashEmbeddingProvider};
+
+    fn cosine(a: &[f32], b: &[f32]) -> f32 {
+        a.iter().zip(b.iter()).map(|(x, y)| x * y).sum()
+    }
+
+    //cargo test llm_provider::embedding::test::test_hash_embedding -- --nocapture
+    #[tokio::test]
+    async fn test_hash_embedding() {
+        let provider = HashEmbeddingProvider::new(64);
+        let resp = provider
+            .embed(
+                "hash",
+                vec![
+                    "the cat sat on the mat".into(),
+                    "a cat on a mat".into(),
+                    "stock market prices".into(),
+                    "".into(),
+                ],
+            )
+            .await
+            .unwrap();
+        assert_eq!(4, resp.vectors.len());
+        assert_eq!(64, resp.vectors[0].len());
+        let near = cosine(&resp.vectors[0], &resp.vectors[1]);
+        let far = cosine(&resp.vectors[0], &resp.vectors[2]);
+        assert!(near > far);
+        assert!((cosine(&resp.vectors[0], &resp.vectors[0]) - 1.0).abs() < 1e-5);
+        assert!(resp.vectors[3].iter().all(|x| *x == 0.0));
+        assert_eq!(14, resp.usage.unwrap().prompt_tokens);
+    }
+}
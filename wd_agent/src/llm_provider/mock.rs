@@ -0,0 +1,169 @@
+use serde_json::Value;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+//测试用的http服务，按顺序或按路径返回预设的响应，并记录收到的请求
+#[derive(Debug, Clone)]
+pub struct MockResponse {
+    pub status: u16,
+    pub content_type: String,
+    pub body: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct MockRequest {
+    pub method: String,
+    pub path: String,
+    pub headers: HashMap<String, String>,
+    //按json解析的请求体，不是json时为null
+    pub body: Value,
+    pub text: String,
+}
+
+pub struct MockServer {
+    pub url: String,
+    requests: Arc<Mutex<Vec<MockRequest>>>,
+}
+
+impl MockRequest {
+    pub fn header(&self, key: &str) -> &str {
+        self.headers.get(key).map(|x| x.as_str()).unwrap_or("")
+    }
+}
+
+impl MockServer {
+    pub fn json(body: Value) -> MockResponse {
+        Self::bytes(200, "application/json", body.to_string())
+    }
+    pub fn bytes<B: Into<Vec<u8>>>(status: u16, content_type: &str, body: B) -> MockResponse {
+        MockResponse {
+            status,
+            content_type: content_type.into(),
+            body: body.into(),
+        }
+    }
+    pub fn sse(data: Vec<&str>) -> MockResponse {
+        let body = data
+            .into_iter()
+            .map(|x| format!("data: {}\n\n", x))
+            .collect::<String>();
+        Self::bytes(200, "text/event-stream", body)
+    }
+    pub fn ndjson(lines: Vec<Value>) -> MockResponse {
+        let body = lines
+            .into_iter()
+            .map(|x| format!("{}\n", x))
+            .collect::<String>();
+        Self::bytes(200, "application/x-ndjson", body)
+    }
+    pub async fn start(responses: Vec<MockResponse>) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(vec![]));
+        let recv = requests.clone();
+        tokio::spawn(async move {
+            for resp in responses {
+                let (mut stream, _) = match listener.accept().await {
+                    Ok(o) => o,
+                    Err(_) => return,
+                };
+                if let Some(req) = Self::read_request(&mut stream).await {
+                    recv.lock().unwrap().push(req);
+                }
+                Self::write_response(&mut stream, &resp).await;
+            }
+        });
+        Self { url, requests }
+    }
+    //按路径(不含query)返回响应，同一路径的响应依次返回，最后一个重复使用，未知路径返回404
+    pub async fn route(routes: Vec<(&str, Vec<MockResponse>)>) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(vec![]));
+        let recv = requests.clone();
+        let mut routes = routes
+            .into_iter()
+            .map(|(k, v)| (k.to_string(), VecDeque::from(v)))
+            .collect::<HashMap<_, _>>();
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = match listener.accept().await {
+                    Ok(o) => o,
+                    Err(_) => return,
+                };
+                let Some(req) = Self::read_request(&mut stream).await else {
+                    continue;
+                };
+                let path = req.path.split('?').next().unwrap_or("");
+                let resp = match routes.get_mut(path) {
+                    Some(list) if list.len() > 1 => list.pop_front(),
+                    Some(list) => list.front().cloned(),
+                    None => None,
+                };
+                let resp = resp.unwrap_or_else(|| Self::bytes(404, "text/plain", "not found"));
+                recv.lock().unwrap().push(req);
+                Self::write_response(&mut stream, &resp).await;
+            }
+        });
+        Self { url, requests }
+    }
+    pub fn requests(&self) -> Vec<MockRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+    async fn write_response(stream: &mut tokio::net::TcpStream, resp: &MockResponse) {
+        let head = format!(
+            "HTTP/1.1 {} OK\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            resp.status,
+            resp.content_type,
+            resp.body.len()
+        );
+        let _ = stream.write_all(head.as_bytes()).await;
+        let _ = stream.write_all(resp.body.as_slice()).await;
+        let _ = stream.shutdown().await;
+    }
+    async fn read_request(stream: &mut tokio::net::TcpStream) -> Option<MockRequest> {
+        let mut buf = vec![];
+        let mut tmp = [0u8; 4096];
+        let head_end = loop {
+            let n = stream.read(&mut tmp).await.ok()?;
+            if n == 0 {
+                return None;
+            }
+            buf.extend_from_slice(&tmp[..n]);
+            if let Some(pos) = buf.windows(4).position(|x| x == b"\r\n\r\n") {
+                break pos + 4;
+            }
+        };
+        let head = String::from_utf8_lossy(&buf[..head_end]).to_string();
+        let mut lines = head.split("\r\n");
+        let mut first = lines.next()?.split(' ');
+        let mut req = MockRequest {
+            method: first.next()?.to_string(),
+            path: first.next()?.to_string(),
+            ..Default::default()
+        };
+        for line in lines {
+            if let Some((k, v)) = line.split_once(':') {
+                req.headers
+                    .insert(k.trim().to_lowercase(), v.trim().to_string());
+            }
+        }
+        let length = req
+            .headers
+            .get("content-length")
+            .and_then(|x| x.parse::<usize>().ok())
+            .unwrap_or(0);
+        while buf.len() < head_end + length {
+            let n = stream.read(&mut tmp).await.ok()?;
+            if n == 0 {
+                break;
+            }
+            buf.extend_from_slice(&tmp[..n]);
+        }
+        req.body = serde_json::from_slice(&buf[head_end..]).unwrap_or_default();
+        req.text = String::from_utf8_lossy(&buf[head_end..]).to_string();
+        Some(req)
+    }
+}
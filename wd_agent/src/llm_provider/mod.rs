@@ -0,0 +1,24 @@
+mod anthropic;
+mod embedding;
+#[cfg(test)]
+pub(crate) mod mock;
+mod ollama;
+mod openai;
+mod pricing;
+mod profile;
+mod provider;
+mod zhipu;
+
+pub use anthropic::*;
+pub use embedding::*;
+pub use ollama::*;
+pub use openai::*;
+pub use pricing::*;
+pub use profile::*;
+pub use provider::{
+    ChatContentPart, ChatDelta, ChatMessage, ChatRequest, ChatResponse, ChatRole, ChatTool,
+    ChatToolCall, ChatUsage, LLMProvider,
+};
+pub use zhipu::*;
+
+pub(crate) use provider::{read_json, LineReader};
@@ -0,0 +1,258 @@
+use crate::llm_provider::provider::{json_str, json_u64, post_json, read_json, LineReader};
+use crate::llm_provider::{
+    ChatContentPart, ChatDelta, ChatMessage, ChatRequest, ChatResponse, ChatRole, ChatToolCall,
+    ChatUsage, EmbeddingProvider, EmbeddingResponse, LLMProvider,
+};
+use serde_json::{json, Value};
+use wd_tools::PFErr;
+
+//本地ollama服务 /api/chat
+#[derive(Debug, Clone)]
+pub struct OllamaProvider {
+    client: reqwest::Client,
+    pub base_url: String,
+    pub default_model: String,
+    pub embedding_model: String,
+}
+
+impl Default for OllamaProvider {
+    fn default() -> Self {
+        let base_url = std::env::var("OLLAMA_HOST").unwrap_or("http://127.0.0.1:11434".into());
+        Self::new(base_url)
+    }
+}
+
+impl OllamaProvider {
+    pub fn new<U: Into<String>>(base_url: U) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+            default_model: "llama3".into(),
+            embedding_model: "nomic-embed-text".into(),
+        }
+    }
+    pub fn default_model<S: Into<String>>(mut self, model: S) -> Self {
+        self.default_model = model.into();
+        self
+    }
+    pub fn embedding_model<S: Into<String>>(mut self, model: S) -> Self {
+        self.embedding_model = model.into();
+        self
+    }
+    pub fn client(mut self, client: reqwest::Client) -> Self {
+        self.client = client;
+        self
+    }
+
+    fn message_to_json(msg: &ChatMessage) -> Value {
+        //ollama没有function角色，函数结果按tool消息发送
+        let role = match msg.role {
+            ChatRole::Function => ChatRole::Tool,
+            role => role,
+        };
+        let mut obj = json!({
+            "role":role,
+            "content":msg.text(),
+        });
+        //只支持base64图片
+        let images = msg
+            .parts
+            .iter()
+            .filter_map(|x| match x {
+                ChatContentPart::ImageBase64 { data, .. } => Some(data.clone()),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        if !images.is_empty() {
+            obj["images"] = json!(images);
+        }
+        if !msg.tool_calls.is_empty() {
+            obj["tool_calls"] = msg
+                .tool_calls
+                .iter()
+                .map(|x| json!({"function":{"name":x.name,"arguments":x.arguments_value()}}))
+                .collect();
+        }
+        obj
+    }
+    pub fn request_body(req: &ChatRequest, stream: bool) -> Value {
+        let mut body = json!({
+            "model":req.model,
+            "messages":req.messages.iter().map(Self::message_to_json).collect::<Vec<_>>(),
+            "stream":stream,
+            "options":{
+                "temperature":req.temperature,
+                "num_predict":req.max_tokens,
+            },
+        });
+        if !req.tools.is_empty() {
+            body["tools"] = req
+                .tools
+                .iter()
+                .map(|x| {
+                    json!({
+                        "type":"function",
+                        "function":{
+                            "name":x.name,
+                            "description":x.description,
+                            "parameters":x.parameters,
+                        }
+                    })
+                })
+                .collect();
+        }
+        if let Some(ref schema) = req.response_schema {
+            body["format"] = schema.clone();
+        }
+        body
+    }
+    //ollama的工具调用没有id，按顺序生成
+    fn append_message(value: &Value, resp: &mut ChatResponse) -> String {
+        let msg = value.get("message").cloned().unwrap_or_default();
+        let content = json_str(&msg, "content");
+        resp.content.push_str(content.as_str());
+        if let Some(Value::Array(calls)) = msg.get("tool_calls") {
+            for i in calls {
+                let function = i.get("function").cloned().unwrap_or_default();
+                let id = format!("call_{}", resp.tool_calls.len());
+                let args = match function.get("arguments") {
+                    Some(Value::String(s)) => s.clone(),
+                    Some(v) => v.to_string(),
+                    None => "{}".into(),
+                };
+                resp.tool_calls
+                    .push(ChatToolCall::new(id, json_str(&function, "name"), args));
+            }
+        }
+        if value.get("done").and_then(|x| x.as_bool()).unwrap_or(false) {
+            resp.model = json_str(value, "model");
+            resp.finish_reason = json_str(value, "done_reason");
+            resp.usage = Some(ChatUsage::new(
+                json_u64(value, "prompt_eval_count"),
+                json_u64(value, "eval_count"),
+            ));
+        }
+        content
+    }
+    async fn post(&self, body: &Value) -> anyhow::Result<reqwest::Response> {
+        self.post_path("/api/chat", body).await
+    }
+    async fn post_path(&self, path: &str, body: &Value) -> anyhow::Result<reqwest::Response> {
+        let url = format!("{}{}", self.base_url, path);
+        post_json(&self.client, url.as_str(), &[], body).await
+    }
+}
+
+#[async_trait::async_trait]
+impl LLMProvider for OllamaProvider {
+    fn name(&self) -> &str {
+        "ollama"
+    }
+
+    fn default_model(&self) -> &str {
+        self.default_model.as_str()
+    }
+
+    async fn chat(&self, req: ChatRequest) -> anyhow::Result<ChatResponse> {
+        let body = Self::request_body(&req, false);
+        let value = read_json(self.post(&body).await?).await?;
+        if let Some(e) = value.get("error") {
+            return anyhow::anyhow!("ollama error:{}", e).err();
+        }
+        let mut resp = ChatResponse::default();
+        Self::append_message(&value, &mut resp);
+        Ok(resp)
+    }
+
+    async fn chat_stream(
+        &self,
+        req: ChatRequest,
+        mut delta: ChatDelta,
+    ) -> anyhow::Result<ChatResponse> {
+        let body = Self::request_body(&req, true);
+        let mut reader = LineReader::new(self.post(&body).await?);
+        let mut resp = ChatResponse::default();
+        while let Some(line) = reader.next_line().await? {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let value = serde_json::from_str::<Value>(line.as_str())?;
+            if let Some(e) = value.get("error") {
+                return anyhow::anyhow!("ollama error:{}", e).err();
+            }
+            let content = Self::append_message(&value, &mut resp);
+            if !content.is_empty() {
+                delta(content.as_str());
+            }
+        }
+        Ok(resp)
+    }
+}
+
+#[async_trait::async_trait]
+impl EmbeddingProvider for OllamaProvider {
+    fn default_embedding_model(&self) -> &str {
+        self.embedding_model.as_str()
+    }
+
+    async fn embed(&self, model: &str, input: Vec<String>) -> anyhow::Result<EmbeddingResponse> {
+        let body = json!({"model":model,"input":input});
+        let value = read_json(self.post_path("/api/embed", &body).await?).await?;
+        if let Some(e) = value.get("error") {
+            return anyhow::anyhow!("ollama error:{}", e).err();
+        }
+        let vectors = serde_json::from_value(value.get("embeddings").cloned().unwrap_or_default())?;
+        Ok(EmbeddingResponse {
+            model: json_str(&value, "model"),
+            vectors,
+            usage: Some(ChatUsage::new(json_u64(&value, "prompt_eval_count"), 0)),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::llm_provider::mock::MockServer;
+    use crate::llm_provider::{ChatMessage, ChatRequest, LLMProvider, OllamaProvider};
+
+    //cargo test llm_provider::ollama::test::test_ollama_provider -- --nocapture
+    #[tokio::test]
+    async fn test_ollama_provider() {
+        let server = MockServer::start(vec![
+            MockServer::json(serde_json::json!({
+                "model":"llama3",
+                "message":{"role":"assistant","content":"","tool_calls":[
+                    {"function":{"name":"weather","arguments":{"city":"bj"}}}
+                ]},
+                "done":true,
+                "done_reason":"stop",
+                "prompt_eval_count":9,
+                "eval_count":4
+            })),
+            MockServer::ndjson(vec![
+                serde_json::json!({"model":"llama3","message":{"role":"assistant","content":"hi"},"done":false}),
+                serde_json::json!({"model":"llama3","message":{"role":"assistant","content":" there"},"done":false}),
+                serde_json::json!({"model":"llama3","message":{"role":"assistant","content":""},"done":true,"done_reason":"stop","prompt_eval_count":9,"eval_count":2}),
+            ]),
+        ])
+        .await;
+        let provider = OllamaProvider::new(server.url.as_str());
+
+        let req = ChatRequest::new("llama3").message(ChatMessage::user("hello"));
+        let resp = provider.chat(req.clone()).await.unwrap();
+        assert_eq!("call_0", resp.tool_calls[0].id.as_str());
+        assert_eq!(r#"{"city":"bj"}"#, resp.tool_calls[0].arguments.as_str());
+        assert_eq!(13, resp.usage.unwrap().total_tokens);
+
+        let recv = server.requests();
+        assert_eq!("/api/chat", recv[0].path.as_str());
+        assert_eq!(1024, recv[0].body["options"]["num_predict"]);
+
+        let resp = provider
+            .chat_stream(req, Box::new(|s| print!("{}", s)))
+            .await
+            .unwrap();
+        assert_eq!("hi there", resp.content.as_str());
+        assert_eq!("stop", resp.finish_reason.as_str());
+    }
+}
@@ -0,0 +1,537 @@
+use crate::llm_provider::provider::{json_str, json_u64, post_json, read_json, LineReader};
+use crate::llm_provider::{
+    ChatContentPart, ChatDelta, ChatMessage, ChatRequest, ChatResponse, ChatRole, ChatToolCall,
+    ChatUsage, EmbeddingProvider, EmbeddingResponse, LLMClientProfile, LLMProvider,
+};
+use serde_json::{json, Value};
+use wd_tools::PFErr;
+
+//兼容openai chat completions协议的服务，如openai,deepseek,vllm等
+#[derive(Debug, Clone)]
+pub struct OpenAICompatibleProvider {
+    name: String,
+    client: reqwest::Client,
+    pub base_url: String,
+    pub api_key: String,
+    pub org: String,
+    pub default_model: String,
+    pub embedding_model: String,
+    //是否支持json_schema类型的response_format，不支持时退化为json_object
+    pub json_schema_format: bool,
+}
+
+impl Default for OpenAICompatibleProvider {
+    fn default() -> Self {
+        let base_url = std::env::var("OPENAI_BASE_URL")
+            .or_else(|_| std::env::var("OPENAI_API_BASE"))
+            .unwrap_or("https://api.openai.com/v1".into());
+        let api_key = std::env::var("OPENAI_API_KEY").unwrap_or_default();
+        Self::new(base_url, api_key)
+    }
+}
+
+impl OpenAICompatibleProvider {
+    pub fn new<U: Into<String>, K: Into<String>>(base_url: U, api_key: K) -> Self {
+        Self {
+            name: "openai".into(),
+            client: reqwest::Client::new(),
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+            api_key: api_key.into(),
+            org: String::new(),
+            default_model: "gpt-3.5-turbo".into(),
+            embedding_model: "text-embedding-3-small".into(),
+            json_schema_format: true,
+        }
+    }
+    pub fn from_profile(profile: &LLMClientProfile) -> anyhow::Result<Self> {
+        let mut this = Self::new(profile.base_url.as_str(), profile.api_key.as_str())
+            .client(profile.http_client()?);
+        this.org = profile.org.clone();
+        Ok(this)
+    }
+    pub fn provider_name<S: Into<String>>(mut self, name: S) -> Self {
+        self.name = name.into();
+        self
+    }
+    pub fn default_model<S: Into<String>>(mut self, model: S) -> Self {
+        self.default_model = model.into();
+        self
+    }
+    pub fn embedding_model<S: Into<String>>(mut self, model: S) -> Self {
+        self.embedding_model = model.into();
+        self
+    }
+    pub fn client(mut self, client: reqwest::Client) -> Self {
+        self.client = client;
+        self
+    }
+    pub fn json_schema_format(mut self, enable: bool) -> Self {
+        self.json_schema_format = enable;
+        self
+    }
+
+    //base64图片按data url发送
+    pub fn part_to_json(part: &ChatContentPart) -> Value {
+        match part {
+            ChatContentPart::Text { text } => json!({"type":"text","text":text}),
+            ChatContentPart::ImageUrl { url, detail } => {
+                let mut image = json!({ "url": url });
+                if !detail.is_empty() {
+                    image["detail"] = Value::String(detail.clone());
+                }
+                json!({"type":"image_url","image_url":image})
+            }
+            ChatContentPart::ImageBase64 { media_type, data } => json!({
+                "type":"image_url",
+                "image_url":{"url":format!("data:{};base64,{}", media_type, data)},
+            }),
+        }
+    }
+    pub fn part_from_json(value: &Value) -> Option<ChatContentPart> {
+        match value.get("type").and_then(|x| x.as_str()) {
+            Some("text") => Some(ChatContentPart::text(json_str(value, "text"))),
+            Some("image_url") => {
+                let image = value.get("image_url")?;
+                let url = json_str(image, "url");
+                if let Some(s) = url.strip_prefix("data:") {
+                    if let Some((media_type, data)) = s.split_once(";base64,") {
+                        return Some(ChatContentPart::image_base64(media_type, data));
+                    }
+                }
+                Some(ChatContentPart::ImageUrl {
+                    url,
+                    detail: json_str(image, "detail"),
+                })
+            }
+            _ => None,
+        }
+    }
+    //没有parts时content为字符串，否则为数组
+    pub fn content_to_json(msg: &ChatMessage) -> Value {
+        if msg.parts.is_empty() {
+            return Value::String(msg.content.clone());
+        }
+        let mut list = vec![];
+        if !msg.content.is_empty() {
+            list.push(json!({"type":"text","text":msg.content}));
+        }
+        list.extend(msg.parts.iter().map(Self::part_to_json));
+        Value::Array(list)
+    }
+    pub fn message_to_json(msg: &ChatMessage) -> Value {
+        let mut obj = json!({
+            "role":msg.role,
+            "content":Self::content_to_json(msg),
+        });
+        if !msg.name.is_empty() {
+            obj["name"] = Value::String(msg.name.clone());
+        }
+        if msg.role == ChatRole::Assistant && !msg.tool_calls.is_empty() {
+            if msg.content.is_empty() && msg.parts.is_empty() {
+                obj["content"] = Value::Null;
+            }
+            obj["tool_calls"] = msg
+                .tool_calls
+                .iter()
+                .map(|x| {
+                    json!({
+                        "id":x.id,
+                        "type":"function",
+                        "function":{"name":x.name,"arguments":x.arguments}
+                    })
+                })
+                .collect();
+        }
+        if msg.role == ChatRole::Tool {
+            obj["tool_call_id"] = Value::String(msg.tool_call_id.clone());
+        }
+        obj
+    }
+    //message_to_json的逆过程，开头的文本part还原为content
+    pub fn message_from_json(value: &Value) -> anyhow::Result<ChatMessage> {
+        let role = json_str(value, "role");
+        let role = match ChatRole::from_name(role.as_str()) {
+            Some(s) => s,
+            None => return anyhow::anyhow!("unknown message role[{}]", role).err(),
+        };
+        let mut msg = ChatMessage {
+            role,
+            name: json_str(value, "name"),
+            tool_call_id: json_str(value, "tool_call_id"),
+            ..Default::default()
+        };
+        match value.get("content") {
+            Some(Value::String(s)) => msg.content = s.clone(),
+            Some(Value::Array(list)) => {
+                msg.parts = list.iter().filter_map(Self::part_from_json).collect();
+                if let Some(ChatContentPart::Text { text }) = msg.parts.first() {
+                    msg.content = text.clone();
+                    msg.parts.remove(0);
+                }
+            }
+            _ => {}
+        }
+        if let Some(Value::Array(calls)) = value.get("tool_calls") {
+            for i in calls {
+                let function = i.get("function").cloned().unwrap_or_default();
+                //部分服务返回的参数是json对象
+                let args = match function.get("arguments") {
+                    Some(Value::String(s)) => s.clone(),
+                    Some(Value::Null) | None => String::new(),
+                    Some(v) => v.to_string(),
+                };
+                msg.tool_calls.push(ChatToolCall::new(
+                    json_str(i, "id"),
+                    json_str(&function, "name"),
+                    args,
+                ));
+            }
+        }
+        Ok(msg)
+    }
+    pub fn request_body(&self, req: &ChatRequest, stream: bool) -> Value {
+        let mut body = json!({
+            "model":req.model,
+            "messages":req.messages.iter().map(Self::message_to_json).collect::<Vec<_>>(),
+            "max_tokens":req.max_tokens,
+            "temperature":req.temperature,
+            "stream":stream,
+        });
+        //流式响应默认不带用量，需要显式要求最后一个分片带上
+        if stream {
+            body["stream_options"] = json!({"include_usage":true});
+        }
+        if !req.tools.is_empty() {
+            body["tools"] = req
+                .tools
+                .iter()
+                .map(|x| {
+                    json!({
+                        "type":"function",
+                        "function":{
+                            "name":x.name,
+                            "description":x.description,
+                            "parameters":x.parameters,
+                        }
+                    })
+                })
+                .collect();
+        }
+        if let Some(ref schema) = req.response_schema {
+            body["response_format"] = if self.json_schema_format {
+                json!({
+                    "type":"json_schema",
+                    "json_schema":{"name":"response","schema":schema},
+                })
+            } else {
+                json!({"type":"json_object"})
+            };
+        }
+        body
+    }
+    pub fn parse_usage(value: &Value) -> Option<ChatUsage> {
+        let usage = value.get("usage")?;
+        if usage.is_null() {
+            return None;
+        }
+        let mut res = ChatUsage::new(
+            json_u64(usage, "prompt_tokens"),
+            json_u64(usage, "completion_tokens"),
+        );
+        if let Some(total) = usage.get("total_tokens").and_then(|x| x.as_u64()) {
+            res.total_tokens = total;
+        }
+        Some(res)
+    }
+    pub fn parse_response(value: Value) -> anyhow::Result<ChatResponse> {
+        let choice = match value.get("choices").and_then(|x| x.get(0)) {
+            Some(s) => s,
+            None => return anyhow::anyhow!("response has no choices:{}", value).err(),
+        };
+        let msg = choice.get("message").cloned().unwrap_or_default();
+        let mut resp = ChatResponse {
+            model: json_str(&value, "model"),
+            content: json_str(&msg, "content"),
+            finish_reason: json_str(choice, "finish_reason"),
+            usage: Self::parse_usage(&value),
+            ..Default::default()
+        };
+        if let Some(Value::Array(calls)) = msg.get("tool_calls") {
+            for i in calls {
+                let function = i.get("function").cloned().unwrap_or_default();
+                resp.tool_calls.push(ChatToolCall::new(
+                    json_str(i, "id"),
+                    json_str(&function, "name"),
+                    json_str(&function, "arguments"),
+                ));
+            }
+        }
+        Ok(resp)
+    }
+    //流式的工具调用按index分片到达
+    fn append_tool_delta(resp: &mut ChatResponse, calls: &[Value]) {
+        for i in calls {
+            let index = i.get("index").and_then(|x| x.as_u64()).unwrap_or(0) as usize;
+            while resp.tool_calls.len() <= index {
+                resp.tool_calls.push(ChatToolCall::default());
+            }
+            let call = &mut resp.tool_calls[index];
+            if let Some(id) = i.get("id").and_then(|x| x.as_str()) {
+                call.id = id.to_string();
+            }
+            if let Some(function) = i.get("function") {
+                if let Some(name) = function.get("name").and_then(|x| x.as_str()) {
+                    call.name.push_str(name);
+                }
+                if let Some(args) = function.get("arguments").and_then(|x| x.as_str()) {
+                    call.arguments.push_str(args);
+                }
+            }
+        }
+    }
+    async fn post(&self, body: &Value) -> anyhow::Result<reqwest::Response> {
+        self.post_path("/chat/completions", body).await
+    }
+    async fn post_path(&self, path: &str, body: &Value) -> anyhow::Result<reqwest::Response> {
+        let url = format!("{}{}", self.base_url, path);
+        let mut headers = vec![];
+        if !self.api_key.is_empty() {
+            headers.push(("Authorization", format!("Bearer {}", self.api_key)));
+        }
+        if !self.org.is_empty() {
+            headers.push(("OpenAI-Organization", self.org.clone()));
+        }
+        post_json(&self.client, url.as_str(), headers.as_slice(), body).await
+    }
+}
+
+#[async_trait::async_trait]
+impl LLMProvider for OpenAICompatibleProvider {
+    fn name(&self) -> &str {
+        self.name.as_str()
+    }
+
+    fn default_model(&self) -> &str {
+        self.default_model.as_str()
+    }
+
+    async fn chat(&self, req: ChatRequest) -> anyhow::Result<ChatResponse> {
+        let body = self.request_body(&req, false);
+        let resp = self.post(&body).await?;
+        Self::parse_response(read_json(resp).await?)
+    }
+
+    async fn chat_stream(
+        &self,
+        req: ChatRequest,
+        mut delta: ChatDelta,
+    ) -> anyhow::Result<ChatResponse> {
+        let body = self.request_body(&req, true);
+        let mut reader = LineReader::new(self.post(&body).await?);
+        let mut resp = ChatResponse::default();
+        while let Some(data) = reader.next_sse_data().await? {
+            if data == "[DONE]" {
+                break;
+            }
+            let chunk = serde_json::from_str::<Value>(data.as_str())?;
+            if resp.model.is_empty() {
+                resp.model = json_str(&chunk, "model");
+            }
+            if let Some(usage) = Self::parse_usage(&chunk) {
+                resp.usage = Some(usage);
+            }
+            let choice = match chunk.get("choices").and_then(|x| x.get(0)) {
+                Some(s) => s,
+                None => continue,
+            };
+            if let Some(reason) = choice.get("finish_reason").and_then(|x| x.as_str()) {
+                resp.finish_reason = reason.to_string();
+            }
+            let Some(d) = choice.get("delta") else {
+                continue;
+            };
+            if let Some(s) = d.get("content").and_then(|x| x.as_str()) {
+                if !s.is_empty() {
+                    delta(s);
+                    resp.content.push_str(s);
+                }
+            }
+            if let Some(Value::Array(calls)) = d.get("tool_calls") {
+                Self::append_tool_delta(&mut resp, calls.as_slice());
+            }
+        }
+        Ok(resp)
+    }
+}
+
+#[async_trait::async_trait]
+impl EmbeddingProvider for OpenAICompatibleProvider {
+    fn default_embedding_model(&self) -> &str {
+        self.embedding_model.as_str()
+    }
+
+    async fn embed(&self, model: &str, input: Vec<String>) -> anyhow::Result<EmbeddingResponse> {
+        let body = json!({"model":model,"input":input});
+        let value = read_json(self.post_path("/embeddings", &body).await?).await?;
+        let mut data = match value.get("data") {
+            Some(Value::Array(list)) => list.clone(),
+            _ => return anyhow::anyhow!("embedding response has no data:{}", value).err(),
+        };
+        data.sort_by_key(|x| json_u64(x, "index"));
+        let vectors = data
+            .iter()
+            .map(|x| serde_json::from_value(x.get("embedding").cloned().unwrap_or_default()))
+            .collect::<Result<Vec<Vec<f32>>, _>>()?;
+        Ok(EmbeddingResponse {
+            model: json_str(&value, "model"),
+            vectors,
+            usage: Self::parse_usage(&value),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::llm_provider::mock::MockServer;
+    use crate::llm_provider::{
+        ChatContentPart, ChatMessage, ChatRequest, ChatTool, ChatToolCall, EmbeddingProvider,
+        LLMProvider, OpenAICompatibleProvider,
+    };
+    use std::sync::{Arc, Mutex};
+
+    //cargo test llm_provider::openai::test::test_openai_provider -- --nocapture
+    #[tokio::test]
+    async fn test_openai_provider() {
+        let server = MockServer::start(vec![
+            MockServer::json(serde_json::json!({
+                "model":"gpt-mock",
+                "choices":[{"index":0,"finish_reason":"tool_calls","message":{
+                    "role":"assistant","content":null,
+                    "tool_calls":[{"id":"call_1","type":"function","function":{"name":"weather","arguments":"{\"city\":\"bj\"}"}}]
+                }}],
+                "usage":{"prompt_tokens":10,"completion_tokens":5,"total_tokens":15}
+            })),
+            MockServer::sse(vec![
+                r#"{"model":"gpt-mock","choices":[{"index":0,"delta":{"role":"assistant","content":"hel"}}]}"#,
+                r#"{"model":"gpt-mock","choices":[{"index":0,"delta":{"content":"lo"},"finish_reason":"stop"}]}"#,
+                "[DONE]",
+            ]),
+        ])
+        .await;
+        let provider = OpenAICompatibleProvider::new(server.url.as_str(), "sk-test");
+
+        let req = ChatRequest::new("gpt-mock")
+            .message(ChatMessage::system("you are a bot"))
+            .message(ChatMessage::user("weather?"))
+            .tool(ChatTool {
+                name: "weather".into(),
+                description: "query weather".into(),
+                parameters: serde_json::json!({"type":"object"}),
+            });
+        let resp = provider.chat(req.clone()).await.unwrap();
+        assert_eq!("tool_calls", resp.finish_reason.as_str());
+        assert_eq!("weather", resp.tool_calls[0].name.as_str());
+        assert_eq!(15, resp.usage.unwrap().total_tokens);
+
+        let recv = server.requests();
+        assert_eq!("POST", recv[0].method.as_str());
+        assert_eq!("/chat/completions", recv[0].path.as_str());
+        assert_eq!("Bearer sk-test", recv[0].header("authorization"));
+        assert_eq!("weather", recv[0].body["tools"][0]["function"]["name"]);
+
+        let deltas = Arc::new(Mutex::new(vec![]));
+        let list = deltas.clone();
+        let resp = provider
+            .chat_stream(
+                req,
+                Box::new(move |s| list.lock().unwrap().push(s.to_string())),
+            )
+            .await
+            .unwrap();
+        assert_eq!("hello", resp.content.as_str());
+        assert_eq!("stop", resp.finish_reason.as_str());
+        assert_eq!(2, deltas.lock().unwrap().len());
+        assert!(server.requests()[1].body["stream"].as_bool().unwrap());
+        assert_eq!(
+            serde_json::json!({"include_usage":true}),
+            server.requests()[1].body["stream_options"]
+        );
+
+        let req =
+            ChatRequest::new("gpt-mock").response_schema(serde_json::json!({"type":"object"}));
+        let body = provider.request_body(&req, false);
+        assert_eq!("json_schema", body["response_format"]["type"]);
+        let body = provider.json_schema_format(false).request_body(&req, false);
+        assert_eq!("json_object", body["response_format"]["type"]);
+    }
+
+    //cargo test llm_provider::openai::test::test_message_json -- --nocapture
+    #[test]
+    fn test_message_json() {
+        let list = vec![
+            ChatMessage::user("what is in the picture")
+                .name("tom")
+                .parts(vec![
+                    ChatContentPart::image_url("https://example.com/a.png"),
+                    ChatContentPart::image_base64("image/png", "aGVsbG8="),
+                ]),
+            ChatMessage::assistant("").tool_calls(vec![
+                ChatToolCall::new("c1", "search", r#"{"q":"cat"}"#),
+                ChatToolCall::new("c2", "weather", "{}"),
+            ]),
+            ChatMessage::tool("c1", "a cat"),
+            ChatMessage::function("search", "a cat"),
+        ];
+        for msg in list {
+            let value = OpenAICompatibleProvider::message_to_json(&msg);
+            println!("{}", value);
+            assert_eq!(
+                msg,
+                OpenAICompatibleProvider::message_from_json(&value).unwrap()
+            );
+        }
+
+        let msg =
+            ChatMessage::user("hi").parts(vec![ChatContentPart::image_base64("image/png", "AA==")]);
+        let value = OpenAICompatibleProvider::message_to_json(&msg);
+        assert_eq!("hi", value["content"][0]["text"]);
+        assert_eq!(
+            "data:image/png;base64,AA==",
+            value["content"][1]["image_url"]["url"]
+        );
+        let value = OpenAICompatibleProvider::message_to_json(&ChatMessage::function("f", "1"));
+        assert_eq!(
+            serde_json::json!({"role":"function","content":"1","name":"f"}),
+            value
+        );
+        assert!(
+            OpenAICompatibleProvider::message_from_json(&serde_json::json!({"role":"bot"}))
+                .is_err()
+        );
+    }
+
+    //cargo test llm_provider::openai::test::test_openai_embedding -- --nocapture
+    #[tokio::test]
+    async fn test_openai_embedding() {
+        let server = MockServer::start(vec![MockServer::json(serde_json::json!({
+            "model":"text-embedding-mock",
+            "data":[
+                {"index":1,"embedding":[0.0,1.0]},
+                {"index":0,"embedding":[1.0,0.0]}
+            ],
+            "usage":{"prompt_tokens":6,"total_tokens":6}
+        }))])
+        .await;
+        let provider = OpenAICompatibleProvider::new(server.url.as_str(), "sk-test");
+        assert_eq!("text-embedding-3-small", provider.default_embedding_model());
+        let resp = provider
+            .embed("text-embedding-mock", vec!["a".into(), "b".into()])
+            .await
+            .unwrap();
+        assert_eq!(vec![vec![1.0, 0.0], vec![0.0, 1.0]], resp.vectors);
+        assert_eq!(6, resp.usage.unwrap().total_tokens);
+        let recv = server.requests();
+        assert_eq!("/embeddings", recv[0].path.as_str());
+        assert_eq!(serde_json::json!(["a", "b"]), recv[0].body["input"]);
+    }
+}
@@ -0,0 +1,77 @@
+use crate::llm_provider::ChatUsage;
+use agent_rt::Usage;
+use std::collections::HashMap;
+
+//模型单价，按每百万token计
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct ModelPrice {
+    pub input: f64,
+    pub output: f64,
+}
+
+impl ModelPrice {
+    pub fn new(input: f64, output: f64) -> Self {
+        Self { input, output }
+    }
+    pub fn cost(&self, usage: &ChatUsage) -> f64 {
+        (usage.prompt_tokens as f64 * self.input + usage.completion_tokens as f64 * self.output)
+            / 1_000_000.0
+    }
+}
+
+//按模型名查找单价，用于计算上报到ctx预算的费用
+#[derive(Debug, Default, Clone)]
+pub struct ModelPricing {
+    prices: HashMap<String, ModelPrice>,
+}
+
+impl ModelPricing {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn price<S: Into<String>>(mut self, model: S, price: ModelPrice) -> Self {
+        self.prices.insert(model.into(), price);
+        self
+    }
+    //精确匹配优先，否则取最长的前缀，gpt-4o可以匹配gpt-4o-2024-08-06
+    pub fn get(&self, model: &str) -> Option<&ModelPrice> {
+        if let Some(s) = self.prices.get(model) {
+            return Some(s);
+        }
+        self.prices
+            .iter()
+            .filter(|(k, _)| model.starts_with(k.as_str()))
+            .max_by_key(|(k, _)| k.len())
+            .map(|(_, v)| v)
+    }
+    //没有配置单价的模型只统计token
+    pub fn usage(&self, model: &str, usage: &ChatUsage) -> Usage {
+        let cost = self.get(model).map(|x| x.cost(usage)).unwrap_or(0.0);
+        Usage::tokens(usage.total_tokens).set_cost(cost)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::llm_provider::{ChatUsage, ModelPrice, ModelPricing};
+
+    //cargo test llm_provider::pricing::test::test_model_pricing -- --nocapture
+    #[test]
+    fn test_model_pricing() {
+        let pricing = ModelPricing::new()
+            .price("gpt-4o", ModelPrice::new(2.5, 10.0))
+            .price("gpt-4o-mini", ModelPrice::new(0.15, 0.6));
+        let usage = ChatUsage::new(1_000_000, 500_000);
+
+        let u = pricing.usage("gpt-4o", &usage);
+        assert_eq!(1_500_000, u.tokens);
+        assert!((u.cost - 7.5).abs() < 1e-9);
+        let u = pricing.usage("gpt-4o-2024-08-06", &usage);
+        assert!((u.cost - 7.5).abs() < 1e-9);
+        let u = pricing.usage("gpt-4o-mini-2024-07-18", &usage);
+        assert!((u.cost - 0.45).abs() < 1e-9);
+        let u = pricing.usage("llama3", &usage);
+        assert_eq!(0.0, u.cost);
+        assert_eq!(1_500_000, u.tokens);
+    }
+}
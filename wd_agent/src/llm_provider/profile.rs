@@ -0,0 +1,44 @@
+use std::time::Duration;
+
+//一组llm客户端配置，节点按名称选择
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct LLMClientProfile {
+    pub base_url: String,
+    pub api_key: String,
+    pub org: String,
+    //http/https/socks5代理地址
+    pub proxy: String,
+    pub timeout: Option<Duration>,
+}
+
+impl LLMClientProfile {
+    pub fn new<U: Into<String>, K: Into<String>>(base_url: U, api_key: K) -> Self {
+        Self {
+            base_url: base_url.into(),
+            api_key: api_key.into(),
+            ..Default::default()
+        }
+    }
+    pub fn org<S: Into<String>>(mut self, org: S) -> Self {
+        self.org = org.into();
+        self
+    }
+    pub fn proxy<S: Into<String>>(mut self, proxy: S) -> Self {
+        self.proxy = proxy.into();
+        self
+    }
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+    pub fn http_client(&self) -> anyhow::Result<reqwest::Client> {
+        let mut builder = reqwest::Client::builder();
+        if !self.proxy.is_empty() {
+            builder = builder.proxy(reqwest::Proxy::all(self.proxy.as_str())?);
+        }
+        if let Some(timeout) = self.timeout {
+            builder = builder.timeout(timeout);
+        }
+        Ok(builder.build()?)
+    }
+}
@@ -0,0 +1,358 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use wd_tools::PFErr;
+
+//与具体厂商无关的对话消息
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChatRole {
+    System,
+    #[default]
+    User,
+    Assistant,
+    Tool,
+    //旧版function calling的函数结果
+    Function,
+}
+
+//消息中的多模态内容
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ChatContentPart {
+    Text {
+        text: String,
+    },
+    ImageUrl {
+        url: String,
+        //auto/low/high，只有openai使用
+        #[serde(default, skip_serializing_if = "String::is_empty")]
+        detail: String,
+    },
+    ImageBase64 {
+        media_type: String,
+        data: String,
+    },
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ChatToolCall {
+    pub id: String,
+    pub name: String,
+    //json字符串
+    pub arguments: String,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ChatMessage {
+    pub role: ChatRole,
+    pub content: String,
+    //跟在content文本之后的图片等内容
+    pub parts: Vec<ChatContentPart>,
+    //参与者名称，function消息为函数名
+    pub name: String,
+    //assistant发起的工具调用
+    pub tool_calls: Vec<ChatToolCall>,
+    //tool消息对应的调用id
+    pub tool_call_id: String,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ChatTool {
+    pub name: String,
+    pub description: String,
+    //json schema
+    pub parameters: Value,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ChatRequest {
+    pub model: String,
+    pub messages: Vec<ChatMessage>,
+    pub tools: Vec<ChatTool>,
+    pub max_tokens: u32,
+    pub temperature: f32,
+    //要求模型按该json schema输出，厂商不支持时忽略
+    pub response_schema: Option<Value>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ChatUsage {
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    pub total_tokens: u64,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ChatResponse {
+    pub model: String,
+    pub content: String,
+    pub tool_calls: Vec<ChatToolCall>,
+    pub finish_reason: String,
+    pub usage: Option<ChatUsage>,
+}
+
+//流式输出时，每收到一段文本回调一次
+pub type ChatDelta = Box<dyn FnMut(&str) + Send>;
+
+#[async_trait::async_trait]
+pub trait LLMProvider: Send + Sync {
+    fn name(&self) -> &str;
+    //请求未指定模型时使用
+    fn default_model(&self) -> &str;
+    async fn chat(&self, req: ChatRequest) -> anyhow::Result<ChatResponse>;
+    //默认不支持流式，一次性回调全部文本
+    async fn chat_stream(
+        &self,
+        req: ChatRequest,
+        mut delta: ChatDelta,
+    ) -> anyhow::Result<ChatResponse> {
+        let resp = self.chat(req).await?;
+        if !resp.content.is_empty() {
+            delta(resp.content.as_str());
+        }
+        Ok(resp)
+    }
+}
+
+impl ChatMessage {
+    pub fn new<S: Into<String>>(role: ChatRole, content: S) -> Self {
+        Self {
+            role,
+            content: content.into(),
+            ..Default::default()
+        }
+    }
+    pub fn system<S: Into<String>>(content: S) -> Self {
+        Self::new(ChatRole::System, content)
+    }
+    pub fn user<S: Into<String>>(content: S) -> Self {
+        Self::new(ChatRole::User, content)
+    }
+    pub fn assistant<S: Into<String>>(content: S) -> Self {
+        Self::new(ChatRole::Assistant, content)
+    }
+    pub fn tool<I: Into<String>, S: Into<String>>(call_id: I, content: S) -> Self {
+        let mut msg = Self::new(ChatRole::Tool, content);
+        msg.tool_call_id = call_id.into();
+        msg
+    }
+    pub fn function<N: Into<String>, S: Into<String>>(name: N, content: S) -> Self {
+        Self::new(ChatRole::Function, content).name(name)
+    }
+    pub fn tool_calls(mut self, calls: Vec<ChatToolCall>) -> Self {
+        self.tool_calls = calls;
+        self
+    }
+    pub fn parts(mut self, parts: Vec<ChatContentPart>) -> Self {
+        self.parts = parts;
+        self
+    }
+    pub fn name<S: Into<String>>(mut self, name: S) -> Self {
+        self.name = name.into();
+        self
+    }
+    //content与文本part拼接后的纯文本，供不支持多模态的场景使用
+    pub fn text(&self) -> String {
+        let mut text = self.content.clone();
+        for i in self.parts.iter() {
+            if let ChatContentPart::Text { text: s } = i {
+                if !text.is_empty() {
+                    text.push('\n');
+                }
+                text.push_str(s);
+            }
+        }
+        text
+    }
+}
+
+impl ChatRole {
+    pub fn from_name(name: &str) -> Option<Self> {
+        let role = match name.to_lowercase().as_str() {
+            "system" => ChatRole::System,
+            "user" => ChatRole::User,
+            "assistant" => ChatRole::Assistant,
+            "tool" => ChatRole::Tool,
+            "function" => ChatRole::Function,
+            _ => return None,
+        };
+        Some(role)
+    }
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ChatRole::System => "system",
+            ChatRole::User => "user",
+            ChatRole::Assistant => "assistant",
+            ChatRole::Tool => "tool",
+            ChatRole::Function => "function",
+        }
+    }
+}
+
+impl ChatContentPart {
+    pub fn text<S: Into<String>>(text: S) -> Self {
+        ChatContentPart::Text { text: text.into() }
+    }
+    pub fn image_url<S: Into<String>>(url: S) -> Self {
+        ChatContentPart::ImageUrl {
+            url: url.into(),
+            detail: String::new(),
+        }
+    }
+    pub fn image_base64<M: Into<String>, D: Into<String>>(media_type: M, data: D) -> Self {
+        ChatContentPart::ImageBase64 {
+            media_type: media_type.into(),
+            data: data.into(),
+        }
+    }
+}
+
+impl ChatRequest {
+    pub fn new<S: Into<String>>(model: S) -> Self {
+        Self {
+            model: model.into(),
+            max_tokens: 1024,
+            temperature: 0.7,
+            ..Default::default()
+        }
+    }
+    pub fn message(mut self, msg: ChatMessage) -> Self {
+        self.messages.push(msg);
+        self
+    }
+    pub fn tool(mut self, tool: ChatTool) -> Self {
+        self.tools.push(tool);
+        self
+    }
+    pub fn response_schema(mut self, schema: Value) -> Self {
+        self.response_schema = Some(schema);
+        self
+    }
+}
+
+impl ChatToolCall {
+    pub fn new<I: Into<String>, N: Into<String>, A: Into<String>>(id: I, name: N, args: A) -> Self {
+        Self {
+            id: id.into(),
+            name: name.into(),
+            arguments: args.into(),
+        }
+    }
+    //部分厂商要求参数是json对象而不是字符串
+    pub fn arguments_value(&self) -> Value {
+        if self.arguments.is_empty() {
+            return Value::Object(Default::default());
+        }
+        serde_json::from_str(self.arguments.as_str())
+            .unwrap_or_else(|_| Value::String(self.arguments.clone()))
+    }
+}
+
+impl ChatUsage {
+    pub fn new(prompt_tokens: u64, completion_tokens: u64) -> Self {
+        Self {
+            prompt_tokens,
+            completion_tokens,
+            total_tokens: prompt_tokens + completion_tokens,
+        }
+    }
+    //累加多次调用的用量
+    pub fn add(&mut self, other: &ChatUsage) {
+        self.prompt_tokens += other.prompt_tokens;
+        self.completion_tokens += other.completion_tokens;
+        self.total_tokens += other.total_tokens;
+    }
+}
+
+pub(crate) async fn post_json(
+    client: &reqwest::Client,
+    url: &str,
+    headers: &[(&str, String)],
+    body: &Value,
+) -> anyhow::Result<reqwest::Response> {
+    let mut builder = client
+        .post(url)
+        .header("Content-Type", "application/json")
+        .body(serde_json::to_vec(body)?);
+    for (k, v) in headers {
+        builder = builder.header(*k, v.as_str());
+    }
+    let resp = builder.send().await?;
+    let status = resp.status();
+    if !status.is_success() {
+        let text = resp.text().await.unwrap_or_default();
+        return anyhow::anyhow!("request[{}] failed status[{}]:{}", url, status, text).err();
+    }
+    Ok(resp)
+}
+
+pub(crate) async fn read_json(resp: reqwest::Response) -> anyhow::Result<Value> {
+    let body = resp.bytes().await?;
+    let value = serde_json::from_slice::<Value>(body.as_ref())?;
+    Ok(value)
+}
+
+//按行读取响应体，用于sse和ndjson
+pub(crate) struct LineReader {
+    resp: reqwest::Response,
+    buf: Vec<u8>,
+    eof: bool,
+}
+
+impl LineReader {
+    pub fn new(resp: reqwest::Response) -> Self {
+        Self {
+            resp,
+            buf: vec![],
+            eof: false,
+        }
+    }
+    pub async fn next_line(&mut self) -> anyhow::Result<Option<String>> {
+        loop {
+            if let Some(pos) = self.buf.iter().position(|x| *x == b'\n') {
+                let line = self.buf.drain(..=pos).collect::<Vec<u8>>();
+                let line = String::from_utf8_lossy(line.as_slice());
+                return Ok(Some(line.trim_end_matches(['\r', '\n']).to_string()));
+            }
+            if self.eof {
+                if self.buf.is_empty() {
+                    return Ok(None);
+                }
+                let line = String::from_utf8_lossy(self.buf.as_slice()).to_string();
+                self.buf.clear();
+                return Ok(Some(line));
+            }
+            match self.resp.chunk().await? {
+                Some(s) => self.buf.extend_from_slice(s.as_ref()),
+                None => self.eof = true,
+            }
+        }
+    }
+    //sse中的data字段，忽略event和注释
+    pub async fn next_sse_data(&mut self) -> anyhow::Result<Option<String>> {
+        while let Some(line) = self.next_line().await? {
+            if let Some(data) = line.strip_prefix("data:") {
+                return Ok(Some(data.trim().to_string()));
+            }
+        }
+        Ok(None)
+    }
+}
+
+pub(crate) fn json_str(value: &Value, key: &str) -> String {
+    value
+        .get(key)
+        .and_then(|x| x.as_str())
+        .unwrap_or_default()
+        .to_string()
+}
+
+pub(crate) fn json_u64(value: &Value, key: &str) -> u64 {
+    value.get(key).and_then(|x| x.as_u64()).unwrap_or(0)
+}
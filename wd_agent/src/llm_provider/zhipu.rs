@@ -0,0 +1,92 @@
+use crate::llm_provider::{
+    ChatDelta, ChatRequest, ChatResponse, LLMProvider, OpenAICompatibleProvider,
+};
+
+//智谱清言GLM，协议兼容openai，temperature取值范围为(0,1]
+#[derive(Debug, Clone)]
+pub struct ZhipuProvider {
+    inner: OpenAICompatibleProvider,
+}
+
+impl Default for ZhipuProvider {
+    fn default() -> Self {
+        let base_url = std::env::var("ZHIPU_BASE_URL")
+            .unwrap_or("https://open.bigmodel.cn/api/paas/v4".into());
+        let api_key = std::env::var("ZHIPU_API_KEY").unwrap_or_default();
+        Self::new(base_url, api_key)
+    }
+}
+
+impl ZhipuProvider {
+    pub fn new<U: Into<String>, K: Into<String>>(base_url: U, api_key: K) -> Self {
+        let inner = OpenAICompatibleProvider::new(base_url, api_key)
+            .provider_name("zhipu")
+            .default_model("glm-4")
+            .json_schema_format(false);
+        Self { inner }
+    }
+    pub fn default_model<S: Into<String>>(mut self, model: S) -> Self {
+        self.inner = self.inner.default_model(model);
+        self
+    }
+    pub fn client(mut self, client: reqwest::Client) -> Self {
+        self.inner = self.inner.client(client);
+        self
+    }
+    fn adjust(mut req: ChatRequest) -> ChatRequest {
+        req.temperature = req.temperature.clamp(0.01, 1.0);
+        req
+    }
+}
+
+#[async_trait::async_trait]
+impl LLMProvider for ZhipuProvider {
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    fn default_model(&self) -> &str {
+        self.inner.default_model.as_str()
+    }
+
+    async fn chat(&self, req: ChatRequest) -> anyhow::Result<ChatResponse> {
+        self.inner.chat(Self::adjust(req)).await
+    }
+
+    async fn chat_stream(
+        &self,
+        req: ChatRequest,
+        delta: ChatDelta,
+    ) -> anyhow::Result<ChatResponse> {
+        self.inner.chat_stream(Self::adjust(req), delta).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::llm_provider::mock::MockServer;
+    use crate::llm_provider::{ChatMessage, ChatRequest, LLMProvider, ZhipuProvider};
+
+    //cargo test llm_provider::zhipu::test::test_zhipu_provider -- --nocapture
+    #[tokio::test]
+    async fn test_zhipu_provider() {
+        let server = MockServer::start(vec![MockServer::json(serde_json::json!({
+            "model":"glm-4",
+            "choices":[{"index":0,"finish_reason":"stop","message":{"role":"assistant","content":"你好"}}],
+            "usage":{"prompt_tokens":6,"completion_tokens":2,"total_tokens":8}
+        }))])
+        .await;
+        let provider = ZhipuProvider::new(server.url.as_str(), "zp-test");
+        assert_eq!("zhipu", provider.name());
+
+        let mut req = ChatRequest::new("glm-4").message(ChatMessage::user("你好"));
+        req.temperature = 1.5;
+        let resp = provider.chat(req).await.unwrap();
+        assert_eq!("你好", resp.content.as_str());
+
+        let recv = server.requests();
+        assert_eq!("/chat/completions", recv[0].path.as_str());
+        assert_eq!("Bearer zp-test", recv[0].header("authorization"));
+        assert_eq!(1.0, recv[0].body["temperature"].as_f64().unwrap());
+    }
+}
@@ -0,0 +1,161 @@
+use crate::remote::proto::remote_node_service_client::RemoteNodeServiceClient;
+use crate::remote::proto::RemoteNodeRequest;
+use crate::rt_node_service::{is_template, parse_path, PathSegment, Template};
+use agent_rt::{Flow, Output, Service};
+use serde_json::{Map, Value};
+use std::str::FromStr;
+use tonic::transport::{Channel, Endpoint};
+use wd_tools::PFErr;
+
+/// 把节点转发到远端worker执行，结果写回本地上下文
+/// 配置的模板中引用到的上下文变量会一并转发
+#[derive(Debug, Clone)]
+pub struct RemoteService {
+    client: RemoteNodeServiceClient<Channel>,
+    //额外需要转发的上下文变量
+    pub vars: Vec<String>,
+    //远端的节点类型，为空时使用本地的node_type_id
+    pub remote_node_type: Option<String>,
+}
+
+impl RemoteService {
+    pub fn new(url: &str) -> anyhow::Result<Self> {
+        let channel = Endpoint::from_shared(url.to_string())?.connect_lazy();
+        let client = RemoteNodeServiceClient::new(channel);
+        Ok(Self {
+            client,
+            vars: vec![],
+            remote_node_type: None,
+        })
+    }
+    pub fn forward_vars<S: Into<String>>(mut self, vars: Vec<S>) -> Self {
+        let mut vars = vars.into_iter().map(|x| x.into()).collect::<Vec<String>>();
+        self.vars.append(&mut vars);
+        self
+    }
+    pub fn remote_node_type<S: Into<String>>(mut self, node_type_id: S) -> Self {
+        self.remote_node_type = Some(node_type_id.into());
+        self
+    }
+    //配置中模板引用到的节点code，包括if/for标签中的变量，不含循环变量
+    pub fn referenced_codes(cfg: &str) -> anyhow::Result<Vec<String>> {
+        fn walk(value: &Value, codes: &mut Vec<String>) -> anyhow::Result<()> {
+            match value {
+                Value::String(s) if is_template(s.as_str()) => {
+                    for path in Template::from_str(s.as_str())?.paths() {
+                        if let Some(PathSegment::Key(code)) = parse_path(path.as_str())?.first() {
+                            if !codes.contains(code) {
+                                codes.push(code.clone());
+                            }
+                        }
+                    }
+                }
+                Value::Array(list) => {
+                    for i in list {
+                        walk(i, codes)?;
+                    }
+                }
+                Value::Object(obj) => {
+                    for i in obj.values() {
+                        walk(i, codes)?;
+                    }
+                }
+                _ => {}
+            }
+            Ok(())
+        }
+        //配置不是json时整体按模板处理
+        let value =
+            serde_json::from_str::<Value>(cfg).unwrap_or_else(|_| Value::String(cfg.to_string()));
+        let mut codes = vec![];
+        walk(&value, &mut codes)?;
+        Ok(codes)
+    }
+    fn collect_vars(&self, flow: &Flow) -> anyhow::Result<Map<String, Value>> {
+        let mut codes = Self::referenced_codes(flow.node_config.as_str())?;
+        for i in self.vars.iter() {
+            if !codes.contains(i) {
+                codes.push(i.clone());
+            }
+        }
+        let mut vars = Map::new();
+        for code in codes {
+            if let Some(val) = flow
+                .ctx
+                .get_json(code.as_str(), |x: Option<&mut Value>| x.cloned())
+            {
+                vars.insert(code, val);
+            }
+        }
+        Ok(vars)
+    }
+}
+
+#[async_trait::async_trait]
+impl Service for RemoteService {
+    async fn call(&self, flow: Flow) -> anyhow::Result<Output> {
+        let vars = self.collect_vars(&flow)?;
+        let Flow {
+            ctx,
+            code,
+            node_type_id,
+            node_config,
+            ..
+        } = flow;
+        let node_type_id = self.remote_node_type.clone().unwrap_or(node_type_id);
+        let req = RemoteNodeRequest {
+            task_code: ctx.code.clone(),
+            code: code.clone(),
+            node_type_id: node_type_id.clone(),
+            node_config,
+            vars: serde_json::to_string(&vars)?,
+        };
+        let resp = match self.client.clone().call(req).await {
+            Ok(o) => o.into_inner(),
+            Err(e) => {
+                return anyhow::anyhow!(
+                    "RemoteService.call code[{}] node_type_id[{}] error:{}",
+                    code,
+                    node_type_id,
+                    e
+                )
+                .err()
+            }
+        };
+        if resp.code != 0 {
+            return anyhow::anyhow!(
+                "RemoteService.call code[{}] node_type_id[{}] remote failed code[{}] msg[{}]",
+                code,
+                node_type_id,
+                resp.code,
+                resp.msg
+            )
+            .err();
+        }
+        let output = if resp.output.is_empty() {
+            Value::Null
+        } else {
+            serde_json::from_str::<Value>(resp.output.as_str())?
+        };
+        Ok(Output::new(output).raw_to_ctx())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::remote::RemoteService;
+
+    //cargo test remote::client::test::test_referenced_codes -- --nocapture
+    #[test]
+    fn test_referenced_codes() {
+        let cfg = r#"{"a":"{{start.query}}","b":["{% if flag.on %}{% for i in items.list %}{{i.name}}{{user.name}}{% endfor %}{% endif %}"],"n":1}"#;
+        let codes = RemoteService::referenced_codes(cfg).unwrap();
+        assert_eq!(vec!["start", "flag", "items", "user"], codes);
+
+        assert_eq!(
+            vec!["start"],
+            RemoteService::referenced_codes("hi {{start.query}}").unwrap()
+        );
+        assert!(RemoteService::referenced_codes(r#"{"a":"{% if a %}"}"#).is_err());
+    }
+}
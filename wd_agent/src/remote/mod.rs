@@ -0,0 +1,9 @@
+mod client;
+//proto.rs由proto/remote_node_service.proto生成后提交，编译时不依赖protoc
+//修改proto后在wd_agent目录下用tonic-build 0.11重新生成，文件名取自package:
+//tonic_build::configure().out_dir("src/remote").compile(&["proto/remote_node_service.proto"], &["proto/"])
+pub mod proto;
+mod worker;
+
+pub use client::*;
+pub use worker::*;
@@ -0,0 +1,139 @@
+use crate::remote::proto;
+use crate::remote::proto::{RemoteNodeRequest, RemoteNodeResponse};
+use agent_rt::{PlanBuilder, Runtime, START_NODE_CODE};
+use serde_json::{Map, Value};
+use std::sync::Arc;
+use tonic::transport::server::TcpIncoming;
+use tonic::{Request, Response, Status};
+
+/// ## 托管已注册服务的worker
+/// RemoteWorker::new(Runtime::default().register_service_layer("python", python))
+/// .run("0.0.0.0:50003")
+/// .await
+/// .unwrap();
+pub struct RemoteWorker {
+    pub rt: Arc<Runtime>,
+}
+
+impl RemoteWorker {
+    pub fn new(rt: Runtime) -> Self {
+        let rt = rt.launch();
+        Self { rt }
+    }
+    pub async fn exec(&self, req: RemoteNodeRequest) -> anyhow::Result<Value> {
+        let RemoteNodeRequest {
+            task_code,
+            code,
+            node_type_id,
+            node_config,
+            vars,
+        } = req;
+        let mut vars = if vars.is_empty() {
+            Map::new()
+        } else {
+            serde_json::from_str::<Map<String, Value>>(vars.as_str())?
+        };
+        //start变量作为入参传入
+        let args = vars.remove(START_NODE_CODE).unwrap_or(Value::Null);
+
+        let plan = PlanBuilder::single_node(node_type_id, node_config).build();
+        let ctx = self.rt.ctx(format!("{}.{}.remote", task_code, code), plan);
+        for (k, v) in vars {
+            ctx.set(k, v);
+        }
+        Arc::new(ctx).block_on::<Value, _>(args).await
+    }
+    pub async fn run(self, addr: &str) -> anyhow::Result<()> {
+        let listener = tokio::net::TcpListener::bind(addr).await?;
+        self.run_with_listener(listener).await
+    }
+    //使用已绑定的端口，可以绑定:0后通过local_addr拿到实际地址
+    pub async fn run_with_listener(self, listener: tokio::net::TcpListener) -> anyhow::Result<()> {
+        wd_log::log_debug_ln!("RemoteWorker lister addr[{}]", listener.local_addr()?);
+        let incoming =
+            TcpIncoming::from_listener(listener, true, None).map_err(|e| anyhow::anyhow!(e))?;
+
+        tonic::transport::Server::builder()
+            .add_service(proto::remote_node_service_server::RemoteNodeServiceServer::new(self))
+            .serve_with_incoming(incoming)
+            .await?;
+        Ok(())
+    }
+}
+
+#[tonic::async_trait]
+impl proto::remote_node_service_server::RemoteNodeService for RemoteWorker {
+    async fn call(
+        &self,
+        request: Request<RemoteNodeRequest>,
+    ) -> Result<Response<RemoteNodeResponse>, Status> {
+        let req = request.into_inner();
+        let node_info = format!(
+            "task[{}] code[{}] node_type_id[{}]",
+            req.task_code, req.code, req.node_type_id
+        );
+        let resp = match self.exec(req).await {
+            Ok(o) => RemoteNodeResponse {
+                code: 0,
+                msg: "success".into(),
+                output: serde_json::to_string(&o).unwrap_or_default(),
+            },
+            Err(e) => {
+                wd_log::log_error_ln!("RemoteWorker.call {} error:{}", node_info, e);
+                RemoteNodeResponse {
+                    code: 500,
+                    msg: e.to_string(),
+                    output: String::new(),
+                }
+            }
+        };
+        Ok(Response::new(resp))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::remote::{RemoteService, RemoteWorker};
+    use crate::rt_node_service::VarFlowChartService;
+    use agent_rt::{PlanBuilder, Runtime};
+    use serde_json::Value;
+    use wd_tools::PFArc;
+
+    //cargo test remote::worker::test::test_remote_service -- --nocapture
+    #[tokio::test]
+    async fn test_remote_service() {
+        let worker = RemoteWorker::new(
+            Runtime::default()
+                .register_service_layer("flow_chart_var", VarFlowChartService::default()),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { worker.run_with_listener(listener).await.unwrap() });
+
+        let remote = RemoteService::new(format!("http://{}", addr).as_str())
+            .unwrap()
+            .remote_node_type("flow_chart_var");
+        let rt = Runtime::default()
+            .register_service("remote_var", remote)
+            .launch();
+
+        let output: Value = rt
+            .ctx(
+                "remote-test-001",
+                PlanBuilder::single_node(
+                    "remote_var",
+                    r#"{"answer":"remote:{{start.query}}","len":"{{start.len}}"}"#,
+                )
+                .build(),
+            )
+            .arc()
+            .block_on(serde_json::json!({
+                "query":"hello",
+                "len":2
+            }))
+            .await
+            .unwrap();
+
+        assert_eq!(serde_json::json!({"answer":"remote:hello","len":2}), output);
+    }
+}
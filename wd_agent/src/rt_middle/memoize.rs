@@ -0,0 +1,309 @@
+use crate::llm_provider::fnv1a;
+use crate::rt_node_service::CfgBound;
+use agent_rt::{Flow, Output, Service};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[async_trait::async_trait]
+pub trait MemoStore: Send + Sync {
+    async fn get(&self, key: &str) -> anyhow::Result<Option<Value>>;
+    async fn set(&self, key: &str, value: Value, ttl: Duration) -> anyhow::Result<()>;
+}
+
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+struct MemoEntry {
+    //unix秒，过期时间
+    expire_at: u64,
+    value: Value,
+}
+
+//缓存的节点输出，命中时按原来的形式还原
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+struct MemoOutput {
+    raw_to_ctx: bool,
+    value: Value,
+}
+
+impl MemoOutput {
+    fn into_output(self) -> Output {
+        let output = Output::new(self.value);
+        if self.raw_to_ctx {
+            output.raw_to_ctx()
+        } else {
+            output
+        }
+    }
+}
+
+impl MemoEntry {
+    fn new(value: Value, ttl: Duration) -> Self {
+        let expire_at = Self::now() + ttl.as_secs();
+        Self { expire_at, value }
+    }
+    fn now() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|x| x.as_secs())
+            .unwrap_or(0)
+    }
+    fn is_expired(&self) -> bool {
+        self.expire_at <= Self::now()
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct MemoryMemoStore {
+    map: Mutex<HashMap<String, MemoEntry>>,
+}
+
+#[async_trait::async_trait]
+impl MemoStore for MemoryMemoStore {
+    async fn get(&self, key: &str) -> anyhow::Result<Option<Value>> {
+        let mut lock = self.map.lock().unwrap();
+        let expired = match lock.get(key) {
+            None => return Ok(None),
+            Some(entry) => entry.is_expired(),
+        };
+        if expired {
+            lock.remove(key);
+            return Ok(None);
+        }
+        Ok(lock.get(key).map(|x| x.value.clone()))
+    }
+
+    async fn set(&self, key: &str, value: Value, ttl: Duration) -> anyhow::Result<()> {
+        let mut lock = self.map.lock().unwrap();
+        lock.insert(key.to_string(), MemoEntry::new(value, ttl));
+        Ok(())
+    }
+}
+
+//每个key一个文件，重启后依然有效
+#[derive(Debug)]
+pub struct FileMemoStore {
+    pub path: String,
+}
+
+impl Default for FileMemoStore {
+    fn default() -> Self {
+        let path = "./memo_cache".into();
+        Self { path }
+    }
+}
+
+impl FileMemoStore {
+    pub fn new<P: Into<String>>(path: P) -> Self {
+        let path = path.into();
+        Self { path }
+    }
+    fn file_path(&self, key: &str) -> String {
+        format!("{}/{}.json", self.path, key)
+    }
+}
+
+#[async_trait::async_trait]
+impl MemoStore for FileMemoStore {
+    async fn get(&self, key: &str) -> anyhow::Result<Option<Value>> {
+        let path = self.file_path(key);
+        let data = match tokio::fs::read(path.as_str()).await {
+            Ok(o) => o,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(anyhow::Error::from(e)),
+        };
+        let entry = match serde_json::from_slice::<MemoEntry>(data.as_slice()) {
+            Ok(o) => o,
+            Err(e) => {
+                wd_log::log_warn_ln!("FileMemoStore.get file[{}] broken:{}", path, e);
+                let _ = tokio::fs::remove_file(path).await;
+                return Ok(None);
+            }
+        };
+        if entry.is_expired() {
+            let _ = tokio::fs::remove_file(path).await;
+            return Ok(None);
+        }
+        Ok(Some(entry.value))
+    }
+
+    async fn set(&self, key: &str, value: Value, ttl: Duration) -> anyhow::Result<()> {
+        tokio::fs::create_dir_all(self.path.as_str()).await?;
+        let data = serde_json::to_vec(&MemoEntry::new(value, ttl))?;
+        tokio::fs::write(self.file_path(key), data).await?;
+        Ok(())
+    }
+}
+
+/// 缓存节点输出，只对开启的node_type_id生效
+/// key = node_type_id + 绑定后配置的hash
+/// 命中缓存时不会执行节点，所以只适合无副作用的节点
+/// 只缓存Value类型的输出，rust原生类型的输出无法还原，不缓存
+pub struct MemoizeMiddle {
+    store: Arc<dyn MemoStore>,
+    services: HashMap<String, Duration>,
+}
+
+impl Default for MemoizeMiddle {
+    fn default() -> Self {
+        MemoizeMiddle::new(MemoryMemoStore::default())
+    }
+}
+
+impl MemoizeMiddle {
+    pub fn new<S: MemoStore + 'static>(store: S) -> Self {
+        let store = Arc::new(store);
+        let services = HashMap::new();
+        Self { store, services }
+    }
+    pub fn enable<S: Into<String>>(mut self, node_type_id: S, ttl: Duration) -> Self {
+        self.services.insert(node_type_id.into(), ttl);
+        self
+    }
+    //key会持久化，使用固定的hash算法，不能用随版本变化的DefaultHasher
+    pub fn memo_key(node_type_id: &str, cfg: &Value) -> String {
+        let hash = fnv1a(format!("{}\n{}", node_type_id, cfg).as_str());
+        format!("{}-{:016x}", node_type_id, hash)
+    }
+    fn bound_key(flow: &Flow) -> anyhow::Result<String> {
+        let cfg = serde_json::from_str::<CfgBound<Value>>(flow.node_config.as_str())?;
+        let value = cfg.raw_bound_value(&flow.ctx)?;
+        Ok(Self::memo_key(flow.node_type_id.as_str(), &value))
+    }
+}
+
+#[async_trait::async_trait]
+impl Service for MemoizeMiddle {
+    async fn call(&self, flow: Flow) -> anyhow::Result<Output> {
+        let ttl = match self.services.get(flow.node_type_id.as_str()) {
+            Some(s) => *s,
+            None => return flow.call().await,
+        };
+        let key = match Self::bound_key(&flow) {
+            Ok(o) => o,
+            Err(e) => {
+                wd_log::log_debug_ln!("MemoizeMiddle: code[{}] skip memo:{}", flow.code, e);
+                return flow.call().await;
+            }
+        };
+        match self.store.get(key.as_str()).await {
+            Ok(Some(val)) => match serde_json::from_value::<MemoOutput>(val) {
+                Ok(o) => {
+                    wd_log::log_debug_ln!("MemoizeMiddle: code[{}] hit memo[{}]", flow.code, key);
+                    return Ok(o.into_output());
+                }
+                Err(e) => wd_log::log_warn_ln!("MemoizeMiddle: memo[{}] broken:{}", key, e),
+            },
+            Ok(None) => {}
+            Err(e) => wd_log::log_warn_ln!("MemoizeMiddle: get memo[{}] error:{}", key, e),
+        }
+        let output = flow.call().await?;
+        if let Some(val) = output.any.downcast_ref::<Value>() {
+            let memo = MemoOutput {
+                raw_to_ctx: output.raw_to_ctx,
+                value: val.clone(),
+            };
+            let memo = serde_json::to_value(memo)?;
+            if let Err(e) = self.store.set(key.as_str(), memo, ttl).await {
+                wd_log::log_warn_ln!("MemoizeMiddle: set memo[{}] error:{}", key, e);
+            }
+        }
+        Ok(output)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::rt_middle::{FileMemoStore, MemoizeMiddle};
+    use agent_rt::{Output, PlanBuilder, Runtime};
+    use serde_json::Value;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+    use wd_tools::PFArc;
+
+    async fn run_twice(memo: MemoizeMiddle) -> usize {
+        let count = Arc::new(AtomicUsize::new(0));
+        let counter = count.clone();
+        let rt = Runtime::default()
+            .register_service_fn("expensive", move |f| {
+                let counter = counter.clone();
+                async move {
+                    counter.fetch_add(1, Ordering::Relaxed);
+                    let val: Value = serde_json::from_str(f.node_config.as_str())?;
+                    Ok(Output::new(val).raw_to_ctx())
+                }
+            })
+            .register_middle(memo)
+            .launch();
+
+        for i in 0..2 {
+            let out = rt
+                .ctx(
+                    format!("memo-test-{i}"),
+                    PlanBuilder::single_node("expensive", r#"{"query":"{{start.query}}"}"#).build(),
+                )
+                .arc()
+                .block_on::<Value, _>(serde_json::json!({"query":"hello"}))
+                .await
+                .unwrap();
+            assert_eq!(r#"{"query":"{{start.query}}"}"#, out.to_string().as_str());
+        }
+        count.load(Ordering::Relaxed)
+    }
+
+    //cargo test rt_middle::memoize::test::test_memoize -- --nocapture
+    #[tokio::test]
+    async fn test_memoize() {
+        let memo = MemoizeMiddle::default();
+        assert_eq!(2, run_twice(memo).await);
+
+        let memo = MemoizeMiddle::default().enable("expensive", Duration::from_secs(60));
+        assert_eq!(1, run_twice(memo).await);
+
+        let path = std::env::temp_dir().join("wd_agent_memo_test");
+        let _ = std::fs::remove_dir_all(&path);
+        let store = FileMemoStore::new(path.to_string_lossy().to_string());
+        let memo = MemoizeMiddle::new(store).enable("expensive", Duration::from_secs(60));
+        assert_eq!(1, run_twice(memo).await);
+        let store = FileMemoStore::new(path.to_string_lossy().to_string());
+        let memo = MemoizeMiddle::new(store).enable("expensive", Duration::from_secs(60));
+        assert_eq!(0, run_twice(memo).await);
+        let _ = std::fs::remove_dir_all(&path);
+    }
+
+    //cargo test rt_middle::memoize::test::test_memoize_output_shape -- --nocapture
+    #[tokio::test]
+    async fn test_memoize_output_shape() {
+        let rt = Runtime::default()
+            .register_service_fn("plain", |f| async move {
+                let val: Value = serde_json::from_str(f.node_config.as_str())?;
+                Ok(Output::new(val))
+            })
+            .register_service_fn("check", |f| async move {
+                //非raw_to_ctx的输出以Output存入ctx
+                let raw = f.ctx.get("a", |x: &mut Output| x.raw_to_ctx);
+                Ok(Output::json(serde_json::json!(raw)))
+            })
+            .register_middle(MemoizeMiddle::default().enable("plain", Duration::from_secs(60)))
+            .launch();
+        for i in 0..2 {
+            let plan = PlanBuilder::start(("a", "plain", r#"{"q":1}"#), vec!["end"])
+                .sequence(vec![("end", "check", "")], "")
+                .check_and_build()
+                .unwrap();
+            let out = rt
+                .ctx(format!("memo-shape-{i}"), plan)
+                .arc()
+                .block_on::<Value, _>(())
+                .await
+                .unwrap();
+            assert_eq!(serde_json::json!(false), out);
+        }
+        assert_eq!(
+            MemoizeMiddle::memo_key("plain", &serde_json::json!({"q":1})),
+            "plain-f528c54ab7e8bfd7"
+        );
+    }
+}
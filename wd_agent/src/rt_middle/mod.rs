@@ -0,0 +1,5 @@
+mod memoize;
+mod strict_binding;
+
+pub use memoize::*;
+pub use strict_binding::*;
@@ -0,0 +1,109 @@
+use crate::rt_node_service::CfgBound;
+use agent_rt::{Flow, Output, Service};
+use serde_json::Value;
+use std::collections::HashSet;
+use wd_tools::PFErr;
+
+/// 严格绑定：节点执行前按严格模式绑定一次配置
+/// 有找不到的引用时报错，错误中带上节点code和全部找不到的引用
+/// 条件判断中的引用和带default过滤器的引用不检查
+/// 绑定结果不会传给节点，节点服务会再渲染一次配置，模板较大时有额外开销
+/// 不直接传递绑定结果，是为了避免ctx中的数据带有模板语法时被二次渲染
+#[derive(Debug, Default)]
+pub struct StrictBindingMiddle {
+    skip: HashSet<String>,
+}
+
+impl StrictBindingMiddle {
+    //配置不是模板的节点类型可以跳过检查
+    pub fn skip<S: Into<String>>(mut self, node_type_id: S) -> Self {
+        self.skip.insert(node_type_id.into());
+        self
+    }
+}
+
+#[async_trait::async_trait]
+impl Service for StrictBindingMiddle {
+    async fn call(&self, flow: Flow) -> anyhow::Result<Output> {
+        if self.skip.contains(flow.node_type_id.as_str()) {
+            return flow.call().await;
+        }
+        //配置不是json对象的节点不检查
+        let Ok(cfg) = serde_json::from_str::<CfgBound<Value>>(flow.node_config.as_str()) else {
+            return flow.call().await;
+        };
+        if let Err(e) = cfg.strict_bound_value(&flow.ctx) {
+            return anyhow::anyhow!("node[{}] type[{}] {}", flow.code, flow.node_type_id, e).err();
+        }
+        flow.call().await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::rt_middle::StrictBindingMiddle;
+    use crate::rt_node_service::VarFlowChartService;
+    use agent_rt::{PlanBuilder, Runtime};
+    use serde_json::Value;
+    use wd_tools::PFArc;
+
+    //cargo test rt_middle::strict_binding::test::test_strict_binding -- --nocapture
+    #[tokio::test]
+    async fn test_strict_binding() {
+        let rt = Runtime::default()
+            .register_service_layer("var", VarFlowChartService::default())
+            .register_service_layer("raw", VarFlowChartService::default())
+            .register_middle(StrictBindingMiddle::default().skip("raw"))
+            .launch();
+        let plan = |end: &str| {
+            PlanBuilder::start(("prompt", "var", r#"{"q":"{{start.q}}"}"#), vec!["end"])
+                .sequence(vec![("end", end, r#"{"text":"{{prompt.q}} {{prompt.a}}","list":"{{start.docs[*].t}}","n":"{{start.n | default:0}}","ok":"{% if start.ok %}y{% endif %}"}"#)], "")
+                .check_and_build()
+                .unwrap()
+        };
+
+        let res = rt
+            .ctx("test_strict_ok", plan("var"))
+            .arc()
+            .block_on::<Value, _>(serde_json::json!({"q":"hi","docs":[{"t":1}]}))
+            .await;
+        let err = format!("{:?}", res);
+        assert!(
+            err.contains("node[end] type[var] unresolved refs[prompt.a]"),
+            "{}",
+            err
+        );
+
+        let res = rt
+            .ctx("test_strict_skip", plan("raw"))
+            .arc()
+            .block_on::<Value, _>(serde_json::json!({"q":"hi","docs":[{"t":1}]}))
+            .await
+            .unwrap();
+        assert_eq!(
+            serde_json::json!({"text":"hi ","list":[1],"n":0,"ok":""}),
+            res
+        );
+
+        let res = rt
+            .ctx("test_strict_start", plan("var"))
+            .arc()
+            .block_on::<Value, _>(serde_json::json!({}))
+            .await;
+        let err = format!("{:?}", res);
+        assert!(
+            err.contains("node[prompt] type[var] unresolved refs[start.q]"),
+            "{}",
+            err
+        );
+
+        //通配符前面的路径找不到时同样报错
+        let res = rt
+            .ctx("test_strict_wildcard", plan("var"))
+            .arc()
+            .block_on::<Value, _>(serde_json::json!({"q":"hi","docz":[{"t":1}]}))
+            .await;
+        let err = format!("{:?}", res);
+        assert!(err.contains("start.docs[*].t"), "{}", err);
+    }
+}
@@ -0,0 +1,328 @@
+use crate::llm_provider::{ChatMessage, ChatUsage};
+use crate::plugin_tools::PluginControlSchedule;
+use crate::rt_node_service::{
+    call_tool_event, CfgBound, ContextWindowReport, LLMContextMessage, LLMNodeRequest, LLMService,
+    LLMToolCallRequest, LLMToolCallResponse, ToolEvent,
+};
+use agent_rt::{Context, ServiceLayer};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::Instant;
+
+//llm与工具调用循环执行的agent节点，替代 llm->selector->python->injector 的环形编排
+pub struct AgentService {
+    llm: LLMService,
+    tools: Box<dyn ToolEvent + Sync + 'static>,
+}
+
+impl Default for AgentService {
+    fn default() -> Self {
+        Self::new(
+            LLMService::default(),
+            PluginControlSchedule::default().to_tool_event(),
+        )
+    }
+}
+
+impl AgentService {
+    pub fn new<T: ToolEvent + Sync + 'static>(llm: LLMService, tools: T) -> Self {
+        Self {
+            llm,
+            tools: Box::new(tools),
+        }
+    }
+    pub fn llm(mut self, llm: LLMService) -> Self {
+        self.llm = llm;
+        self
+    }
+    pub fn tools<T: ToolEvent + Sync + 'static>(mut self, tools: T) -> Self {
+        self.tools = Box::new(tools);
+        self
+    }
+}
+
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+pub struct AgentNodeRequest {
+    #[serde(flatten)]
+    pub llm: LLMNodeRequest,
+    //llm调用的最大轮数，达到后即使模型仍在调用工具也结束
+    #[serde(default = "AgentNodeRequest::default_max_iterations")]
+    pub max_iterations: usize,
+}
+
+impl AgentNodeRequest {
+    pub fn default_max_iterations() -> usize {
+        5
+    }
+}
+
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct AgentStep {
+    pub iteration: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub answer: Option<String>,
+    pub tools: Vec<LLMToolCallRequest>,
+    pub results: Vec<LLMToolCallResponse>,
+}
+
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct AgentNodeResponse {
+    pub answer: Option<String>,
+    //stop:模型不再调用工具 max_iterations:达到最大轮数
+    pub finish_reason: String,
+    pub iterations: usize,
+    //各轮llm调用的用量合计
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub usage: Option<ChatUsage>,
+    pub latency_ms: u64,
+    //最后一轮请求的上下文窗口裁剪情况
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub context_window: Option<ContextWindowReport>,
+    pub steps: Vec<AgentStep>,
+    //本次新增的assistant与tool消息，可直接追加到会话历史
+    pub context: Vec<LLMContextMessage>,
+}
+
+#[async_trait::async_trait]
+impl ServiceLayer for AgentService {
+    type Config = CfgBound<AgentNodeRequest>;
+    type Output = AgentNodeResponse;
+
+    async fn call(
+        &self,
+        code: String,
+        ctx: Arc<Context>,
+        cfg: Self::Config,
+    ) -> anyhow::Result<Self::Output> {
+        let AgentNodeRequest {
+            mut llm,
+            max_iterations,
+        } = cfg.bound(&ctx)?;
+        let is_stream = llm.is_stream;
+        //query放进context，后续的assistant与tool消息追加在它之后，裁剪上下文时保留
+        if !llm.query.is_empty() {
+            let query = std::mem::take(&mut llm.query);
+            llm.context
+                .push(LLMContextMessage::new("user", query).pin());
+        }
+
+        let mut resp = AgentNodeResponse {
+            finish_reason: "max_iterations".into(),
+            ..Default::default()
+        };
+        let start = Instant::now();
+        for iteration in 1..=max_iterations.max(1) {
+            //每轮都按上下文窗口重新裁剪，工具结果会让消息不断变长
+            let (provider, req, report) = self.llm.chat_request(llm.clone()).await?;
+            resp.context_window = Some(report);
+            let chat = self
+                .llm
+                .send(&ctx, provider.as_ref(), req, is_stream, true)
+                .await?;
+            resp.latency_ms = start.elapsed().as_millis() as u64;
+            resp.iterations = iteration;
+            if let Some(ref usage) = chat.usage {
+                resp.usage.get_or_insert_with(ChatUsage::default).add(usage);
+            }
+            let mut step = AgentStep {
+                iteration,
+                ..Default::default()
+            };
+            if !chat.content.is_empty() {
+                step.answer = Some(chat.content.clone());
+            }
+            if chat.tool_calls.is_empty() {
+                resp.answer = step.answer.clone();
+                resp.finish_reason = "stop".into();
+                resp.steps.push(step);
+                if !chat.content.is_empty() {
+                    resp.context
+                        .push(LLMContextMessage::new("assistant", chat.content));
+                }
+                break;
+            }
+
+            let msg =
+                ChatMessage::assistant(chat.content.clone()).tool_calls(chat.tool_calls.clone());
+            resp.context.push(msg.clone().into());
+            llm.context.push(msg.into());
+            for call in chat.tool_calls.into_iter() {
+                let call: LLMToolCallRequest = call.into();
+                wd_log::log_debug_ln!(
+                    "agent[{}] step[{}] call tool[{}] args:{}",
+                    code,
+                    iteration,
+                    call.name,
+                    call.args
+                );
+                let result = call_tool_event(self.tools.as_ref(), &ctx, &call).await?;
+                let msg = ChatMessage::tool(
+                    result.call_id.clone().unwrap_or_default(),
+                    result.content.clone(),
+                );
+                resp.context.push(msg.clone().into());
+                llm.context.push(msg.into());
+                step.tools.push(call);
+                step.results.push(result);
+            }
+            resp.steps.push(step);
+        }
+        Ok(resp)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::llm_provider::{
+        ChatRequest, ChatResponse, ChatRole, ChatToolCall, ChatUsage, LLMProvider,
+    };
+    use crate::plugin_tools::PluginControlSchedule;
+    use crate::rt_node_service::{AgentNodeResponse, AgentService, LLMService};
+    use agent_rt::{PlanBuilder, Runtime};
+    use serde_json::Value;
+    use wd_tools::{PFArc, PFErr};
+
+    //前两轮调用工具，第三轮汇总工具结果回答
+    struct ToolLoopProvider;
+
+    #[async_trait::async_trait]
+    impl LLMProvider for ToolLoopProvider {
+        fn name(&self) -> &str {
+            "tool_loop"
+        }
+        fn default_model(&self) -> &str {
+            "tool-loop-model"
+        }
+        async fn chat(&self, req: ChatRequest) -> anyhow::Result<ChatResponse> {
+            if !req.messages.iter().any(|x| x.role == ChatRole::User) {
+                return anyhow::anyhow!("user question not found").err();
+            }
+            let turns = req
+                .messages
+                .iter()
+                .filter(|x| x.role == ChatRole::Assistant)
+                .count();
+            let results = req
+                .messages
+                .iter()
+                .filter(|x| x.role == ChatRole::Tool)
+                .map(|x| x.content.clone())
+                .collect::<Vec<_>>();
+            let resp = if turns < 2 {
+                ChatResponse {
+                    content: format!("step{}", turns),
+                    tool_calls: vec![
+                        ChatToolCall::new(format!("call_{}", turns), "calc", "{}"),
+                        ChatToolCall::new("call_e", "miss", "{}"),
+                    ],
+                    finish_reason: "tool_calls".into(),
+                    usage: Some(ChatUsage::new(10, 5)),
+                    ..Default::default()
+                }
+            } else {
+                ChatResponse {
+                    content: format!("done:{}", results.join(",")),
+                    finish_reason: "stop".into(),
+                    usage: Some(ChatUsage::new(20, 5)),
+                    ..Default::default()
+                }
+            };
+            Ok(resp)
+        }
+    }
+
+    //cargo test rt_node_service::agent::test::test_agent_tool_loop -- --nocapture
+    #[tokio::test]
+    async fn test_agent_tool_loop() {
+        let tools = PluginControlSchedule::default()
+            .register_plugin("calc", |_| async move { Ok("1".to_string()) })
+            .to_tool_event();
+        let agent = AgentService::new(
+            LLMService::new().register_provider("tool_loop", ToolLoopProvider),
+            tools,
+        );
+        let rt = Runtime::default()
+            .register_service_layer("agent", agent)
+            .launch();
+
+        let cfg = r#"{"provider":"tool_loop","prompt":"you are a bot","query":"{{start.query}}","max_iterations":3}"#;
+        let res = rt
+            .ctx("test_agent", PlanBuilder::single_node("agent", cfg).build())
+            .arc()
+            .block_on::<Value, _>(serde_json::json!({"query":"hi"}))
+            .await
+            .unwrap();
+        println!("{}", res);
+        let resp = serde_json::from_value::<AgentNodeResponse>(res).unwrap();
+        assert_eq!("stop", resp.finish_reason.as_str());
+        assert_eq!(3, resp.iterations);
+        assert_eq!(3, resp.steps.len());
+        assert_eq!(Some(ChatUsage::new(40, 15)), resp.usage);
+        assert_eq!("step0", resp.steps[0].answer.clone().unwrap());
+        assert_eq!(2, resp.steps[0].results.len());
+        assert!(resp.steps[0].results[1]
+            .content
+            .starts_with("tool[miss] error"));
+        let answer = resp.answer.unwrap();
+        assert!(answer.starts_with("done:1,tool[miss] error"));
+        //每轮一条带全部工具调用的assistant消息加各自的tool结果，最后追加回答
+        assert_eq!(7, resp.context.len());
+        assert_eq!("step0", resp.context[0].content.as_str());
+        assert_eq!(2, resp.context[0].tool_calls.len());
+        assert_eq!("calc", resp.context[0].tool_calls[0].name.as_str());
+        assert_eq!("call_0", resp.context[1].call_id.as_str());
+        assert_eq!("1", resp.context[1].content.as_str());
+
+        let cfg = r#"{"provider":"tool_loop","query":"loop","max_iterations":2}"#;
+        let res = rt
+            .ctx(
+                "test_agent_max",
+                PlanBuilder::single_node("agent", cfg).build(),
+            )
+            .arc()
+            .block_on::<Value, _>(())
+            .await
+            .unwrap();
+        let resp = serde_json::from_value::<AgentNodeResponse>(res).unwrap();
+        assert_eq!("max_iterations", resp.finish_reason.as_str());
+        assert_eq!(2, resp.iterations);
+        assert_eq!(None, resp.answer);
+    }
+
+    //cargo test rt_node_service::agent::test::test_agent_context_window -- --nocapture
+    #[tokio::test]
+    async fn test_agent_context_window() {
+        let tools = PluginControlSchedule::default()
+            .register_plugin("calc", |_| async move { Ok("word ".repeat(400)) })
+            .to_tool_event();
+        let agent = AgentService::new(
+            LLMService::new().register_provider("tool_loop", ToolLoopProvider),
+            tools,
+        );
+        let rt = Runtime::default()
+            .register_service_layer("agent", agent)
+            .launch();
+
+        let cfg = r#"{"provider":"tool_loop","prompt":"you are a bot","query":"hi","max_iterations":3,"context_window":300,"max_tokens":50}"#;
+        let res = rt
+            .ctx(
+                "test_agent_window",
+                PlanBuilder::single_node("agent", cfg).build(),
+            )
+            .arc()
+            .block_on::<Value, _>(())
+            .await
+            .unwrap();
+        let resp = serde_json::from_value::<AgentNodeResponse>(res).unwrap();
+        //过长的工具结果在下一轮被裁掉，模型看不到之前的调用而继续调用工具
+        let report = resp.context_window.unwrap();
+        assert!(report.dropped_messages > 0);
+        assert!(report.prompt_tokens + report.max_tokens <= report.window);
+        //用户的问题不会被裁掉，每轮都能正常请求
+        assert_eq!(3, resp.iterations);
+        assert_eq!("max_iterations", resp.finish_reason.as_str());
+        assert_eq!(9, resp.context.len());
+    }
+}
@@ -0,0 +1,276 @@
+use crate::llm_provider::{ChatContentPart, ChatRole};
+use crate::rt_node_service::{
+    estimate_tokens, ChatSummarizer, LLMContextMessage, LLMNodeRequest, CHAT_SUMMARY_PREFIX,
+};
+use serde::{Deserialize, Serialize};
+use tiktoken_rs::tokenizer::{get_tokenizer, Tokenizer};
+
+//openai系列模型用tiktoken计数，其他模型退化为估算
+pub fn count_tokens(model: &str, text: &str) -> usize {
+    if text.is_empty() {
+        return 0;
+    }
+    let bpe = match get_tokenizer(model) {
+        Some(Tokenizer::O200kBase) => tiktoken_rs::o200k_base_singleton(),
+        Some(Tokenizer::Cl100kBase) => tiktoken_rs::cl100k_base_singleton(),
+        Some(Tokenizer::P50kBase) => tiktoken_rs::p50k_base_singleton(),
+        Some(Tokenizer::P50kEdit) => tiktoken_rs::p50k_edit_singleton(),
+        Some(Tokenizer::R50kBase) | Some(Tokenizer::Gpt2) => tiktoken_rs::r50k_base_singleton(),
+        None => return estimate_tokens(text),
+    };
+    let count = bpe.lock().encode_ordinary(text).len();
+    count
+}
+
+//模型的上下文窗口大小，未知模型按8k处理
+pub fn model_context_window(model: &str) -> usize {
+    let name = model.to_lowercase();
+    if name.starts_with("claude") {
+        return 200_000;
+    }
+    if name.starts_with("glm-4") {
+        return 128_000;
+    }
+    if name.starts_with("deepseek") {
+        return 64_000;
+    }
+    if name.starts_with("qwen") {
+        return 32_768;
+    }
+    if get_tokenizer(model).is_some() {
+        return tiktoken_rs::model::get_context_size(model);
+    }
+    8192
+}
+
+//一张图片按低精度的开销计算
+const IMAGE_TOKENS: usize = 85;
+
+fn message_tokens(model: &str, msg: &LLMContextMessage) -> usize {
+    //每条消息的格式开销按4个token计算
+    let mut tokens = 4 + count_tokens(model, msg.content.as_str());
+    tokens += count_tokens(model, msg.name.as_str());
+    for i in msg.parts.iter() {
+        tokens += match i {
+            ChatContentPart::Text { text } => count_tokens(model, text.as_str()),
+            _ => IMAGE_TOKENS,
+        };
+    }
+    for i in msg.all_tool_calls() {
+        tokens += count_tokens(model, i.name.as_str()) + count_tokens(model, i.arguments.as_str());
+    }
+    tokens
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ContextWindowReport {
+    pub model: String,
+    pub window: usize,
+    //裁剪后的prompt token数
+    pub prompt_tokens: usize,
+    pub max_tokens: usize,
+    pub dropped_messages: usize,
+    pub summarized: bool,
+}
+
+impl LLMNodeRequest {
+    pub fn count_prompt_tokens(&self) -> usize {
+        let model = self.model_name();
+        //回复的起始开销
+        let mut tokens = 3;
+        let has_system = self
+            .context
+            .first()
+            .map(|x| x.is_role(ChatRole::System))
+            .unwrap_or(false);
+        //有system消息时prompt合并进去
+        if !self.prompt.is_empty() {
+            tokens += count_tokens(model, self.prompt.as_str()) + if has_system { 1 } else { 4 };
+        }
+        for msg in self.context.iter() {
+            tokens += message_tokens(model, msg);
+        }
+        if !self.query.is_empty() {
+            tokens += 4 + count_tokens(model, self.query.as_str());
+        }
+        if !self.tools.is_empty() {
+            let tools = serde_json::to_string(&self.tools).unwrap_or_default();
+            tokens += count_tokens(model, tools.as_str());
+        }
+        tokens
+    }
+
+    //从最早的非system且未固定的消息开始丢弃，直到prompt加上max_tokens能放进上下文窗口
+    pub async fn fit_context_window(
+        &mut self,
+        summarizer: Option<&dyn ChatSummarizer>,
+    ) -> anyhow::Result<ContextWindowReport> {
+        let window = if self.context_window > 0 {
+            self.context_window
+        } else {
+            model_context_window(self.model_name())
+        };
+        let budget = window.saturating_sub(self.max_tokens as usize);
+        let mut tokens = self.count_prompt_tokens();
+        let mut dropped = vec![];
+        while tokens > budget {
+            let Some(index) = self
+                .context
+                .iter()
+                .position(|x| !x.is_role(ChatRole::System) && !x.pinned)
+            else {
+                break;
+            };
+            dropped.push(self.context.remove(index));
+            //不保留失去对应工具调用的tool消息
+            while index < self.context.len() && self.context[index].is_role(ChatRole::Tool) {
+                dropped.push(self.context.remove(index));
+            }
+            tokens = self.count_prompt_tokens();
+        }
+
+        let mut report = ContextWindowReport {
+            model: self.model_name().to_string(),
+            window,
+            max_tokens: self.max_tokens as usize,
+            dropped_messages: dropped.len(),
+            ..Default::default()
+        };
+        if let Some(summarizer) = summarizer.filter(|_| self.summarize_context) {
+            if !dropped.is_empty() {
+                let summary = summarizer.summarize(dropped.as_slice()).await?;
+                let index = self
+                    .context
+                    .iter()
+                    .position(|x| !x.is_role(ChatRole::System))
+                    .unwrap_or(self.context.len());
+                self.context.insert(
+                    index,
+                    LLMContextMessage::new("user", format!("{}{}", CHAT_SUMMARY_PREFIX, summary)),
+                );
+                report.summarized = true;
+                tokens = self.count_prompt_tokens();
+            }
+        }
+        if tokens > budget {
+            wd_log::log_warn_ln!(
+                "llm prompt tokens[{}] + max_tokens[{}] exceed context window[{}] of model[{}]",
+                tokens,
+                self.max_tokens,
+                window,
+                self.model_name()
+            );
+        }
+        report.prompt_tokens = tokens;
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::rt_node_service::{
+        count_tokens, model_context_window, ChatSummarizer, LLMContextMessage, LLMNodeRequest,
+        CHAT_SUMMARY_PREFIX,
+    };
+
+    struct JoinSummarizer;
+
+    #[async_trait::async_trait]
+    impl ChatSummarizer for JoinSummarizer {
+        async fn summarize(&self, history: &[LLMContextMessage]) -> anyhow::Result<String> {
+            Ok(format!("{} messages", history.len()))
+        }
+    }
+
+    fn request() -> LLMNodeRequest {
+        let mut context = vec![LLMContextMessage::new("system", "you are a bot")];
+        for i in 0..10 {
+            context.push(LLMContextMessage::new(
+                "user",
+                format!("question {i} ").repeat(20),
+            ));
+            context.push(LLMContextMessage {
+                role: "assistant".into(),
+                call_id: format!("call_{i}"),
+                call_name: "search".into(),
+                call_args: "{}".into(),
+                ..Default::default()
+            });
+            context.push(LLMContextMessage {
+                role: "tool".into(),
+                content: format!("result {i} ").repeat(20),
+                call_id: format!("call_{i}"),
+                ..Default::default()
+            });
+        }
+        serde_json::from_value(serde_json::json!({
+            "model":"gpt-4o",
+            "query":"last question",
+            "context":context,
+            "max_tokens":200,
+            "context_window":1000,
+        }))
+        .unwrap()
+    }
+
+    //cargo test rt_node_service::context_window::test::test_count_tokens -- --nocapture
+    #[test]
+    fn test_count_tokens() {
+        assert_eq!(2, count_tokens("gpt-4o", "hello world"));
+        assert_eq!(2, count_tokens("gpt-3.5-turbo", "hello world"));
+        assert_eq!(3, count_tokens("glm-4", "hello world"));
+        assert_eq!(128_000, model_context_window("gpt-4o-mini"));
+        assert_eq!(200_000, model_context_window("claude-3-5-sonnet"));
+        assert_eq!(8192, model_context_window("llama3"));
+    }
+
+    //cargo test rt_node_service::context_window::test::test_fit_context_window -- --nocapture
+    #[tokio::test]
+    async fn test_fit_context_window() {
+        let mut req = request();
+        assert!(req.count_prompt_tokens() > 1000);
+        let report = req.fit_context_window(None).await.unwrap();
+        println!("{:?}", report);
+        assert!(report.prompt_tokens <= 800);
+        assert!(report.dropped_messages > 0);
+        assert!(!report.summarized);
+        assert_eq!(31 - report.dropped_messages, req.context.len());
+        assert_eq!("system", req.context[0].role.as_str());
+        assert_ne!("tool", req.context[1].role.as_str());
+
+        //角色名不区分大小写
+        let mut req = request();
+        req.context[0].role = "System".into();
+        req.fit_context_window(None).await.unwrap();
+        assert_eq!("System", req.context[0].role.as_str());
+        assert_ne!("tool", req.context[1].role.as_str());
+
+        //固定的消息不会被丢弃
+        let mut req = request();
+        req.context[1].pinned = true;
+        let report = req.fit_context_window(None).await.unwrap();
+        assert!(report.dropped_messages > 0);
+        assert!(req.context[1].pinned);
+        assert!(req.context[1].content.starts_with("question 0"));
+
+        let mut req = request();
+        req.summarize_context = true;
+        let report = req.fit_context_window(Some(&JoinSummarizer)).await.unwrap();
+        assert!(report.summarized);
+        assert_eq!(
+            format!(
+                "{}{} messages",
+                CHAT_SUMMARY_PREFIX, report.dropped_messages
+            ),
+            req.context[1].content
+        );
+
+        //窗口足够时不做处理
+        let mut req = request();
+        req.context_window = 0;
+        let report = req.fit_context_window(None).await.unwrap();
+        assert_eq!(0, report.dropped_messages);
+        assert_eq!(128_000, report.window);
+    }
+}
@@ -0,0 +1,965 @@
+use crate::llm_provider::fnv1a;
+use crate::rt_node_service::{CfgBound, VectorDocument};
+use agent_rt::Context;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use wd_tools::PFErr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DocumentFormat {
+    Text,
+    Markdown,
+    Html,
+    Json,
+}
+
+impl DocumentFormat {
+    pub fn from_path(path: &str) -> Option<Self> {
+        let ext = Path::new(path).extension()?.to_str()?.to_lowercase();
+        match ext.as_str() {
+            "txt" | "text" => Some(DocumentFormat::Text),
+            "md" | "markdown" => Some(DocumentFormat::Markdown),
+            "html" | "htm" => Some(DocumentFormat::Html),
+            "json" => Some(DocumentFormat::Json),
+            _ => None,
+        }
+    }
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DocumentFormat::Text => "text",
+            DocumentFormat::Markdown => "markdown",
+            DocumentFormat::Html => "html",
+            DocumentFormat::Json => "json",
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChunkStrategy {
+    //按字符数切分
+    #[default]
+    Fixed,
+    //按句子聚合到chunk_size
+    Sentence,
+    //按标题分段，超长的段落再按句子切分
+    Heading,
+}
+
+//去掉标记后的一个段落，heading为所在的标题路径
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct DocumentSection {
+    pub heading: String,
+    pub text: String,
+}
+
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct InputDocument {
+    #[serde(alias = "content")]
+    pub text: String,
+    pub source: String,
+    pub format: Option<DocumentFormat>,
+    pub metadata: Map<String, Value>,
+}
+
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+pub struct DocumentLoaderRequest {
+    //文件或目录
+    #[serde(default = "String::default")]
+    pub path: String,
+    //目录下加载的文件后缀
+    #[serde(default = "DocumentLoaderRequest::default_extensions")]
+    pub extensions: Vec<String>,
+    #[serde(default = "DocumentLoaderRequest::default_recursive")]
+    pub recursive: bool,
+    //ctx中的文档，字符串、{text,source,format,metadata}或它们的数组
+    #[serde(default = "Value::default")]
+    pub documents: Value,
+    //无法从后缀判断格式时使用
+    #[serde(default = "Option::default")]
+    pub format: Option<DocumentFormat>,
+    #[serde(default = "ChunkStrategy::default")]
+    pub strategy: ChunkStrategy,
+    //按字符计算
+    #[serde(default = "DocumentLoaderRequest::default_chunk_size")]
+    pub chunk_size: usize,
+    #[serde(default = "DocumentLoaderRequest::default_overlap")]
+    pub overlap: usize,
+    //附加到每个chunk的metadata
+    #[serde(default = "Map::default")]
+    pub metadata: Map<String, Value>,
+}
+
+impl DocumentLoaderRequest {
+    pub fn default_extensions() -> Vec<String> {
+        ["txt", "md", "markdown", "html", "htm", "json"]
+            .into_iter()
+            .map(String::from)
+            .collect()
+    }
+    pub fn default_recursive() -> bool {
+        true
+    }
+    pub fn default_chunk_size() -> usize {
+        800
+    }
+    pub fn default_overlap() -> usize {
+        100
+    }
+    pub fn input_documents(&self) -> anyhow::Result<Vec<InputDocument>> {
+        let list = match self.documents {
+            Value::Null => return Ok(vec![]),
+            Value::Array(ref list) => list.clone(),
+            ref v => vec![v.clone()],
+        };
+        let mut docs = vec![];
+        for (i, v) in list.into_iter().enumerate() {
+            let mut doc = match v {
+                Value::String(text) => InputDocument {
+                    text,
+                    ..Default::default()
+                },
+                v => serde_json::from_value::<InputDocument>(v)?,
+            };
+            if doc.source.is_empty() {
+                doc.source = format!("documents[{}]", i);
+            }
+            docs.push(doc);
+        }
+        Ok(docs)
+    }
+}
+
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+pub struct DocumentLoaderResponse {
+    //加载的文档数
+    pub documents: usize,
+    pub count: usize,
+    //可直接作为vector_upsert的docs
+    pub chunks: Vec<VectorDocument>,
+}
+
+fn decode_entities(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(i) = rest.find('&') {
+        out.push_str(&rest[..i]);
+        rest = &rest[i..];
+        let end = match rest.find(';') {
+            Some(e) if e <= 10 => e,
+            _ => {
+                out.push('&');
+                rest = &rest[1..];
+                continue;
+            }
+        };
+        let name = &rest[1..end];
+        let c = match name {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            "nbsp" => Some(' '),
+            _ => {
+                let code = if let Some(hex) = name.strip_prefix("#x").or(name.strip_prefix("#X")) {
+                    u32::from_str_radix(hex, 16).ok()
+                } else {
+                    name.strip_prefix('#').and_then(|x| x.parse::<u32>().ok())
+                };
+                code.and_then(char::from_u32)
+            }
+        };
+        match c {
+            Some(c) => {
+                out.push(c);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+//合并空白，去掉空行
+fn normalize_text(text: &str) -> String {
+    text.lines()
+        .map(|x| x.split_whitespace().collect::<Vec<_>>().join(" "))
+        .filter(|x| !x.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+//维护标题栈，返回 "一级 > 二级" 形式的路径
+fn push_heading(stack: &mut Vec<(usize, String)>, level: usize, title: String) -> String {
+    while stack.last().map(|x| x.0 >= level).unwrap_or(false) {
+        stack.pop();
+    }
+    stack.push((level, title));
+    stack
+        .iter()
+        .map(|x| x.1.as_str())
+        .collect::<Vec<_>>()
+        .join(" > ")
+}
+
+fn push_section(list: &mut Vec<DocumentSection>, heading: &str, text: &str) {
+    let text = normalize_text(text);
+    if !text.is_empty() {
+        list.push(DocumentSection {
+            heading: heading.to_string(),
+            text,
+        });
+    }
+}
+
+//去掉链接、图片、强调和行内代码标记
+fn strip_markdown_inline(line: &str) -> String {
+    let chars = line.chars().collect::<Vec<_>>();
+    let mut out = String::with_capacity(line.len());
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let next = chars.get(i + 1).copied();
+        if c == '!' && next == Some('[') {
+            i += 1;
+            continue;
+        }
+        if c == '[' {
+            let close = chars[i..].iter().position(|x| *x == ']').map(|x| x + i);
+            if let Some(close) = close {
+                if chars.get(close + 1) == Some(&'(') {
+                    if let Some(end) = chars[close..].iter().position(|x| *x == ')') {
+                        out.extend(&chars[i + 1..close]);
+                        i = close + end + 1;
+                        continue;
+                    }
+                }
+            }
+        }
+        if c == '*' || c == '`' {
+            i += 1;
+            continue;
+        }
+        if c == '_' && next == Some('_') {
+            i += 2;
+            continue;
+        }
+        out.push(c);
+        i += 1;
+    }
+    out
+}
+
+fn markdown_sections(text: &str) -> Vec<DocumentSection> {
+    let mut list = vec![];
+    let mut stack = vec![];
+    let mut heading = String::new();
+    let mut buf = String::new();
+    let mut fence = false;
+    for line in text.lines() {
+        let t = line.trim_start();
+        if t.starts_with("```") || t.starts_with("~~~") {
+            fence = !fence;
+            continue;
+        }
+        if fence {
+            buf.push_str(line);
+            buf.push('\n');
+            continue;
+        }
+        let level = t.chars().take_while(|x| *x == '#').count();
+        if (1..=6).contains(&level) && (t.len() == level || t[level..].starts_with(' ')) {
+            push_section(&mut list, heading.as_str(), buf.as_str());
+            buf.clear();
+            let title = strip_markdown_inline(t[level..].trim().trim_end_matches('#').trim());
+            heading = push_heading(&mut stack, level, title.clone());
+            buf.push_str(title.as_str());
+            buf.push('\n');
+            continue;
+        }
+        let t = t.trim_start_matches('>');
+        buf.push_str(strip_markdown_inline(t).as_str());
+        buf.push('\n');
+    }
+    push_section(&mut list, heading.as_str(), buf.as_str());
+    list
+}
+
+const HTML_BLOCK_TAGS: &[&str] = &[
+    "p",
+    "br",
+    "div",
+    "li",
+    "tr",
+    "td",
+    "th",
+    "ul",
+    "ol",
+    "table",
+    "section",
+    "article",
+    "header",
+    "footer",
+    "blockquote",
+    "pre",
+    "hr",
+    "title",
+];
+
+fn html_sections(html: &str) -> Vec<DocumentSection> {
+    //只转换ascii大小写，字节位置与原文一致
+    let lower = html.to_ascii_lowercase();
+    let mut list = vec![];
+    let mut stack = vec![];
+    let mut heading = String::new();
+    let mut buf = String::new();
+    //正在读取的标题级别和文本
+    let mut title: Option<(usize, String)> = None;
+    let mut i = 0;
+    while i < html.len() {
+        let rest = &html[i..];
+        if rest.starts_with("<!--") {
+            i = match rest.find("-->") {
+                Some(e) => i + e + 3,
+                None => html.len(),
+            };
+            continue;
+        }
+        if rest.starts_with('<') {
+            let Some(e) = rest.find('>') else {
+                buf.push_str(decode_entities(rest).as_str());
+                break;
+            };
+            let start = i;
+            let end = i + e + 1;
+            let tag = &lower[i + 1..end - 1];
+            let closing = tag.starts_with('/');
+            let name = tag
+                .trim_start_matches('/')
+                .chars()
+                .take_while(|x| x.is_ascii_alphanumeric())
+                .collect::<String>();
+            i = end;
+            if !closing && ["script", "style", "head", "noscript"].contains(&name.as_str()) {
+                i = match lower[end..].find(format!("</{}", name).as_str()) {
+                    Some(s) => match lower[end + s..].find('>') {
+                        Some(x) => end + s + x + 1,
+                        None => html.len(),
+                    },
+                    None => html.len(),
+                };
+                continue;
+            }
+            let level = match name.as_bytes() {
+                [b'h', n @ b'1'..=b'6'] => (n - b'0') as usize,
+                _ => 0,
+            };
+            if level > 0 {
+                if !closing {
+                    push_section(&mut list, heading.as_str(), buf.as_str());
+                    buf.clear();
+                    title = Some((level, String::new()));
+                } else if let Some((level, text)) = title.take() {
+                    let text = normalize_text(text.as_str()).replace('\n', " ");
+                    heading = push_heading(&mut stack, level, text.clone());
+                    buf.push_str(text.as_str());
+                    buf.push('\n');
+                }
+                continue;
+            }
+            if HTML_BLOCK_TAGS.contains(&name.as_str()) {
+                buf.push('\n');
+            } else if name == "img" {
+                //保留图片的alt文本
+                if let Some(s) = tag.find("alt=\"") {
+                    let alt = start + 1 + s + 5;
+                    if let Some(l) = html[alt..end].find('"') {
+                        buf.push_str(decode_entities(&html[alt..alt + l]).as_str());
+                    }
+                }
+            }
+            continue;
+        }
+        let next = rest.find('<').map(|x| i + x).unwrap_or(html.len());
+        let text = decode_entities(&html[i..next]);
+        match title {
+            Some((_, ref mut t)) => t.push_str(text.as_str()),
+            None => buf.push_str(text.as_str()),
+        }
+        i = next;
+    }
+    push_section(&mut list, heading.as_str(), buf.as_str());
+    list
+}
+
+fn flatten_json(prefix: &str, value: &Value, out: &mut Vec<String>) {
+    match value {
+        Value::Object(map) => {
+            for (k, v) in map.iter() {
+                let key = if prefix.is_empty() {
+                    k.clone()
+                } else {
+                    format!("{}.{}", prefix, k)
+                };
+                flatten_json(key.as_str(), v, out);
+            }
+        }
+        Value::Array(list) => {
+            for (i, v) in list.iter().enumerate() {
+                flatten_json(format!("{}[{}]", prefix, i).as_str(), v, out);
+            }
+        }
+        Value::Null => {}
+        Value::String(s) if prefix.is_empty() => out.push(s.clone()),
+        Value::String(s) => out.push(format!("{}: {}", prefix, s)),
+        v => out.push(format!("{}: {}", prefix, v)),
+    }
+}
+
+//去掉标记，按标题拆成段落；text和json只有一个段落
+pub fn parse_document(text: &str, format: DocumentFormat) -> anyhow::Result<Vec<DocumentSection>> {
+    let list = match format {
+        DocumentFormat::Markdown => markdown_sections(text),
+        DocumentFormat::Html => html_sections(text),
+        DocumentFormat::Json => {
+            let value = serde_json::from_str::<Value>(text)?;
+            let mut lines = vec![];
+            flatten_json("", &value, &mut lines);
+            let mut list = vec![];
+            push_section(&mut list, "", lines.join("\n").as_str());
+            list
+        }
+        DocumentFormat::Text => {
+            let mut list = vec![];
+            push_section(&mut list, "", text);
+            list
+        }
+    };
+    Ok(list)
+}
+
+//按句末标点或换行切分，每句保留其后的空白，拼接后与原文一致
+pub fn split_sentences(text: &str) -> Vec<String> {
+    let chars = text.chars().collect::<Vec<_>>();
+    let mut list = vec![];
+    let mut cur = String::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        cur.push(c);
+        i += 1;
+        let end = match c {
+            '。' | '！' | '？' | '；' | '!' | '?' | ';' | '\n' => true,
+            '.' => chars.get(i).map(|x| x.is_whitespace()).unwrap_or(true),
+            _ => false,
+        };
+        if end {
+            while i < chars.len() && chars[i].is_whitespace() {
+                cur.push(chars[i]);
+                i += 1;
+            }
+            list.push(std::mem::take(&mut cur));
+        }
+    }
+    if !cur.is_empty() {
+        list.push(cur);
+    }
+    list
+}
+
+fn split_fixed(text: &str, size: usize, overlap: usize) -> Vec<String> {
+    let chars = text.chars().collect::<Vec<_>>();
+    let mut list = vec![];
+    let mut start = 0;
+    while start < chars.len() {
+        let end = (start + size).min(chars.len());
+        list.push(chars[start..end].iter().collect::<String>());
+        if end == chars.len() {
+            break;
+        }
+        start += size - overlap;
+    }
+    list
+}
+
+//把句子聚合成不超过size的chunk，新chunk带上前一个chunk末尾不超过overlap的句子
+fn pack_sentences(sentences: Vec<String>, size: usize, overlap: usize) -> Vec<String> {
+    let mut list = vec![];
+    let mut cur: Vec<(String, usize)> = vec![];
+    let mut len = 0;
+    //cur中是否有未输出过的句子
+    let mut fresh = false;
+    for s in sentences {
+        let n = s.chars().count();
+        if n > size {
+            if fresh {
+                list.push(cur.iter().map(|x| x.0.as_str()).collect::<String>());
+            }
+            list.extend(split_fixed(s.as_str(), size, overlap));
+            cur.clear();
+            len = 0;
+            fresh = false;
+            continue;
+        }
+        if len + n > size && fresh {
+            list.push(cur.iter().map(|x| x.0.as_str()).collect::<String>());
+            let mut keep = 0;
+            let mut keep_len = 0;
+            for (_, m) in cur.iter().rev() {
+                if keep_len + m > overlap || keep_len + m + n > size {
+                    break;
+                }
+                keep += 1;
+                keep_len += m;
+            }
+            cur.drain(..cur.len() - keep);
+            len = keep_len;
+        }
+        while len + n > size {
+            len -= cur.remove(0).1;
+        }
+        cur.push((s, n));
+        len += n;
+        fresh = true;
+    }
+    if fresh {
+        list.push(cur.iter().map(|x| x.0.as_str()).collect::<String>());
+    }
+    list
+}
+
+//返回 (标题路径, chunk文本)
+pub fn chunk_sections(
+    sections: &[DocumentSection],
+    strategy: ChunkStrategy,
+    size: usize,
+    overlap: usize,
+) -> anyhow::Result<Vec<(String, String)>> {
+    if size == 0 || overlap >= size {
+        return anyhow::anyhow!(
+            "chunk overlap[{}] must less than chunk_size[{}]",
+            overlap,
+            size
+        )
+        .err();
+    }
+    let join = || {
+        sections
+            .iter()
+            .map(|x| x.text.as_str())
+            .collect::<Vec<_>>()
+            .join("\n\n")
+    };
+    let list = match strategy {
+        ChunkStrategy::Fixed => split_fixed(join().as_str(), size, overlap)
+            .into_iter()
+            .map(|x| (String::new(), x))
+            .collect(),
+        ChunkStrategy::Sentence => pack_sentences(split_sentences(join().as_str()), size, overlap)
+            .into_iter()
+            .map(|x| (String::new(), x))
+            .collect(),
+        ChunkStrategy::Heading => {
+            let mut list = vec![];
+            for i in sections.iter() {
+                for chunk in pack_sentences(split_sentences(i.text.as_str()), size, overlap) {
+                    list.push((i.heading.clone(), chunk));
+                }
+            }
+            list
+        }
+    };
+    Ok(list
+        .into_iter()
+        .map(|(h, x)| (h, x.trim().to_string()))
+        .filter(|x| !x.1.is_empty())
+        .collect())
+}
+
+//解析符号链接后必须位于允许的目录下
+async fn check_path(path: &Path, roots: &[PathBuf]) -> anyhow::Result<PathBuf> {
+    let real = tokio::fs::canonicalize(path)
+        .await
+        .map_err(|e| anyhow::anyhow!("document path[{}] error:{}", path.display(), e))?;
+    if !roots.iter().any(|x| real.starts_with(x)) {
+        return anyhow::anyhow!("document path[{}] is not allowed", path.display()).err();
+    }
+    Ok(real)
+}
+
+//读取文件或目录下指定后缀的文件，按路径排序
+async fn read_documents(
+    path: &str,
+    roots: &[PathBuf],
+    extensions: &[String],
+    recursive: bool,
+) -> anyhow::Result<Vec<InputDocument>> {
+    check_path(Path::new(path), roots).await?;
+    let mut files = vec![];
+    if tokio::fs::metadata(path).await?.is_file() {
+        files.push(PathBuf::from(path));
+    } else {
+        let mut dirs = vec![PathBuf::from(path)];
+        while let Some(dir) = dirs.pop() {
+            let mut entries = tokio::fs::read_dir(dir).await?;
+            while let Some(entry) = entries.next_entry().await? {
+                let file = entry.path();
+                if entry.file_type().await?.is_dir() {
+                    if recursive {
+                        dirs.push(file);
+                    }
+                    continue;
+                }
+                let ext = file
+                    .extension()
+                    .and_then(|x| x.to_str())
+                    .unwrap_or_default()
+                    .to_lowercase();
+                if extensions.iter().any(|x| x.trim_start_matches('.') == ext) {
+                    files.push(file);
+                }
+            }
+        }
+        files.sort();
+    }
+    let mut docs = vec![];
+    for file in files {
+        let source = file.to_string_lossy().to_string();
+        let real = check_path(&file, roots).await?;
+        let text = match tokio::fs::read_to_string(real).await {
+            Ok(s) => s,
+            Err(e) => return anyhow::anyhow!("read document[{}] error:{}", source, e).err(),
+        };
+        docs.push(InputDocument {
+            text,
+            source,
+            ..Default::default()
+        });
+    }
+    Ok(docs)
+}
+
+//从目录或ctx加载文档，去掉标记后切分成带来源信息的chunk
+#[derive(Debug, Default)]
+pub struct DocumentLoaderService {
+    //path只能读取这些目录下的文件，为空时不允许按path加载
+    roots: Vec<PathBuf>,
+}
+
+impl DocumentLoaderService {
+    pub fn root<P: Into<PathBuf>>(mut self, dir: P) -> Self {
+        self.roots.push(dir.into());
+        self
+    }
+}
+
+#[async_trait::async_trait]
+impl agent_rt::ServiceLayer for DocumentLoaderService {
+    type Config = CfgBound<DocumentLoaderRequest>;
+    type Output = DocumentLoaderResponse;
+
+    async fn call(
+        &self,
+        _code: String,
+        ctx: Arc<Context>,
+        cfg: Self::Config,
+    ) -> anyhow::Result<Self::Output> {
+        let cfg = cfg.bound(&ctx)?;
+        let mut docs = vec![];
+        if !cfg.path.is_empty() {
+            let mut roots = vec![];
+            for dir in self.roots.iter() {
+                if let Ok(dir) = tokio::fs::canonicalize(dir).await {
+                    roots.push(dir);
+                }
+            }
+            if roots.is_empty() {
+                return anyhow::anyhow!("document loader has no allowed root for path").err();
+            }
+            docs =
+                read_documents(cfg.path.as_str(), &roots, &cfg.extensions, cfg.recursive).await?;
+        }
+        docs.extend(cfg.input_documents()?);
+        if docs.is_empty() {
+            return anyhow::anyhow!("document loader need path or documents").err();
+        }
+
+        let mut resp = DocumentLoaderResponse {
+            documents: docs.len(),
+            ..Default::default()
+        };
+        for doc in docs {
+            let format = doc
+                .format
+                .or(DocumentFormat::from_path(doc.source.as_str()))
+                .or(cfg.format)
+                .unwrap_or(DocumentFormat::Text);
+            let sections = match parse_document(doc.text.as_str(), format) {
+                Ok(s) => s,
+                Err(e) => {
+                    return anyhow::anyhow!("parse document[{}] error:{}", doc.source, e).err()
+                }
+            };
+            let chunks = chunk_sections(&sections, cfg.strategy, cfg.chunk_size, cfg.overlap)?;
+            let total = chunks.len();
+            for (index, (heading, text)) in chunks.into_iter().enumerate() {
+                let mut metadata = cfg.metadata.clone();
+                metadata.extend(doc.metadata.clone());
+                metadata.insert("source".into(), Value::from(doc.source.as_str()));
+                metadata.insert("format".into(), Value::from(format.as_str()));
+                metadata.insert("chunk".into(), Value::from(index));
+                metadata.insert("chunks".into(), Value::from(total));
+                if !heading.is_empty() {
+                    metadata.insert("heading".into(), Value::from(heading));
+                }
+                //id包含内容，没有source的文档也不会冲突；内容变化后用vector_upsert的replace清理旧chunk
+                let id = format!(
+                    "{:016x}",
+                    fnv1a(format!("{}#{}#{}", doc.source, index, text).as_str())
+                );
+                resp.chunks.push(VectorDocument {
+                    id,
+                    text,
+                    metadata,
+                    vector: vec![],
+                });
+            }
+        }
+        resp.count = resp.chunks.len();
+        Ok(resp)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::llm_provider::HashEmbeddingProvider;
+    use crate::rt_node_service::{
+        chunk_sections, parse_document, split_sentences, ChunkStrategy, DocumentFormat,
+        DocumentLoaderResponse, DocumentLoaderService, DocumentSection, EmbeddingService,
+        LocalVectorStore, VectorUpsertResponse, VectorUpsertService,
+    };
+    use agent_rt::{PlanBuilder, Runtime};
+    use serde_json::Value;
+    use std::sync::Arc;
+    use wd_tools::PFArc;
+
+    //cargo test rt_node_service::document::test::test_document_chunk -- --nocapture
+    #[test]
+    fn test_document_chunk() {
+        let md = "# Guide\nintro **bold** and [link](http://a.com)\n\n## Install\nrun `cargo build`\n```\nfn main() {}\n```\n# FAQ\n![logo](a.png) done";
+        let sections = parse_document(md, DocumentFormat::Markdown).unwrap();
+        assert_eq!(3, sections.len());
+        assert_eq!("Guide", sections[0].heading);
+        assert_eq!("Guide\nintro bold and link", sections[0].text);
+        assert_eq!("Guide > Install", sections[1].heading);
+        assert_eq!("Install\nrun cargo build\nfn main() {}", sections[1].text);
+        assert_eq!("FAQ", sections[2].heading);
+        assert_eq!("FAQ\nlogo done", sections[2].text);
+
+        let html = r#"<html><head><title>x</title><style>p{}</style></head><body>
+            <h1>Title &amp; more</h1><p>first<br>second</p><script>var a = "<p>";</script>
+            <!-- comment --><h2>Sub</h2><div>a &lt; b&#33; <img alt="pic" src="a.png"></div></body></html>"#;
+        let sections = parse_document(html, DocumentFormat::Html).unwrap();
+        assert_eq!(
+            vec![
+                DocumentSection {
+                    heading: "Title & more".into(),
+                    text: "Title & more\nfirst\nsecond".into()
+                },
+                DocumentSection {
+                    heading: "Title & more > Sub".into(),
+                    text: "Sub\na < b! pic".into()
+                },
+            ],
+            sections
+        );
+
+        let json = r#"{"name":"wd","tags":["a","b"],"meta":{"n":1,"x":null}}"#;
+        let sections = parse_document(json, DocumentFormat::Json).unwrap();
+        assert_eq!(
+            "meta.n: 1\nname: wd\ntags[0]: a\ntags[1]: b",
+            sections[0].text
+        );
+        assert!(parse_document("{", DocumentFormat::Json).is_err());
+
+        let text = "你好。How are you? I am fine.\nok";
+        let list = split_sentences(text);
+        assert_eq!(vec!["你好。", "How are you? ", "I am fine.\n", "ok"], list);
+        assert_eq!(text, list.concat());
+
+        let sections = parse_document("abcdefghij", DocumentFormat::Text).unwrap();
+        let chunks = chunk_sections(&sections, ChunkStrategy::Fixed, 4, 1).unwrap();
+        let chunks = chunks.into_iter().map(|x| x.1).collect::<Vec<_>>();
+        assert_eq!(vec!["abcd", "defg", "ghij"], chunks);
+        assert!(chunk_sections(&sections, ChunkStrategy::Fixed, 4, 4).is_err());
+
+        let sections = parse_document("aa. bb. cc. dd.", DocumentFormat::Text).unwrap();
+        let chunks = chunk_sections(&sections, ChunkStrategy::Sentence, 8, 4).unwrap();
+        let chunks = chunks.into_iter().map(|x| x.1).collect::<Vec<_>>();
+        assert_eq!(vec!["aa. bb.", "bb. cc.", "cc. dd."], chunks);
+    }
+
+    //cargo test rt_node_service::document::test::test_document_loader -- --nocapture
+    #[tokio::test]
+    async fn test_document_loader() {
+        let path = std::env::temp_dir().join("wd_agent_document_test");
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(path.join("sub")).unwrap();
+        std::fs::write(path.join("a.md"), "# A\nalpha text\n# B\nbeta text").unwrap();
+        std::fs::write(path.join("sub/b.html"), "<h1>C</h1><p>gamma</p>").unwrap();
+        std::fs::write(path.join("c.bin"), "skip").unwrap();
+
+        let store = Arc::new(LocalVectorStore::default());
+        let embedding = EmbeddingService::new()
+            .register_provider("hash", HashEmbeddingProvider::default())
+            .default_provider("hash");
+        let rt = Runtime::default()
+            .register_service_layer(
+                "document_loader",
+                DocumentLoaderService::default().root(&path),
+            )
+            .register_service_layer("vector_upsert", VectorUpsertService::new(store, embedding))
+            .launch();
+
+        let cfg = serde_json::json!({
+            "path": path.to_string_lossy(),
+            "documents": "{{start.docs}}",
+            "strategy": "heading",
+            "metadata": {"kb":"test"}
+        })
+        .to_string();
+        let res = rt
+            .ctx(
+                "test_document_loader",
+                PlanBuilder::single_node("document_loader", cfg.as_str()).build(),
+            )
+            .arc()
+            .block_on::<Value, _>(serde_json::json!({
+                "docs":["plain text", {"content":"<p>x</p>","format":"html","metadata":{"kb":"ctx"}}]
+            }))
+            .await
+            .unwrap();
+        let resp = serde_json::from_value::<DocumentLoaderResponse>(res.clone()).unwrap();
+        assert_eq!(4, resp.documents);
+        assert_eq!(5, resp.count);
+        let texts = resp
+            .chunks
+            .iter()
+            .map(|x| x.text.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                "A\nalpha text",
+                "B\nbeta text",
+                "C\ngamma",
+                "plain text",
+                "x"
+            ],
+            texts
+        );
+        let meta = &resp.chunks[1].metadata;
+        assert_eq!("B", meta["heading"]);
+        assert_eq!("markdown", meta["format"]);
+        assert_eq!(1, meta["chunk"]);
+        assert_eq!("test", meta["kb"]);
+        assert!(meta["source"].as_str().unwrap().ends_with("a.md"));
+        assert_eq!("documents[0]", resp.chunks[3].metadata["source"]);
+        assert_eq!("ctx", resp.chunks[4].metadata["kb"]);
+        let id = resp.chunks[3].id.clone();
+
+        //没有source的文档内容不同时id不同
+        let res = rt
+            .ctx(
+                "test_document_loader_ctx",
+                PlanBuilder::single_node("document_loader", r#"{"documents":"{{start.docs}}"}"#)
+                    .build(),
+            )
+            .arc()
+            .block_on::<Value, _>(serde_json::json!({"docs":["other text"]}))
+            .await
+            .unwrap();
+        let resp = serde_json::from_value::<DocumentLoaderResponse>(res).unwrap();
+        assert_eq!("documents[0]", resp.chunks[0].metadata["source"]);
+        assert_ne!(id, resp.chunks[0].id);
+
+        //只能读取允许的目录
+        let outside = std::env::temp_dir().join("wd_agent_document_outside.md");
+        std::fs::write(&outside, "secret").unwrap();
+        let mut paths = vec![outside.clone(), path.join("../")];
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(&outside, path.join("sub/link.md")).unwrap();
+            paths.push(path.join("sub/link.md"));
+        }
+        for p in paths {
+            let cfg = serde_json::json!({ "path": p.to_string_lossy() }).to_string();
+            let err = rt
+                .ctx(
+                    "test_document_loader_root",
+                    PlanBuilder::single_node("document_loader", cfg.as_str()).build(),
+                )
+                .arc()
+                .block_on::<Value, _>(serde_json::json!({}))
+                .await
+                .unwrap_err();
+            assert!(err.to_string().contains("is not allowed"));
+        }
+        let _ = std::fs::remove_file(path.join("sub/link.md"));
+        let _ = std::fs::remove_file(&outside);
+
+        //chunk可以直接写入向量库
+        let plan = PlanBuilder::start(("loader", "document_loader", cfg.as_str()), vec!["end"])
+            .sequence(
+                vec![(
+                    "end",
+                    "vector_upsert",
+                    r#"{"collection":"kb","docs":"{{loader.chunks}}"}"#,
+                )],
+                "",
+            )
+            .check_and_build()
+            .unwrap();
+        let res = rt
+            .ctx("test_document_upsert", plan)
+            .arc()
+            .block_on::<Value, _>(serde_json::json!({"docs":[]}))
+            .await
+            .unwrap();
+        let resp = serde_json::from_value::<VectorUpsertResponse>(res).unwrap();
+        assert_eq!(3, resp.count);
+        assert_eq!(0, resp.removed);
+
+        //文档变化后重新导入，replace清理同一来源的旧chunk
+        std::fs::write(path.join("a.md"), "# A\nalpha text changed").unwrap();
+        let plan = PlanBuilder::start(("loader", "document_loader", cfg.as_str()), vec!["end"])
+            .sequence(
+                vec![(
+                    "end",
+                    "vector_upsert",
+                    r#"{"collection":"kb","docs":"{{loader.chunks}}","replace":true}"#,
+                )],
+                "",
+            )
+            .check_and_build()
+            .unwrap();
+        let res = rt
+            .ctx("test_document_replace", plan)
+            .arc()
+            .block_on::<Value, _>(serde_json::json!({"docs":[]}))
+            .await
+            .unwrap();
+        let resp = serde_json::from_value::<VectorUpsertResponse>(res).unwrap();
+        assert_eq!(2, resp.count);
+        assert_eq!(3, resp.removed);
+        let _ = std::fs::remove_dir_all(&path);
+    }
+}
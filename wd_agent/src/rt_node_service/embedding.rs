@@ -0,0 +1,215 @@
+use crate::llm_provider::{
+    ChatUsage, EmbeddingProvider, EmbeddingResponse, HashEmbeddingProvider, ModelPricing,
+    OllamaProvider, OpenAICompatibleProvider,
+};
+use crate::rt_node_service::CfgBound;
+use agent_rt::Context;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::Arc;
+use wd_tools::PFErr;
+
+//按名称选择embedding厂商的节点，也供向量库节点生成向量
+#[derive(Clone)]
+pub struct EmbeddingService {
+    providers: HashMap<String, Arc<dyn EmbeddingProvider>>,
+    default_provider: String,
+    pricing: ModelPricing,
+}
+
+impl Default for EmbeddingService {
+    fn default() -> Self {
+        Self::new()
+            .register_provider("openai", OpenAICompatibleProvider::default())
+            .register_provider("ollama", OllamaProvider::default())
+            .register_provider("hash", HashEmbeddingProvider::default())
+    }
+}
+
+impl EmbeddingService {
+    pub fn new() -> Self {
+        Self {
+            providers: HashMap::new(),
+            default_provider: "openai".into(),
+            pricing: ModelPricing::default(),
+        }
+    }
+    pub fn register_provider<S: Into<String>, P: EmbeddingProvider + 'static>(
+        mut self,
+        name: S,
+        provider: P,
+    ) -> Self {
+        self.providers.insert(name.into(), Arc::new(provider));
+        self
+    }
+    pub fn default_provider<S: Into<String>>(mut self, name: S) -> Self {
+        self.default_provider = name.into();
+        self
+    }
+    pub fn pricing(mut self, pricing: ModelPricing) -> Self {
+        self.pricing = pricing;
+        self
+    }
+    pub fn get_provider(&self, name: &str) -> anyhow::Result<Arc<dyn EmbeddingProvider>> {
+        let name = if name.is_empty() {
+            self.default_provider.as_str()
+        } else {
+            name
+        };
+        match self.providers.get(name) {
+            Some(s) => Ok(s.clone()),
+            None => anyhow::anyhow!("embedding provider[{}] not found", name).err(),
+        }
+    }
+    //生成向量并把用量计入ctx的预算
+    pub async fn embed(
+        &self,
+        ctx: &Context,
+        provider: &str,
+        model: &str,
+        input: Vec<String>,
+    ) -> anyhow::Result<EmbeddingResponse> {
+        if input.is_empty() {
+            return Ok(EmbeddingResponse::default());
+        }
+        let provider = self.get_provider(provider)?;
+        let model = if model.is_empty() {
+            provider.default_embedding_model()
+        } else {
+            model
+        };
+        let count = input.len();
+        let resp = provider.embed(model, input).await?;
+        if resp.vectors.len() != count {
+            return anyhow::anyhow!(
+                "embedding returned {} vectors for {} inputs",
+                resp.vectors.len(),
+                count
+            )
+            .err();
+        }
+        if let Some(ref usage) = resp.usage {
+            ctx.report_usage(self.pricing.usage(model, usage))?;
+        }
+        Ok(resp)
+    }
+}
+
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+pub struct EmbeddingNodeRequest {
+    //为空使用默认厂商
+    #[serde(default = "String::default")]
+    pub provider: String,
+    //为空使用厂商的默认模型
+    #[serde(default = "String::default")]
+    pub model: String,
+    //字符串或字符串数组
+    pub input: Value,
+}
+
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct EmbeddingNodeResponse {
+    pub model: String,
+    pub vectors: Vec<Vec<f32>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub usage: Option<ChatUsage>,
+}
+
+impl EmbeddingNodeRequest {
+    pub fn input_list(&self) -> anyhow::Result<Vec<String>> {
+        let text = |v: &Value| match v {
+            Value::String(s) => Ok(s.clone()),
+            Value::Null => anyhow::anyhow!("embedding input is null").err(),
+            v => Ok(v.to_string()),
+        };
+        match self.input {
+            Value::Array(ref list) => list.iter().map(text).collect(),
+            ref v => Ok(vec![text(v)?]),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl agent_rt::ServiceLayer for EmbeddingService {
+    type Config = CfgBound<EmbeddingNodeRequest>;
+    type Output = EmbeddingNodeResponse;
+
+    async fn call(
+        &self,
+        _code: String,
+        ctx: Arc<Context>,
+        cfg: Self::Config,
+    ) -> anyhow::Result<Self::Output> {
+        let cfg = cfg.bound(&ctx)?;
+        let input = cfg.input_list()?;
+        let resp = self
+            .embed(&ctx, cfg.provider.as_str(), cfg.model.as_str(), input)
+            .await?;
+        Ok(EmbeddingNodeResponse {
+            model: resp.model,
+            vectors: resp.vectors,
+            usage: resp.usage,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::llm_provider::HashEmbeddingProvider;
+    use crate::rt_node_service::{EmbeddingNodeResponse, EmbeddingService};
+    use agent_rt::{PlanBuilder, Runtime};
+    use serde_json::Value;
+    use wd_tools::PFArc;
+
+    //cargo test rt_node_service::embedding::test::test_embedding_node -- --nocapture
+    #[tokio::test]
+    async fn test_embedding_node() {
+        let embedding = EmbeddingService::new()
+            .register_provider("hash", HashEmbeddingProvider::new(8))
+            .default_provider("hash");
+        let rt = Runtime::default()
+            .register_service_layer("embedding", embedding)
+            .launch();
+
+        let ctx = rt
+            .ctx(
+                "test_embedding",
+                PlanBuilder::single_node("embedding", r#"{"input":"{{start.texts}}"}"#).build(),
+            )
+            .arc();
+        let res = ctx
+            .clone()
+            .block_on::<Value, _>(serde_json::json!({"texts":["hello world","good morning"]}))
+            .await
+            .unwrap();
+        let resp = serde_json::from_value::<EmbeddingNodeResponse>(res).unwrap();
+        assert_eq!("hash", resp.model.as_str());
+        assert_eq!(2, resp.vectors.len());
+        assert_eq!(8, resp.vectors[0].len());
+        assert_eq!(4, ctx.used_budget().tokens);
+
+        let res = rt
+            .ctx(
+                "test_embedding_one",
+                PlanBuilder::single_node("embedding", r#"{"input":"hello"}"#).build(),
+            )
+            .arc()
+            .block_on::<Value, _>(())
+            .await
+            .unwrap();
+        let resp = serde_json::from_value::<EmbeddingNodeResponse>(res).unwrap();
+        assert_eq!(1, resp.vectors.len());
+
+        let res = rt
+            .ctx(
+                "test_embedding_err",
+                PlanBuilder::single_node("embedding", r#"{"provider":"x","input":"hi"}"#).build(),
+            )
+            .arc()
+            .block_on::<Value, _>(())
+            .await;
+        assert!(format!("{:?}", res).contains("embedding provider[x] not found"));
+    }
+}
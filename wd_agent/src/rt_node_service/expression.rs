@@ -0,0 +1,457 @@
+use crate::rt_node_service::template::{compare, truthy};
+use crate::rt_node_service::{parse_path, PathSegment};
+use serde_json::Value;
+use std::str::FromStr;
+use wd_tools::PFErr;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Literal(Value),
+    //变量路径、函数名或关键字
+    Ident(String),
+    Op(String),
+    LParen,
+    RParen,
+    LBracket,
+    RBracket,
+    Comma,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Node {
+    Literal(Value),
+    Path(String),
+    List(Vec<Node>),
+    Not(Box<Node>),
+    And(Box<Node>, Box<Node>),
+    Or(Box<Node>, Box<Node>),
+    Compare(Box<Node>, String, Box<Node>),
+    Call(String, Vec<Node>),
+}
+
+//分支条件使用的布尔表达式：
+//  start.intent == 'refund' and (length(start.items) > 0 or not start.vip)
+//  contains(start.query, '退款') || startsWith(start.query, 'refund') || matches(start.query, '^\d+$')
+//  start.level in ['a', 'b'] && type(start.tags) == 'array' && !empty(start.tags)
+//变量直接写路径，支持 a[0]、a[-1]、a[*].b、a["k.with.dot"]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Expression {
+    root: Node,
+}
+
+fn tokenize(s: &str) -> anyhow::Result<Vec<Token>> {
+    let chars = s.chars().collect::<Vec<_>>();
+    let mut list = vec![];
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let next = chars.get(i + 1).copied();
+        if c.is_whitespace() {
+            i += 1;
+            continue;
+        }
+        match c {
+            '(' => list.push(Token::LParen),
+            ')' => list.push(Token::RParen),
+            '[' => list.push(Token::LBracket),
+            ']' => list.push(Token::RBracket),
+            ',' => list.push(Token::Comma),
+            '"' | '\'' => {
+                let mut text = String::new();
+                let mut end = None;
+                let mut j = i + 1;
+                while j < chars.len() {
+                    match chars[j] {
+                        '\\' if j + 1 < chars.len() => {
+                            text.push(match chars[j + 1] {
+                                'n' => '\n',
+                                't' => '\t',
+                                c => c,
+                            });
+                            j += 2;
+                            continue;
+                        }
+                        x if x == c => {
+                            end = Some(j);
+                            break;
+                        }
+                        x => text.push(x),
+                    }
+                    j += 1;
+                }
+                let Some(end) = end else {
+                    return anyhow::anyhow!("expression[{}] unclosed string", s).err();
+                };
+                list.push(Token::Literal(Value::String(text)));
+                i = end + 1;
+                continue;
+            }
+            '=' | '!' | '>' | '<' if next == Some('=') => {
+                list.push(Token::Op(format!("{}=", c)));
+                i += 2;
+                continue;
+            }
+            '&' | '|' if next == Some(c) => {
+                list.push(Token::Op(format!("{}{}", c, c)));
+                i += 2;
+                continue;
+            }
+            '!' | '>' | '<' => list.push(Token::Op(c.to_string())),
+            c if c.is_ascii_digit() || (c == '-' && next.is_some_and(|x| x.is_ascii_digit())) => {
+                let mut j = i + 1;
+                while j < chars.len() && (chars[j].is_ascii_alphanumeric() || chars[j] == '.') {
+                    j += 1;
+                }
+                let text = chars[i..j].iter().collect::<String>();
+                match serde_json::from_str::<Value>(text.as_str()) {
+                    Ok(v) if v.is_number() => list.push(Token::Literal(v)),
+                    _ => return anyhow::anyhow!("expression invalid number[{}]", text).err(),
+                }
+                i = j;
+                continue;
+            }
+            c if c.is_alphabetic() || c == '_' => {
+                //路径中紧跟的[...]一起读取
+                let mut j = i;
+                let mut bracket = false;
+                let mut quote = None;
+                while j < chars.len() {
+                    let x = chars[j];
+                    if let Some(q) = quote {
+                        if x == q {
+                            quote = None;
+                        }
+                    } else if bracket {
+                        if x == '"' || x == '\'' {
+                            quote = Some(x);
+                        } else if x == ']' {
+                            bracket = false;
+                        }
+                    } else if x == '[' {
+                        bracket = true;
+                    } else if !(x.is_alphanumeric() || x == '_' || x == '-' || x == '.') {
+                        break;
+                    }
+                    j += 1;
+                }
+                list.push(Token::Ident(chars[i..j].iter().collect()));
+                i = j;
+                continue;
+            }
+            c => return anyhow::anyhow!("expression[{}] unexpected char[{}]", s, c).err(),
+        }
+        i += 1;
+    }
+    Ok(list)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+    fn is_keyword(&self, keyword: &str, op: &str) -> bool {
+        match self.peek() {
+            Some(Token::Ident(s)) => s == keyword,
+            Some(Token::Op(s)) => s == op,
+            _ => false,
+        }
+    }
+    fn or(&mut self) -> anyhow::Result<Node> {
+        let mut node = self.and()?;
+        while self.is_keyword("or", "||") {
+            self.pos += 1;
+            node = Node::Or(Box::new(node), Box::new(self.and()?));
+        }
+        Ok(node)
+    }
+    fn and(&mut self) -> anyhow::Result<Node> {
+        let mut node = self.not()?;
+        while self.is_keyword("and", "&&") {
+            self.pos += 1;
+            node = Node::And(Box::new(node), Box::new(self.not()?));
+        }
+        Ok(node)
+    }
+    fn not(&mut self) -> anyhow::Result<Node> {
+        if self.is_keyword("not", "!") {
+            self.pos += 1;
+            return Ok(Node::Not(Box::new(self.not()?)));
+        }
+        self.compare()
+    }
+    fn compare(&mut self) -> anyhow::Result<Node> {
+        let left = self.primary()?;
+        let op = match self.peek() {
+            Some(Token::Op(op)) if ["==", "!=", ">", ">=", "<", "<="].contains(&op.as_str()) => {
+                op.clone()
+            }
+            Some(Token::Ident(s)) if s == "in" => s.clone(),
+            _ => return Ok(left),
+        };
+        self.pos += 1;
+        let right = self.primary()?;
+        Ok(Node::Compare(Box::new(left), op, Box::new(right)))
+    }
+    fn list(&mut self, end: Token) -> anyhow::Result<Vec<Node>> {
+        let mut list = vec![];
+        if self.peek() == Some(&end) {
+            self.pos += 1;
+            return Ok(list);
+        }
+        loop {
+            list.push(self.or()?);
+            match self.next() {
+                Some(Token::Comma) => continue,
+                Some(t) if t == end => return Ok(list),
+                t => {
+                    return anyhow::anyhow!("expression expect ',' or {:?}, found {:?}", end, t)
+                        .err()
+                }
+            }
+        }
+    }
+    fn primary(&mut self) -> anyhow::Result<Node> {
+        match self.next() {
+            Some(Token::Literal(v)) => Ok(Node::Literal(v)),
+            Some(Token::LParen) => {
+                let node = self.or()?;
+                match self.next() {
+                    Some(Token::RParen) => Ok(node),
+                    t => anyhow::anyhow!("expression expect ')', found {:?}", t).err(),
+                }
+            }
+            Some(Token::LBracket) => Ok(Node::List(self.list(Token::RBracket)?)),
+            Some(Token::Ident(s)) => match s.as_str() {
+                "true" => Ok(Node::Literal(Value::Bool(true))),
+                "false" => Ok(Node::Literal(Value::Bool(false))),
+                "null" => Ok(Node::Literal(Value::Null)),
+                "and" | "or" | "not" | "in" => {
+                    anyhow::anyhow!("expression unexpected keyword[{}]", s).err()
+                }
+                _ if self.peek() == Some(&Token::LParen) => {
+                    self.pos += 1;
+                    let args = self.list(Token::RParen)?;
+                    Ok(Node::Call(s, args))
+                }
+                _ => match parse_path(s.as_str())?.first() {
+                    Some(PathSegment::Key(_)) => Ok(Node::Path(s)),
+                    _ => anyhow::anyhow!("expression path[{}] must start with a name", s).err(),
+                },
+            },
+            t => anyhow::anyhow!("expression unexpected {:?}", t).err(),
+        }
+    }
+}
+
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "bool",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+fn equal(a: &Value, b: &Value) -> bool {
+    compare(a, "==", b)
+}
+
+//字符串包含子串，数组包含元素，对象包含key，selector的contain也使用它
+pub(crate) fn contains(container: &Value, item: &Value) -> bool {
+    match (container, item) {
+        (Value::String(s), Value::String(x)) => s.contains(x.as_str()),
+        (Value::Array(list), x) => list.iter().any(|i| equal(i, x)),
+        (Value::Object(obj), Value::String(x)) => obj.contains_key(x),
+        _ => false,
+    }
+}
+
+fn call(name: &str, args: Vec<Value>) -> anyhow::Result<Value> {
+    let need = match name {
+        "length" | "len" | "type" | "empty" | "lower" | "upper" => 1,
+        "contains" | "startsWith" | "endsWith" | "matches" => 2,
+        _ => return anyhow::anyhow!("expression unknown function[{}]", name).err(),
+    };
+    if args.len() != need {
+        return anyhow::anyhow!("expression function[{}] need {} args", name, need).err();
+    }
+    let text = |v: &Value| v.as_str().map(|x| x.to_string());
+    let value = match name {
+        "length" | "len" => Value::from(match &args[0] {
+            Value::Null => 0,
+            Value::String(s) => s.chars().count(),
+            Value::Array(list) => list.len(),
+            Value::Object(obj) => obj.len(),
+            v => v.to_string().chars().count(),
+        }),
+        "type" => Value::from(type_name(&args[0])),
+        "empty" => {
+            Value::Bool(!truthy(&args[0]) && !matches!(args[0], Value::Bool(_) | Value::Number(_)))
+        }
+        "lower" => text(&args[0])
+            .map(|x| Value::from(x.to_lowercase()))
+            .unwrap_or_default(),
+        "upper" => text(&args[0])
+            .map(|x| Value::from(x.to_uppercase()))
+            .unwrap_or_default(),
+        "contains" => Value::Bool(contains(&args[0], &args[1])),
+        "startsWith" | "endsWith" | "matches" => {
+            let (Some(s), Some(x)) = (text(&args[0]), text(&args[1])) else {
+                return Ok(Value::Bool(false));
+            };
+            Value::Bool(match name {
+                "startsWith" => s.starts_with(x.as_str()),
+                "endsWith" => s.ends_with(x.as_str()),
+                _ => regex::Regex::new(x.as_str())?.is_match(s.as_str()),
+            })
+        }
+        _ => Value::Null,
+    };
+    Ok(value)
+}
+
+fn eval(node: &Node, lookup: &dyn Fn(&str) -> Option<Value>) -> anyhow::Result<Value> {
+    let value = match node {
+        Node::Literal(v) => v.clone(),
+        Node::Path(p) => lookup(p).unwrap_or_default(),
+        Node::List(list) => Value::Array(
+            list.iter()
+                .map(|x| eval(x, lookup))
+                .collect::<anyhow::Result<_>>()?,
+        ),
+        Node::Not(n) => Value::Bool(!truthy(&eval(n, lookup)?)),
+        Node::And(a, b) => Value::Bool(truthy(&eval(a, lookup)?) && truthy(&eval(b, lookup)?)),
+        Node::Or(a, b) => Value::Bool(truthy(&eval(a, lookup)?) || truthy(&eval(b, lookup)?)),
+        Node::Compare(a, op, b) => {
+            let (a, b) = (eval(a, lookup)?, eval(b, lookup)?);
+            if op == "in" {
+                Value::Bool(contains(&b, &a))
+            } else {
+                Value::Bool(compare(&a, op, &b))
+            }
+        }
+        Node::Call(name, args) => {
+            let args = args
+                .iter()
+                .map(|x| eval(x, lookup))
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            call(name, args)?
+        }
+    };
+    Ok(value)
+}
+
+impl FromStr for Expression {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let tokens = tokenize(s)?;
+        if tokens.is_empty() {
+            return anyhow::anyhow!("expression is empty").err();
+        }
+        let mut parser = Parser { tokens, pos: 0 };
+        let root = parser.or()?;
+        if let Some(t) = parser.peek() {
+            return anyhow::anyhow!("expression[{}] unexpected {:?}", s, t).err();
+        }
+        Ok(Self { root })
+    }
+}
+
+impl Expression {
+    pub fn eval(&self, lookup: &dyn Fn(&str) -> Option<Value>) -> anyhow::Result<Value> {
+        eval(&self.root, lookup)
+    }
+    //按真值判断结果：null、false、0、空字符串、空数组和空对象为假
+    pub fn test(&self, lookup: &dyn Fn(&str) -> Option<Value>) -> anyhow::Result<bool> {
+        Ok(truthy(&self.eval(lookup)?))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::rt_node_service::{parse_path, select_path, Expression};
+    use serde_json::Value;
+    use std::str::FromStr;
+
+    //cargo test rt_node_service::expression::test::test_expression -- --nocapture
+    #[test]
+    fn test_expression() {
+        let data = serde_json::json!({
+            "start":{
+                "intent":"refund",
+                "query":"我要退款 order 123",
+                "items":[{"id":1},{"id":2}],
+                "vip":false,
+                "n":3,
+                "tags":[],
+                "a.b":{"c":"x"}
+            },
+            "node-1":{"ok":true}
+        });
+        let lookup = |path: &str| select_path(&data, &parse_path(path).ok()?);
+        let test = |s: &str| Expression::from_str(s).unwrap().test(&lookup).unwrap();
+        let eval = |s: &str| Expression::from_str(s).unwrap().eval(&lookup).unwrap();
+
+        assert!(test("start.intent == 'refund'"));
+        assert!(test(
+            "start.n <= 3 && start.n >= 3 && start.n < 4 && start.n != 2"
+        ));
+        assert!(!test("start.n < 3"));
+        assert!(test(
+            "start.intent == 'refund' and (length(start.items) > 1 or start.vip)"
+        ));
+        assert!(test("not start.vip and !start.none"));
+        assert!(test(
+            "contains(start.query, '退款') || startsWith(start.query, 'x')"
+        ));
+        assert!(test(
+            r#"matches(start.query, "\\d+$") and endsWith(start.query, '123')"#
+        ));
+        assert!(test(
+            "contains(start.items[*].id, 2) and 1 in start.items[*].id"
+        ));
+        assert!(test(
+            "start.intent in ['refund', 'cancel'] and 'fun' in start.intent"
+        ));
+        assert!(test(
+            "type(start.tags) == 'array' and empty(start.tags) and !empty(start.n)"
+        ));
+        assert!(test(r#"start["a.b"].c == "x" and start.items[-1].id == 2"#));
+        assert!(test("node-1.ok == true and lower('AB') == 'ab'"));
+        assert!(test("start.none == null and len(start.none) == 0"));
+        assert!(test("start.intent > 'a' and -1 < 0 and 1.5 > 1"));
+        assert_eq!(serde_json::json!([1, 2]), eval("start.items[*].id"));
+        assert_eq!(Value::from("number"), eval("type(start.n)"));
+
+        for i in [
+            "",
+            "a ==",
+            "(a == 1",
+            "a == 1)",
+            "'abc",
+            "a b",
+            "and a",
+            "contains(a)",
+            "[1, 2",
+            "a # b",
+        ] {
+            let res = Expression::from_str(i).and_then(|x| x.eval(&lookup));
+            assert!(res.is_err(), "{}", i);
+        }
+        let res = Expression::from_str("matches(start.query, '(')").unwrap();
+        assert!(res.eval(&lookup).is_err());
+    }
+}
@@ -0,0 +1,442 @@
+use crate::rt_node_service::{value_to_text, CfgBound};
+use agent_rt::{Context, ServiceLayer};
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::sync::Arc;
+use std::time::Duration;
+use wd_tools::PFErr;
+
+//通用http请求节点，不需要包装成tool
+#[derive(Debug, Default)]
+pub struct HttpRequestService {
+    pub client: reqwest::Client,
+}
+
+impl HttpRequestService {
+    pub fn new(client: reqwest::Client) -> Self {
+        Self { client }
+    }
+    //状态码规则: 200、2xx、200-299
+    pub fn status_match(rules: &[String], status: u16) -> anyhow::Result<bool> {
+        for rule in rules.iter() {
+            let rule = rule.trim().to_lowercase();
+            let (min, max) = if let Some(prefix) = rule.strip_suffix("xx") {
+                let n = prefix.parse::<u16>().ok().filter(|x| (1..=5).contains(x));
+                match n {
+                    Some(n) => (n * 100, n * 100 + 99),
+                    None => return anyhow::anyhow!("invalid status rule[{}]", rule).err(),
+                }
+            } else if let Some((min, max)) = rule.split_once('-') {
+                match (min.trim().parse::<u16>(), max.trim().parse::<u16>()) {
+                    (Ok(min), Ok(max)) if min <= max => (min, max),
+                    _ => return anyhow::anyhow!("invalid status rule[{}]", rule).err(),
+                }
+            } else {
+                match rule.parse::<u16>() {
+                    Ok(n) => (n, n),
+                    Err(_) => return anyhow::anyhow!("invalid status rule[{}]", rule).err(),
+                }
+            };
+            if (min..=max).contains(&status) {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+    //null跳过，数组展开成多个同名参数
+    fn pairs(map: &Map<String, Value>) -> Vec<(String, String)> {
+        let mut list = vec![];
+        for (k, v) in map.iter() {
+            match v {
+                Value::Null => {}
+                Value::Array(vs) => {
+                    for v in vs.iter().filter(|x| !x.is_null()) {
+                        list.push((k.clone(), value_to_text(v)));
+                    }
+                }
+                v => list.push((k.clone(), value_to_text(v))),
+            }
+        }
+        list
+    }
+    //请求只构建一次，非法的header、url等错误直接返回，不进入重试
+    fn build(
+        &self,
+        method: &reqwest::Method,
+        req: &HttpRequestServiceRequest,
+    ) -> anyhow::Result<reqwest::Request> {
+        let mut builder = self
+            .client
+            .request(method.clone(), req.url.as_str())
+            .query(&Self::pairs(&req.query))
+            .timeout(Duration::from_millis(req.timeout_ms));
+        for (k, v) in Self::pairs(&req.headers) {
+            builder = builder.header(k, v);
+        }
+        if !req.json.is_null() {
+            builder = builder
+                .header(reqwest::header::CONTENT_TYPE, "application/json")
+                .body(serde_json::to_vec(&req.json)?);
+        } else if !req.form.is_empty() {
+            builder = builder.form(&Self::pairs(&req.form));
+        }
+        Ok(builder.build()?)
+    }
+    async fn send(
+        &self,
+        request: &reqwest::Request,
+    ) -> anyhow::Result<(u16, Map<String, Value>, bytes::Bytes)> {
+        let request = match request.try_clone() {
+            Some(s) => s,
+            None => return anyhow::anyhow!("request body can not be cloned").err(),
+        };
+        let resp = self.client.execute(request).await?;
+        let status = resp.status().as_u16();
+        let mut headers = Map::new();
+        for (k, v) in resp.headers().iter() {
+            let v = String::from_utf8_lossy(v.as_bytes()).to_string();
+            match headers.get_mut(k.as_str()) {
+                Some(Value::String(s)) => {
+                    s.push_str(", ");
+                    s.push_str(v.as_str());
+                }
+                _ => {
+                    headers.insert(k.as_str().to_string(), Value::String(v));
+                }
+            }
+        }
+        let body = resp.bytes().await?;
+        Ok((status, headers, body))
+    }
+}
+
+#[async_trait::async_trait]
+impl ServiceLayer for HttpRequestService {
+    type Config = CfgBound<HttpRequestServiceRequest>;
+    type Output = HttpRequestServiceResponse;
+
+    async fn call(
+        &self,
+        code: String,
+        ctx: Arc<Context>,
+        cfg: Self::Config,
+    ) -> anyhow::Result<Self::Output> {
+        let req = cfg.bound(&ctx)?;
+        let method = match reqwest::Method::from_bytes(req.method.to_uppercase().as_bytes()) {
+            Ok(o) => o,
+            Err(_) => {
+                return anyhow::anyhow!("node[{}] invalid http method[{}]", code, req.method).err()
+            }
+        };
+        if req.url.is_empty() {
+            return anyhow::anyhow!("node[{}] http_request url is empty", code).err();
+        }
+        if !req.json.is_null() && !req.form.is_empty() {
+            return anyhow::anyhow!("node[{}] http_request json and form are exclusive", code)
+                .err();
+        }
+        //提前校验规则，避免请求发出后才报错
+        Self::status_match(&req.success, 0)?;
+        Self::status_match(&req.retry_on, 0)?;
+        let request = self.build(&method, &req).map_err(|e| {
+            anyhow::anyhow!(
+                "node[{}] http_request {} {} invalid request:{}",
+                code,
+                method,
+                req.url,
+                e
+            )
+        })?;
+
+        let mut attempt = 0;
+        let (status, headers, body) = loop {
+            let result = self.send(&request).await;
+            let retry = match result {
+                Ok((status, ..)) => Self::status_match(&req.retry_on, status)?,
+                Err(_) => true,
+            };
+            if !retry || attempt >= req.retries {
+                break result.map_err(|e| {
+                    anyhow::anyhow!(
+                        "node[{}] http_request {} {} failed:{}",
+                        code,
+                        method,
+                        req.url,
+                        e
+                    )
+                })?;
+            }
+            attempt += 1;
+            wd_log::log_debug_ln!(
+                "node[{}] http_request {} {} retry {}/{}",
+                code,
+                method,
+                req.url,
+                attempt,
+                req.retries
+            );
+            tokio::time::sleep(Duration::from_millis(req.retry_interval_ms)).await;
+        };
+
+        if !Self::status_match(&req.success, status)? {
+            let text = String::from_utf8_lossy(body.as_ref());
+            let text = text.chars().take(512).collect::<String>();
+            return anyhow::anyhow!(
+                "node[{}] http_request {} {} status[{}] not success: {}",
+                code,
+                method,
+                req.url,
+                status,
+                text
+            )
+            .err();
+        }
+
+        let content_type = headers
+            .get("content-type")
+            .and_then(|x| x.as_str())
+            .unwrap_or("")
+            .to_lowercase();
+        let format = match req.response {
+            HttpResponseFormat::Auto if content_type.contains("json") => HttpResponseFormat::Json,
+            HttpResponseFormat::Auto if std::str::from_utf8(body.as_ref()).is_ok() => {
+                HttpResponseFormat::Text
+            }
+            HttpResponseFormat::Auto => HttpResponseFormat::Base64,
+            f => f,
+        };
+        let body = match format {
+            HttpResponseFormat::Json if body.is_empty() => Value::Null,
+            HttpResponseFormat::Json => match serde_json::from_slice::<Value>(body.as_ref()) {
+                Ok(o) => o,
+                Err(e) => {
+                    return anyhow::anyhow!(
+                        "node[{}] http_request response is not json:{}",
+                        code,
+                        e
+                    )
+                    .err()
+                }
+            },
+            HttpResponseFormat::Text => {
+                Value::String(String::from_utf8_lossy(body.as_ref()).to_string())
+            }
+            _ => Value::String(base64::engine::general_purpose::STANDARD.encode(body.as_ref())),
+        };
+        Ok(HttpRequestServiceResponse {
+            status,
+            headers,
+            body,
+        })
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HttpResponseFormat {
+    //content-type为json时按json解析，其他文本按text，否则base64
+    #[default]
+    Auto,
+    Json,
+    Text,
+    Base64,
+}
+
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+pub struct HttpRequestServiceRequest {
+    #[serde(default = "HttpRequestServiceRequest::default_method")]
+    pub method: String,
+    #[serde(default = "String::default")]
+    pub url: String,
+    #[serde(default = "Map::default")]
+    pub query: Map<String, Value>,
+    #[serde(default = "Map::default")]
+    pub headers: Map<String, Value>,
+    //json和form只能设置一个
+    #[serde(default = "Value::default")]
+    pub json: Value,
+    #[serde(default = "Map::default")]
+    pub form: Map<String, Value>,
+    #[serde(default = "HttpRequestServiceRequest::default_timeout_ms")]
+    pub timeout_ms: u64,
+    #[serde(default = "u32::default")]
+    pub retries: u32,
+    #[serde(default = "HttpRequestServiceRequest::default_retry_interval_ms")]
+    pub retry_interval_ms: u64,
+    //请求失败或者状态码命中时重试
+    #[serde(default = "HttpRequestServiceRequest::default_retry_on")]
+    pub retry_on: Vec<String>,
+    #[serde(default = "HttpRequestServiceRequest::default_success")]
+    pub success: Vec<String>,
+    #[serde(default = "HttpResponseFormat::default")]
+    pub response: HttpResponseFormat,
+}
+
+impl HttpRequestServiceRequest {
+    pub fn default_method() -> String {
+        "GET".into()
+    }
+    pub fn default_timeout_ms() -> u64 {
+        30_000
+    }
+    pub fn default_retry_interval_ms() -> u64 {
+        500
+    }
+    pub fn default_retry_on() -> Vec<String> {
+        vec!["429".into(), "5xx".into()]
+    }
+    pub fn default_success() -> Vec<String> {
+        vec!["2xx".into()]
+    }
+}
+
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+pub struct HttpRequestServiceResponse {
+    pub status: u16,
+    pub headers: Map<String, Value>,
+    pub body: Value,
+}
+
+#[cfg(test)]
+mod test {
+    use crate::llm_provider::mock::MockServer;
+    use crate::rt_node_service::{HttpRequestService, HttpRequestServiceResponse};
+    use agent_rt::{PlanBuilder, Runtime};
+    use serde_json::Value;
+    use wd_tools::PFArc;
+
+    //cargo test rt_node_service::http_request::test::test_http_request -- --nocapture
+    #[tokio::test]
+    async fn test_http_request() {
+        let server = MockServer::route(vec![
+            (
+                "/echo",
+                vec![MockServer::json(serde_json::json!({"ok":true}))],
+            ),
+            (
+                "/flaky",
+                vec![
+                    MockServer::bytes(503, "text/plain", "busy"),
+                    MockServer::bytes(503, "text/plain", "busy"),
+                    MockServer::bytes(200, "text/plain", "ok"),
+                ],
+            ),
+            (
+                "/bytes",
+                vec![MockServer::bytes(
+                    200,
+                    "application/octet-stream",
+                    vec![0u8, 1, 2, 255],
+                )],
+            ),
+        ])
+        .await;
+        let host = server.url.clone();
+        let rt = Runtime::default()
+            .register_service_layer("http_request", HttpRequestService::default())
+            .launch();
+        let run = |cfg: Value| {
+            let rt = rt.clone();
+            async move {
+                let cfg = serde_json::to_string(&cfg).unwrap();
+                rt.ctx(
+                    "http-request-001",
+                    PlanBuilder::single_node("http_request", cfg).build(),
+                )
+                .arc()
+                .block_on::<Value, _>(serde_json::json!({
+                    "token":"abc",
+                    "query":"rust",
+                    "tags":["a","b"]
+                }))
+                .await
+                .map(|x| serde_json::from_value::<HttpRequestServiceResponse>(x).unwrap())
+            }
+        };
+
+        let resp = run(serde_json::json!({
+            "method":"post",
+            "url":format!("{}/echo", host),
+            "query":{"q":"{{start.query}}","tag":"{{start.tags}}","none":"{{start.none}}"},
+            "headers":{"x-token":"{{start.token}}"},
+            "json":{"query":"{{start.query}}"}
+        }))
+        .await
+        .unwrap();
+        println!("{:?}", resp);
+        assert_eq!(resp.status, 200);
+        assert_eq!(resp.headers["content-type"], "application/json");
+        assert_eq!(resp.body["ok"], true);
+        let req = server.requests().pop().unwrap();
+        assert_eq!("POST", req.method.as_str());
+        assert_eq!("/echo?q=rust&tag=a&tag=b", req.path.as_str());
+        assert_eq!("abc", req.header("x-token"));
+        assert_eq!("application/json", req.header("content-type"));
+        assert_eq!(r#"{"query":"rust"}"#, req.text.as_str());
+
+        run(serde_json::json!({
+            "method":"PUT",
+            "url":format!("{}/echo", host),
+            "form":{"a":1,"b":"x y"},
+            "response":"text"
+        }))
+        .await
+        .unwrap();
+        let req = server.requests().pop().unwrap();
+        assert_eq!("PUT", req.method.as_str());
+        assert_eq!(
+            "application/x-www-form-urlencoded",
+            req.header("content-type")
+        );
+        assert_eq!("a=1&b=x+y", req.text.as_str());
+
+        //重试到成功
+        let resp = run(serde_json::json!({
+            "url":format!("{}/flaky", host),
+            "retries":2,
+            "retry_interval_ms":10
+        }))
+        .await
+        .unwrap();
+        assert_eq!(resp.body, "ok");
+
+        let resp = run(serde_json::json!({"url":format!("{}/bytes", host)}))
+            .await
+            .unwrap();
+        assert_eq!(resp.body, "AAEC/w==");
+
+        let err = run(serde_json::json!({"url":format!("{}/missing", host)}))
+            .await
+            .unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("status[404] not success: not found"));
+        let resp = run(serde_json::json!({
+            "url":format!("{}/missing", host),
+            "success":["2xx","404"]
+        }))
+        .await
+        .unwrap();
+        assert_eq!(resp.status, 404);
+
+        let err = run(serde_json::json!({"url":format!("{}/bytes", host),"response":"json"}))
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("response is not json"));
+
+        //构建请求失败时不重试
+        let count = server.requests().len();
+        for cfg in [
+            serde_json::json!({"url":format!("{}/echo", host),"headers":{"bad header":"x"},"retries":3}),
+            serde_json::json!({"url":"not a url","retries":3}),
+        ] {
+            let err = run(cfg).await.unwrap_err();
+            assert!(err.to_string().contains("invalid request"), "{}", err);
+        }
+        assert_eq!(count, server.requests().len());
+
+        assert!(HttpRequestService::status_match(&["6xx".into()], 200).is_err());
+        assert!(HttpRequestService::status_match(&["200-204".into()], 204).unwrap());
+    }
+}
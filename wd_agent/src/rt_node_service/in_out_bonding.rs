@@ -1,10 +1,10 @@
+use crate::rt_node_service::{is_template, parse_path, select_path, PathSegment, Template};
 use agent_rt::Context;
 use serde::{Deserialize, Serialize};
 use serde_json::{Map, Value};
-use std::collections::VecDeque;
 use std::marker::PhantomData;
 use std::str::FromStr;
-use wd_tools::SimpleRegexMatch;
+use wd_tools::PFErr;
 
 #[derive(Debug, Default, Clone, Deserialize, Serialize)]
 pub struct CfgBound<T> {
@@ -19,49 +19,52 @@ where
     T: for<'a> serde::Deserialize<'a>,
 {
     pub fn raw_bound_value(self, ctx: &Context) -> anyhow::Result<Value> {
-        Self::bound_value(self.inner, ctx)
+        Self::bound_value(self.inner, ctx, &mut vec![])
+    }
+    //严格模式，有找不到的引用时报错并列出全部
+    pub fn strict_bound_value(self, ctx: &Context) -> anyhow::Result<Value> {
+        let mut missing = vec![];
+        let value = Self::bound_value(self.inner, ctx, &mut missing)?;
+        if !missing.is_empty() {
+            return anyhow::anyhow!("unresolved refs[{}]", missing.join(", ")).err();
+        }
+        Ok(value)
     }
     pub fn bound(self, ctx: &Context) -> anyhow::Result<T> {
         let value = self.raw_bound_value(ctx)?;
         let t: T = serde_json::from_value(value)?;
         Ok(t)
     }
-    fn bound_value(value: Value, ctx: &Context) -> anyhow::Result<Value> {
+    fn bound_value(
+        value: Value,
+        ctx: &Context,
+        missing: &mut Vec<String>,
+    ) -> anyhow::Result<Value> {
         match value {
             // Value::Null => {}
             // Value::Bool(_) => {}
             // Value::Number(_) => {}
-            Value::String(mut s) => {
-                let a = s.as_str();
-                let list = Self::string_type(a)
-                    .into_iter()
-                    .map(|x| x.to_string())
-                    .collect::<Vec<String>>();
-                if list.is_empty() {
+            Value::String(s) => {
+                if !is_template(s.as_str()) {
                     return Ok(Value::String(s));
                 }
-                if list[0].len() + 4 == s.len() {
-                    if let Some(s) = Self::get_value_from_ctx(list[0].as_str(), ctx) {
-                        return Ok(s);
-                    }
-                    return Ok(Value::Null);
-                    // return anyhow::anyhow!("not found var[{}]", list[0]).err();
-                }
+                let tpl = match Template::from_str(s.as_str()) {
+                    Ok(t) => t,
+                    Err(e) => return anyhow::anyhow!("config[{}] {}", s, e).err(),
+                };
+                let (value, list) =
+                    tpl.render_checked(&|path| Self::get_value_from_ctx(path, ctx))?;
                 for i in list {
-                    let val = if let Some(val) = Self::get_value_from_ctx(i.as_str(), ctx) {
-                        val
-                    } else {
-                        Value::Null
-                        // return anyhow::anyhow!("not found var[{}]", i).err();
-                    };
-                    s = s.replace(format!("{{{{{i}}}}}").as_str(), val.as_str().unwrap_or(""));
+                    if !missing.contains(&i) {
+                        missing.push(i);
+                    }
                 }
-                Ok(Value::String(s))
+                Ok(value)
             }
             Value::Array(list) => {
                 let mut vec = vec![];
                 for i in list {
-                    let val = Self::bound_value(i, ctx)?;
+                    let val = Self::bound_value(i, ctx, missing)?;
                     vec.push(val);
                 }
                 Ok(Value::Array(vec))
@@ -69,7 +72,7 @@ where
             Value::Object(obj) => {
                 let mut map = Map::new();
                 for (k, v) in obj {
-                    let val = Self::bound_value(v, ctx)?;
+                    let val = Self::bound_value(v, ctx, missing)?;
                     map.insert(k, val);
                 }
                 Ok(Value::Object(map))
@@ -77,41 +80,13 @@ where
             _ => Ok(value),
         }
     }
-    fn string_type(s: &str) -> Vec<&str> {
-        let list = s.regex(r"\{\{(.*?)\}\}").unwrap_or(vec![]);
-        list
-    }
+    //第一段为节点code，其余按parse_path的规则取值
     pub fn get_value_from_ctx(pos: &str, ctx: &Context) -> Option<Value> {
-        let mut ks = pos.split(".").collect::<VecDeque<&str>>();
-        let code = ks.pop_front()?;
-        let res = ctx.get_opt(code, |x: Option<&mut Value>| {
-            let mut x = x?;
-            loop {
-                if let Some(key) = ks.pop_front() {
-                    match x {
-                        Value::Array(ref mut list) => {
-                            if let Ok(index) = usize::from_str(key) {
-                                if let Some(val) = list.get_mut(index) {
-                                    x = val;
-                                    continue;
-                                }
-                            }
-                        }
-                        Value::Object(ref mut obj) => {
-                            if let Some(val) = obj.get_mut(key) {
-                                x = val;
-                                continue;
-                            }
-                        }
-                        _ => return None,
-                    }
-                    return None;
-                } else {
-                    return Some(x.clone());
-                }
-            }
-        });
-        return res;
+        let path = parse_path(pos).ok()?;
+        let (PathSegment::Key(code), rest) = path.split_first()? else {
+            return None;
+        };
+        ctx.get_json(code, |x: Option<&mut Value>| select_path(x?, rest))
     }
 }
 
@@ -171,5 +146,30 @@ mod test {
         assert_eq!(true, tc.open);
         assert_eq!("this is a key", tc.query);
         assert_eq!(3, tc.list.len());
+
+        //拼接到字符串中的数字和对象按json输出
+        let cb: CfgBound<serde_json::Value> = serde_json::from_str(
+            r#"{"text":"n={{j1.number}} map={{j1.map}} {% for i in j1.list %}{{i}};{% endfor %}",
+            "len":"{{j1.list | length}}","none":"{{j1.none | default:\"-\"}}"}"#,
+        )
+        .unwrap();
+        let value = cb.raw_bound_value(&ctx).unwrap();
+        assert_eq!(
+            serde_json::json!({"text":"n=1 map={\"a\":true} 2;3;4;","len":3,"none":"-"}),
+            value
+        );
+        let cb: CfgBound<serde_json::Value> =
+            serde_json::from_str(r#"{"a":"{{j1.hello"}"#).unwrap();
+        assert!(cb.raw_bound_value(&ctx).is_err());
+
+        let cfg = r#"{"last":"{{j1.list[-1]}}","a":"{{j1.map[\"a\"]}}","x":"{{j1.x}}","y":["{{j3.y}}","{{j1.x}}"]}"#;
+        let cb: CfgBound<serde_json::Value> = serde_json::from_str(cfg).unwrap();
+        let value = cb.clone().raw_bound_value(&ctx).unwrap();
+        assert_eq!(
+            serde_json::json!({"last":4,"a":true,"x":null,"y":[null,null]}),
+            value
+        );
+        let err = cb.strict_bound_value(&ctx).unwrap_err().to_string();
+        assert!(err.contains("unresolved refs[j1.x, j3.y]"), "{}", err);
     }
 }
@@ -1,8 +1,7 @@
-use crate::rt_node_service::CfgBound;
-use agent_rt::Context;
+use crate::rt_node_service::{parse_path, CfgBound, PathSegment};
+use agent_rt::{Context, Output};
 use serde::{Deserialize, Serialize};
-use serde_json::Value;
-use std::collections::VecDeque;
+use serde_json::{Map, Value};
 use std::sync::Arc;
 use wd_tools::PFErr;
 
@@ -14,6 +13,9 @@ impl InjectorService {
         match operate.to_lowercase().as_str() {
             "=" => *src = value,
             "append" => {
+                if src.is_null() {
+                    *src = Value::Array(vec![]);
+                }
                 if let Value::Array(list) = src {
                     if let Value::Array(ref mut vec) = value {
                         list.append(vec);
@@ -27,6 +29,118 @@ impl InjectorService {
                 }
                 return anyhow::anyhow!("InjectorService.operate append from must is array").err();
             }
+            "prepend" => {
+                if src.is_null() {
+                    *src = Value::Array(vec![]);
+                }
+                let Value::Array(list) = src else {
+                    return anyhow::anyhow!("InjectorService.operate prepend from must is array")
+                        .err();
+                };
+                let items = match value {
+                    Value::Array(vec) => vec,
+                    Value::Null => vec![],
+                    v => vec![v],
+                };
+                list.splice(0..0, items);
+            }
+            //浅合并，同名key覆盖
+            "merge" => {
+                if src.is_null() {
+                    *src = Value::Object(Map::new());
+                }
+                match (src, value) {
+                    (Value::Object(obj), Value::Object(map)) => obj.extend(map),
+                    (Value::Object(_), Value::Null) => {}
+                    _ => {
+                        return anyhow::anyhow!("InjectorService.operate merge need two object")
+                            .err()
+                    }
+                }
+            }
+            //数组按下标删除，负数从末尾开始；对象按key删除；value可以是数组
+            "remove" => {
+                let keys = match value {
+                    Value::Array(list) => list,
+                    v => vec![v],
+                };
+                match src {
+                    Value::Array(list) => {
+                        let len = list.len() as i64;
+                        let mut index = vec![];
+                        for i in keys.iter() {
+                            let Some(i) = i.as_i64() else {
+                                return anyhow::anyhow!(
+                                    "InjectorService.operate remove array need index, found {}",
+                                    i
+                                )
+                                .err();
+                            };
+                            let i = if i < 0 { len + i } else { i };
+                            if (0..len).contains(&i) {
+                                index.push(i as usize);
+                            }
+                        }
+                        index.sort_unstable();
+                        index.dedup();
+                        for i in index.into_iter().rev() {
+                            list.remove(i);
+                        }
+                    }
+                    Value::Object(obj) => {
+                        for k in keys.iter() {
+                            let Some(k) = k.as_str() else {
+                                return anyhow::anyhow!(
+                                    "InjectorService.operate remove object need key, found {}",
+                                    k
+                                )
+                                .err();
+                            };
+                            obj.remove(k);
+                        }
+                    }
+                    Value::Null => {}
+                    _ => {
+                        return anyhow::anyhow!(
+                            "InjectorService.operate remove from must is array or object"
+                        )
+                        .err()
+                    }
+                }
+            }
+            //不存在时从0开始，value为空时加1
+            "increment" => {
+                let step = if value.is_null() {
+                    Value::from(1)
+                } else {
+                    value
+                };
+                let current = if src.is_null() {
+                    Value::from(0)
+                } else {
+                    src.clone()
+                };
+                *src = match (current.as_i64(), step.as_i64()) {
+                    (Some(a), Some(b)) => match a.checked_add(b) {
+                        Some(n) => Value::from(n),
+                        None => {
+                            return anyhow::anyhow!("InjectorService.operate increment overflow")
+                                .err()
+                        }
+                    },
+                    _ => match (current.as_f64(), step.as_f64()) {
+                        (Some(a), Some(b)) => Value::from(a + b),
+                        _ => {
+                            return anyhow::anyhow!(
+                                "InjectorService.operate increment need number, found {} and {}",
+                                current,
+                                step
+                            )
+                            .err()
+                        }
+                    },
+                };
+            }
             _ => {
                 return anyhow::anyhow!("InjectorService.operate not support operate[{}]", operate)
                     .err()
@@ -34,18 +148,80 @@ impl InjectorService {
         }
         Ok(())
     }
+    //按路径找到要修改的值，create为true时创建不存在的key
+    pub fn locate<'a>(
+        root: &'a mut Value,
+        path: &[PathSegment],
+        create: bool,
+        pos: &str,
+    ) -> anyhow::Result<&'a mut Value> {
+        let mut cur = root;
+        for seg in path {
+            if create && cur.is_null() && matches!(seg, PathSegment::Key(_)) {
+                *cur = Value::Object(Map::new());
+            }
+            cur = match (seg, cur) {
+                (PathSegment::Key(key), Value::Object(obj)) => {
+                    if create {
+                        obj.entry(key.clone()).or_insert(Value::Null)
+                    } else {
+                        match obj.get_mut(key) {
+                            Some(s) => s,
+                            None => {
+                                return anyhow::anyhow!("CfgBound.not find field[{}]", pos).err()
+                            }
+                        }
+                    }
+                }
+                (PathSegment::Index(i), Value::Array(list)) => {
+                    let i = if *i < 0 { list.len() as i64 + i } else { *i };
+                    match usize::try_from(i).ok().and_then(|i| list.get_mut(i)) {
+                        Some(s) => s,
+                        None => {
+                            return anyhow::anyhow!("InjectorService index out of range[{}]", pos)
+                                .err()
+                        }
+                    }
+                }
+                (PathSegment::Key(key), Value::Array(list)) => {
+                    match key.parse::<usize>().ok().and_then(|i| list.get_mut(i)) {
+                        Some(s) => s,
+                        None => {
+                            return anyhow::anyhow!("InjectorService index out of range[{}]", pos)
+                                .err()
+                        }
+                    }
+                }
+                (PathSegment::Wildcard, _) => {
+                    return anyhow::anyhow!("InjectorService to[{}] not support [*]", pos).err()
+                }
+                _ => return anyhow::anyhow!("InjectorService.find field failed [{}]", pos).err(),
+            };
+        }
+        Ok(cur)
+    }
     pub fn update(
         pos: String,
         ctx: &Context,
         function: impl FnOnce(&mut Value) -> anyhow::Result<()> + 'static,
     ) -> anyhow::Result<()> {
-        let mut ks = pos.as_str().split(".").collect::<VecDeque<&str>>();
-        let code = ks.pop_front().unwrap_or("").to_string();
-        return ctx.plan.update(
-            code.as_str(),
+        Self::update_plan(pos, ctx, false, function)
+    }
+    //修改plan中节点的配置
+    pub fn update_plan(
+        pos: String,
+        ctx: &Context,
+        create: bool,
+        function: impl FnOnce(&mut Value) -> anyhow::Result<()> + 'static,
+    ) -> anyhow::Result<()> {
+        let path = parse_path(pos.as_str())?;
+        let Some(PathSegment::Key(code)) = path.first() else {
+            return anyhow::anyhow!("InjectorService.update invalid to[{}]", pos).err();
+        };
+        let code = code.clone();
+        ctx.plan.update(
+            code.clone().as_str(),
             Box::new(move |x| {
-                let mut ks = pos.split(".").collect::<VecDeque<&str>>();
-                let code = ks.pop_front().unwrap_or("");
                 if x.is_none() {
                     return anyhow::anyhow!("InjectorService.update not found node[{}]", code)
                         .err();
@@ -59,34 +235,46 @@ impl InjectorService {
                 };
                 let mut cfg =
                     serde_json::from_str::<Value>(node.node_config.as_str()).unwrap_or(Value::Null);
-                let mut mut_from = &mut cfg;
-                loop {
-                    if let Some(key) = ks.pop_front() {
-                        match mut_from {
-                            Value::Object(ref mut obj) => {
-                                if let Some(val) = obj.get_mut(key) {
-                                    mut_from = val;
-                                    continue;
-                                }
-                            }
-                            _ => {
-                                return anyhow::anyhow!(
-                                    "InjectorService.find field failed [{}]",
-                                    pos
-                                )
-                                .err()
-                            }
-                        }
-                        return anyhow::anyhow!("CfgBound.not find field[{}]", pos).err();
-                    } else {
-                        break;
-                    }
-                }
-                let result = function(mut_from);
+                let target = Self::locate(&mut cfg, &path[1..], create, pos.as_str())?;
+                let result = function(target);
                 node.node_config = serde_json::to_string(&cfg).unwrap_or("".into());
-                return result;
+                result
             }),
-        );
+        )
+    }
+    //修改ctx中的变量或节点输出，变量不存在且create为true时新建
+    pub fn update_ctx(
+        pos: &str,
+        ctx: &Context,
+        create: bool,
+        function: impl FnOnce(&mut Value) -> anyhow::Result<()>,
+    ) -> anyhow::Result<()> {
+        let path = parse_path(pos)?;
+        let Some((PathSegment::Key(code), rest)) = path.split_first() else {
+            return anyhow::anyhow!("InjectorService.update invalid to[{}]", pos).err();
+        };
+        if !ctx.exist(code) {
+            if !create {
+                return anyhow::anyhow!("InjectorService.update not found ctx var[{}]", code).err();
+            }
+            ctx.set(code.clone(), Value::Null);
+        }
+        //rust原生类型的输出只能改到json视图，读取原始类型的节点看不到，直接报错
+        let is_json = ctx.get(code, |_: &mut Value| ()).is_some()
+            || ctx
+                .get(code, |x: &mut Output| x.any.is::<Value>())
+                .unwrap_or(false);
+        if !is_json {
+            return anyhow::anyhow!("InjectorService.update ctx var[{}] is not json", code).err();
+        }
+        ctx.get_json(code, |x| {
+            let Some(root) = x else {
+                return anyhow::anyhow!("InjectorService.update ctx var[{}] is not json", code)
+                    .err();
+            };
+            let target = Self::locate(root, rest, create, pos)?;
+            function(target)
+        })
     }
 }
 
@@ -102,13 +290,29 @@ impl agent_rt::ServiceLayer for InjectorService {
         cfg: Self::Config,
     ) -> anyhow::Result<Self::Output> {
         let InjectorServiceConfig {
-            from, to, operate, ..
+            from,
+            to,
+            operate,
+            target,
+            create,
+            ..
         } = cfg.bound(&ctx)?;
         if to.is_empty() {
             return anyhow::anyhow!("InjectorService: from and to must have a value").err();
         }
 
-        Self::update(to, &ctx, |x| Self::operate(x, from, operate))?;
+        match target.as_str() {
+            "" | "plan" => {
+                Self::update_plan(to, &ctx, create, |x| Self::operate(x, from, operate))?
+            }
+            "ctx" => Self::update_ctx(to.as_str(), &ctx, create, |x| {
+                Self::operate(x, from, operate)
+            })?,
+            _ => {
+                return anyhow::anyhow!("InjectorService: unknown target[{}], plan or ctx", target)
+                    .err()
+            }
+        }
 
         Ok(Value::Null)
     }
@@ -120,17 +324,157 @@ pub struct InjectorServiceConfig {
     pub from: Value,
     pub to: String,
     pub default: Value,
-    // =,append,
+    // =,append,prepend,merge,remove,increment
     pub operate: String,
+    //plan(默认):修改节点配置，ctx:修改ctx中的变量或节点输出
+    pub target: String,
+    //to的路径不存在时创建
+    pub create: bool,
 }
 
 #[cfg(test)]
 mod test {
     use crate::rt_node_service::{InjectorService, VarFlowChartService};
-    use agent_rt::{PlanBuilder, Runtime, END_NODE_CODE};
+    use agent_rt::{Output, PlanBuilder, Runtime, END_NODE_CODE};
     use serde_json::Value;
     use wd_tools::PFArc;
 
+    //cargo test rt_node_service::injector::test::test_injector_operate -- --nocapture
+    #[test]
+    fn test_injector_operate() {
+        use serde_json::json;
+        let op = |mut src: Value, value: Value, operate: &str| {
+            InjectorService::operate(&mut src, value, operate.to_string()).map(|_| src)
+        };
+
+        assert_eq!(
+            op(json!([2]), json!([0, 1]), "prepend").unwrap(),
+            json!([0, 1, 2])
+        );
+        assert_eq!(
+            op(Value::Null, json!("a"), "prepend").unwrap(),
+            json!(["a"])
+        );
+        assert_eq!(op(Value::Null, json!("a"), "append").unwrap(), json!(["a"]));
+        assert_eq!(
+            op(json!({"a":1,"b":1}), json!({"b":2,"c":3}), "merge").unwrap(),
+            json!({"a":1,"b":2,"c":3})
+        );
+        assert!(op(json!([1]), json!({"a":1}), "merge").is_err());
+        assert_eq!(
+            op(json!([0, 1, 2, 3]), json!([0, -1, 9]), "remove").unwrap(),
+            json!([1, 2])
+        );
+        assert_eq!(
+            op(json!({"a":1,"b":2}), json!("a"), "remove").unwrap(),
+            json!({"b":2})
+        );
+        assert!(op(json!([1]), json!("a"), "remove").is_err());
+        assert_eq!(op(Value::Null, Value::Null, "increment").unwrap(), json!(1));
+        assert_eq!(op(json!(3), json!(-5), "increment").unwrap(), json!(-2));
+        assert_eq!(op(json!(1), json!(0.5), "increment").unwrap(), json!(1.5));
+        assert!(op(json!("1"), Value::Null, "increment").is_err());
+        let res = op(json!(i64::MAX), Value::Null, "increment");
+        assert!(format!("{:?}", res).contains("increment overflow"));
+        assert!(op(Value::Null, Value::Null, "unknown").is_err());
+
+        let mut root = json!({"a":[{"b":1}]});
+        let path = crate::rt_node_service::parse_path("a[-1].c.d").unwrap();
+        assert!(InjectorService::locate(&mut root, &path, false, "a[-1].c.d").is_err());
+        *InjectorService::locate(&mut root, &path, true, "a[-1].c.d").unwrap() = json!(2);
+        assert_eq!(root, json!({"a":[{"b":1,"c":{"d":2}}]}));
+    }
+
+    //cargo test rt_node_service::injector::test::test_injector_ctx -- --nocapture
+    #[tokio::test]
+    async fn test_injector_ctx() {
+        let rt = Runtime::default()
+            .register_service_layer("flow_chart_injector", InjectorService::default())
+            .register_service_layer("flow_chart_var", VarFlowChartService::default())
+            .launch();
+
+        let counter = serde_json::json!({
+            "from":"{{start.step}}",
+            "to":"state.count",
+            "operate":"increment",
+            "target":"ctx",
+            "create":true
+        });
+        let history = serde_json::json!({
+            "from":{"role":"user","content":"{{start.query}}"},
+            "to":"end.history",
+            "operate":"append",
+            "create":true
+        });
+
+        let result = rt
+            .ctx(
+                "injector-002",
+                PlanBuilder::start(
+                    (
+                        "counter",
+                        "flow_chart_injector",
+                        serde_json::to_string(&counter).unwrap(),
+                    ),
+                    vec!["history"],
+                )
+                .sequence(
+                    vec![
+                        (
+                            "history",
+                            "flow_chart_injector",
+                            serde_json::to_string(&history).unwrap(),
+                        ),
+                        (
+                            "end",
+                            "flow_chart_var",
+                            r#"{"count":"{{state.count}}"}"#.to_string(),
+                        ),
+                    ],
+                    "",
+                )
+                .check_and_build()
+                .unwrap(),
+            )
+            .arc()
+            .block_on::<Value, _>(serde_json::json!({
+                "step":2,
+                "query":"hello"
+            }))
+            .await
+            .unwrap();
+
+        println!("{}", result);
+        assert_eq!(
+            result,
+            serde_json::json!({
+                "count":2,
+                "history":[{"role":"user","content":"hello"}]
+            })
+        );
+
+        //已存在的非json变量和rust原生类型的输出不能修改
+        let ctx = rt.ctx("injector-003", PlanBuilder::single_node("end", "").build());
+        ctx.set("raw", 1u32);
+        ctx.set("typed", Output::serialize(1u32).unwrap());
+        ctx.set("output", Output::json(serde_json::json!({"a":1})));
+        for code in ["raw.a", "typed.a"] {
+            let res = InjectorService::update_ctx(code, &ctx, true, |x| {
+                InjectorService::operate(x, Value::from(2), "=".into())
+            });
+            assert!(res.unwrap_err().to_string().contains("is not json"));
+        }
+        assert_eq!(Some(1), ctx.get("raw", |x: &mut u32| *x));
+        InjectorService::update_ctx("output.a", &ctx, false, |x| {
+            InjectorService::operate(x, Value::from(2), "=".into())
+        })
+        .unwrap();
+        assert_eq!(
+            Some(serde_json::json!({"a":2})),
+            ctx.get_json("output", |x| x.cloned())
+        );
+    }
+
     #[tokio::test]
     async fn test_injector() {
         let rt = Runtime::default()
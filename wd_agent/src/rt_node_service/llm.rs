@@ -0,0 +1,610 @@
+use crate::llm_provider::{
+    AnthropicProvider, ChatMessage, ChatRequest, ChatResponse, ChatRole, ChatTool, ChatToolCall,
+    LLMClientProfile, LLMProvider, ModelPricing, OllamaProvider, OpenAICompatibleProvider,
+    ZhipuProvider,
+};
+use crate::rt_node_service::{
+    call_with_response_schema, CfgBound, ChatSummarizer, ContextWindowReport, LLMContextMessage,
+    LLMNodeRequest, LLMNodeResponse, LLMToolCallRequest, OpenaiLLMService,
+};
+use agent_rt::Context;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Instant;
+use wd_tools::PFErr;
+
+//按名称选择模型厂商的llm节点
+pub struct LLMService {
+    providers: HashMap<String, Arc<dyn LLMProvider>>,
+    default_provider: String,
+    //按客户端配置创建的openai兼容厂商，节点通过profile选择
+    profiles: HashMap<String, Arc<dyn LLMProvider>>,
+    //压缩超出上下文窗口的消息，节点需打开summarize_context
+    summarizer: Option<Arc<dyn ChatSummarizer>>,
+    //按模型单价计算费用，计入ctx预算的max_cost
+    pricing: ModelPricing,
+}
+
+impl Default for LLMService {
+    fn default() -> Self {
+        Self::new()
+            .register_provider("openai", OpenAICompatibleProvider::default())
+            .register_provider("anthropic", AnthropicProvider::default())
+            .register_provider("ollama", OllamaProvider::default())
+            .register_provider("zhipu", ZhipuProvider::default())
+    }
+}
+
+impl LLMService {
+    pub fn new() -> Self {
+        Self {
+            providers: HashMap::new(),
+            default_provider: "openai".into(),
+            profiles: HashMap::new(),
+            summarizer: None,
+            pricing: ModelPricing::default(),
+        }
+    }
+    pub fn register_provider<S: Into<String>, P: LLMProvider + 'static>(
+        mut self,
+        name: S,
+        provider: P,
+    ) -> Self {
+        self.providers.insert(name.into(), Arc::new(provider));
+        self
+    }
+    pub fn default_provider<S: Into<String>>(mut self, name: S) -> Self {
+        self.default_provider = name.into();
+        self
+    }
+    pub fn register_profile<S: Into<String>>(
+        mut self,
+        name: S,
+        profile: LLMClientProfile,
+    ) -> anyhow::Result<Self> {
+        let provider = OpenAICompatibleProvider::from_profile(&profile)?;
+        self.profiles.insert(name.into(), Arc::new(provider));
+        Ok(self)
+    }
+    pub fn summarizer<S: ChatSummarizer + 'static>(mut self, summarizer: S) -> Self {
+        self.summarizer = Some(Arc::new(summarizer));
+        self
+    }
+    pub fn pricing(mut self, pricing: ModelPricing) -> Self {
+        self.pricing = pricing;
+        self
+    }
+    pub fn get_provider(&self, name: &str) -> anyhow::Result<Arc<dyn LLMProvider>> {
+        let name = if name.is_empty() {
+            self.default_provider.as_str()
+        } else {
+            name
+        };
+        match self.providers.get(name) {
+            Some(s) => Ok(s.clone()),
+            None => anyhow::anyhow!("llm provider[{}] not found", name).err(),
+        }
+    }
+    pub fn get_profile(&self, name: &str) -> anyhow::Result<Arc<dyn LLMProvider>> {
+        match self.profiles.get(name) {
+            Some(s) => Ok(s.clone()),
+            None => anyhow::anyhow!("llm profile[{}] not found", name).err(),
+        }
+    }
+    //选择厂商，按模型上下文窗口裁剪消息，并将节点配置转换为厂商请求
+    pub async fn chat_request(
+        &self,
+        mut cfg: LLMNodeRequest,
+    ) -> anyhow::Result<(Arc<dyn LLMProvider>, ChatRequest, ContextWindowReport)> {
+        let provider = if cfg.profile.is_empty() {
+            self.get_provider(cfg.provider.as_str())?
+        } else {
+            self.get_profile(cfg.profile.as_str())?
+        };
+        //未显式指定模型时，由厂商决定默认模型
+        if cfg.model.as_deref().unwrap_or_default().is_empty() {
+            cfg.model = Some(provider.default_model().to_string());
+        }
+        let report = cfg.fit_context_window(self.summarizer.as_deref()).await?;
+        Ok((provider, cfg.to_chat_request()?, report))
+    }
+    pub async fn send(
+        &self,
+        ctx: &Arc<Context>,
+        provider: &dyn LLMProvider,
+        req: ChatRequest,
+        is_stream: bool,
+        to_channel: bool,
+    ) -> anyhow::Result<ChatResponse> {
+        let model = req.model.clone();
+        let resp = if !is_stream {
+            provider.chat(req).await?
+        } else {
+            let stream_ctx = ctx.clone();
+            provider
+                .chat_stream(
+                    req,
+                    Box::new(move |s| {
+                        if to_channel {
+                            OpenaiLLMService::try_send_to_channel(&stream_ctx, s.to_string());
+                        }
+                    }),
+                )
+                .await?
+        };
+        //token用量和费用计入ctx的预算，优先按响应中的模型计价
+        if let Some(ref usage) = resp.usage {
+            let model = if resp.model.is_empty() {
+                model.as_str()
+            } else {
+                resp.model.as_str()
+            };
+            ctx.report_usage(self.pricing.usage(model, usage))?;
+        }
+        Ok(resp)
+    }
+}
+
+impl LLMContextMessage {
+    pub fn to_provider_message(self) -> anyhow::Result<ChatMessage> {
+        let Some(role) = ChatRole::from_name(self.role.as_str()) else {
+            return anyhow::anyhow!("unknown context message role[{}]", self.role).err();
+        };
+        let tool_calls = match role {
+            ChatRole::Assistant => self.all_tool_calls(),
+            _ => vec![],
+        };
+        let tool_call_id = match role {
+            ChatRole::Tool => self.call_id,
+            _ => String::new(),
+        };
+        Ok(ChatMessage {
+            role,
+            content: self.content,
+            parts: self.parts,
+            name: self.name,
+            tool_calls,
+            tool_call_id,
+        })
+    }
+}
+
+impl From<ChatMessage> for LLMContextMessage {
+    fn from(value: ChatMessage) -> Self {
+        LLMContextMessage {
+            role: value.role.as_str().into(),
+            content: value.content,
+            parts: value.parts,
+            name: value.name,
+            tool_calls: value.tool_calls,
+            call_id: value.tool_call_id,
+            ..Default::default()
+        }
+    }
+}
+
+impl LLMNodeRequest {
+    pub fn to_chat_request(self) -> anyhow::Result<ChatRequest> {
+        let LLMNodeRequest {
+            prompt,
+            model,
+            tools,
+            context,
+            max_tokens,
+            temperature,
+            query,
+            response_schema,
+            ..
+        } = self;
+        let mut messages = context
+            .into_iter()
+            .map(|x| x.to_provider_message())
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        if !prompt.is_empty() {
+            match messages.first_mut() {
+                //已有system消息时合并，不丢弃节点的prompt和输出格式要求
+                Some(first) if first.role == ChatRole::System => {
+                    first.content = format!("{}\n\n{}", prompt, first.content);
+                }
+                _ => messages.insert(0, ChatMessage::system(prompt)),
+            }
+        }
+        if !query.is_empty() {
+            messages.push(ChatMessage::user(query));
+        }
+        let tools = tools
+            .into_iter()
+            .map(|x| ChatTool {
+                name: x.function.name,
+                description: x.function.description.unwrap_or_default(),
+                parameters: x
+                    .function
+                    .parameters
+                    .unwrap_or(serde_json::json!({"type":"object","properties":{}})),
+            })
+            .collect();
+        Ok(ChatRequest {
+            model: model.unwrap_or_default(),
+            messages,
+            tools,
+            max_tokens,
+            temperature,
+            response_schema,
+        })
+    }
+}
+
+impl From<ChatToolCall> for LLMToolCallRequest {
+    fn from(value: ChatToolCall) -> Self {
+        LLMToolCallRequest {
+            call_id: Some(value.id),
+            name: value.name,
+            args: value.arguments,
+            input: None,
+        }
+    }
+}
+
+impl From<ChatResponse> for LLMNodeResponse {
+    fn from(value: ChatResponse) -> Self {
+        let mut resp = LLMNodeResponse {
+            usage: value.usage,
+            ..Default::default()
+        };
+        if !value.model.is_empty() {
+            resp.model = Some(value.model);
+        }
+        if !value.content.is_empty() {
+            resp.answer = Some(Value::String(value.content));
+        }
+        if !value.finish_reason.is_empty() {
+            resp.finish_reason = Some(value.finish_reason);
+        }
+        if !value.tool_calls.is_empty() {
+            let tools = value.tool_calls.into_iter().map(|x| x.into()).collect();
+            resp.tools = Some(tools);
+        }
+        resp
+    }
+}
+
+#[async_trait::async_trait]
+impl agent_rt::ServiceLayer for LLMService {
+    type Config = CfgBound<LLMNodeRequest>;
+    type Output = LLMNodeResponse;
+
+    async fn call(
+        &self,
+        _code: String,
+        ctx: Arc<Context>,
+        cfg: Self::Config,
+    ) -> anyhow::Result<Self::Output> {
+        let cfg = cfg.bound(&ctx)?;
+        call_with_response_schema(cfg, |cfg| async {
+            let is_stream = cfg.is_stream;
+            //声明了response_schema时需要完整文本做校验，流式片段不再发送到channel
+            let to_channel = cfg.response_schema.is_none();
+            let (provider, req, report) = self.chat_request(cfg).await?;
+            let start = Instant::now();
+            let resp = self
+                .send(&ctx, provider.as_ref(), req, is_stream, to_channel)
+                .await?;
+            let mut resp = LLMNodeResponse::from(resp);
+            resp.latency_ms = Some(start.elapsed().as_millis() as u64);
+            resp.context_window = Some(report);
+            Ok(resp)
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::llm_provider::mock::MockServer;
+    use crate::llm_provider::{
+        ChatContentPart, ChatMessage, ChatRequest, ChatResponse, ChatToolCall, ChatUsage,
+        LLMClientProfile, LLMProvider, ModelPrice, ModelPricing,
+    };
+    use crate::rt_node_service::{
+        LLMContextMessage, LLMNodeRequest, LLMNodeResponse, LLMService, OpenaiLLMService,
+    };
+    use agent_rt::{Budget, PlanBuilder, Runtime};
+    use serde_json::Value;
+    use wd_tools::PFArc;
+
+    struct EchoProvider(&'static str);
+
+    #[async_trait::async_trait]
+    impl LLMProvider for EchoProvider {
+        fn name(&self) -> &str {
+            self.0
+        }
+        fn default_model(&self) -> &str {
+            "echo-model"
+        }
+        async fn chat(&self, req: ChatRequest) -> anyhow::Result<ChatResponse> {
+            let content = format!(
+                "{}:{}:{}",
+                self.0,
+                req.model,
+                req.messages.last().unwrap().content
+            );
+            Ok(ChatResponse {
+                model: req.model,
+                content,
+                finish_reason: "length".into(),
+                usage: Some(ChatUsage::new(3, 2)),
+                ..Default::default()
+            })
+        }
+    }
+
+    //cargo test rt_node_service::llm::test::test_llm_provider_select -- --nocapture
+    #[tokio::test]
+    async fn test_llm_provider_select() {
+        let llm = LLMService::new()
+            .register_provider("a", EchoProvider("a"))
+            .register_provider("b", EchoProvider("b"))
+            .default_provider("a");
+        let rt = Runtime::default()
+            .register_service_layer("llm", llm)
+            .launch();
+
+        for (cfg, expect) in [
+            (r#"{"query":"{{start.query}}"}"#, "a:echo-model:hi"),
+            (
+                r#"{"provider":"b","model":"m1","query":"{{start.query}}"}"#,
+                "b:m1:hi",
+            ),
+            (
+                r#"{"model":"gpt-3.5-turbo","query":"{{start.query}}"}"#,
+                "a:gpt-3.5-turbo:hi",
+            ),
+        ] {
+            let res = rt
+                .ctx(
+                    "test_llm_provider",
+                    PlanBuilder::single_node("llm", cfg).build(),
+                )
+                .arc()
+                .block_on::<Value, _>(serde_json::json!({"query":"hi"}))
+                .await
+                .unwrap();
+            let resp = serde_json::from_value::<LLMNodeResponse>(res).unwrap();
+            assert_eq!(Some(expect), resp.answer_text());
+        }
+
+        let res = rt
+            .ctx(
+                "test_llm_provider",
+                PlanBuilder::single_node("llm", r#"{"provider":"c","query":"hi"}"#).build(),
+            )
+            .arc()
+            .block_on::<Value, _>(())
+            .await;
+        assert!(format!("{:?}", res).contains("llm provider[c] not found"));
+    }
+
+    //cargo test rt_node_service::llm::test::test_llm_usage -- --nocapture
+    #[tokio::test]
+    async fn test_llm_usage() {
+        let rt = Runtime::default()
+            .register_service_layer(
+                "llm",
+                LLMService::new()
+                    .register_provider("openai", EchoProvider("a"))
+                    .pricing(ModelPricing::new().price("echo", ModelPrice::new(1000.0, 2000.0))),
+            )
+            .launch();
+        let plan = PlanBuilder::start(("a", "llm", r#"{"query":"hi"}"#), vec!["end"])
+            .sequence(vec![("end", "llm", r#"{"query":"{{a.model}}"}"#)], "")
+            .check_and_build()
+            .unwrap();
+        let ctx = rt.ctx("test_llm_usage", plan);
+        ctx.set_budget(Budget::new().max_tokens(100));
+        let ctx = ctx.arc();
+        let res = ctx.clone().block_on::<Value, _>(()).await.unwrap();
+        let resp = serde_json::from_value::<LLMNodeResponse>(res).unwrap();
+        assert_eq!(Some("a:echo-model:echo-model"), resp.answer_text());
+        assert_eq!(Some("echo-model"), resp.model.as_deref());
+        assert_eq!(Some("length"), resp.finish_reason.as_deref());
+        assert_eq!(Some(ChatUsage::new(3, 2)), resp.usage);
+        assert!(resp.latency_ms.is_some());
+        //两次调用的用量都计入ctx预算
+        assert_eq!(10, ctx.used_budget().tokens);
+        assert!((ctx.used_budget().cost - 0.014).abs() < 1e-9);
+
+        let ctx = rt.ctx(
+            "test_llm_usage_exceed",
+            PlanBuilder::single_node("llm", r#"{"query":"hi"}"#).build(),
+        );
+        ctx.set_budget(Budget::new().max_tokens(4));
+        let res = ctx.arc().block_on::<Value, _>(()).await;
+        assert!(format!("{:?}", res).contains("budget exceeded"));
+
+        let ctx = rt.ctx(
+            "test_llm_usage_cost",
+            PlanBuilder::single_node("llm", r#"{"query":"hi"}"#).build(),
+        );
+        ctx.set_budget(Budget::new().max_cost(0.005));
+        let res = ctx.arc().block_on::<Value, _>(()).await;
+        assert!(format!("{:?}", res).contains("cost used[0.007000]"));
+    }
+
+    //cargo test rt_node_service::llm::test::test_llm_profile -- --nocapture
+    #[tokio::test]
+    async fn test_llm_profile() {
+        let server = MockServer::start(vec![MockServer::json(serde_json::json!({
+            "model":"mock",
+            "choices":[{"index":0,"finish_reason":"stop","message":{"role":"assistant","content":"from profile"}}],
+            "usage":{"prompt_tokens":3,"completion_tokens":2,"total_tokens":5}
+        }))])
+        .await;
+        let llm = LLMService::new()
+            .register_provider("a", EchoProvider("a"))
+            .default_provider("a")
+            .register_profile(
+                "local",
+                LLMClientProfile::new(server.url.as_str(), "sk-local"),
+            )
+            .unwrap();
+        let rt = Runtime::default()
+            .register_service_layer("llm", llm)
+            .launch();
+
+        let res = rt
+            .ctx(
+                "test_llm_profile",
+                PlanBuilder::single_node("llm", r#"{"profile":"local","model":"m","query":"hi"}"#)
+                    .build(),
+            )
+            .arc()
+            .block_on::<Value, _>(())
+            .await
+            .unwrap();
+        let resp = serde_json::from_value::<LLMNodeResponse>(res).unwrap();
+        assert_eq!(Some("from profile"), resp.answer_text());
+        assert_eq!(
+            "Bearer sk-local",
+            server.requests()[0].header("authorization")
+        );
+
+        let res = rt
+            .ctx(
+                "test_llm_profile",
+                PlanBuilder::single_node("llm", r#"{"profile":"other","query":"hi"}"#).build(),
+            )
+            .arc()
+            .block_on::<Value, _>(())
+            .await;
+        assert!(format!("{:?}", res).contains("llm profile[other] not found"));
+    }
+
+    //cargo test rt_node_service::llm::test::test_llm_stream_schema -- --nocapture
+    #[tokio::test]
+    async fn test_llm_stream_schema() {
+        let chunk = |answer: &str| {
+            serde_json::json!({
+                "id":"chatcmpl-1",
+                "object":"chat.completion.chunk",
+                "created":1700000000,
+                "model":"mock",
+                "choices":[{"index":0,"delta":{"role":"assistant","content":answer},"finish_reason":"stop"}]
+            })
+            .to_string()
+        };
+        let (text, json) = (chunk("hello"), chunk(r#"{"city":"杭州"}"#));
+        let server = MockServer::start(vec![
+            MockServer::sse(vec![text.as_str(), "[DONE]"]),
+            MockServer::sse(vec![json.as_str(), "[DONE]"]),
+        ])
+        .await;
+        let llm = LLMService::new()
+            .register_profile(
+                "local",
+                LLMClientProfile::new(server.url.as_str(), "sk-local"),
+            )
+            .unwrap();
+        let rt = Runtime::default()
+            .register_service_layer("llm", llm)
+            .launch();
+
+        let mut ctx = rt.ctx(
+            "test_llm_stream",
+            PlanBuilder::single_node(
+                "llm",
+                r#"{"profile":"local","model":"m","is_stream":true,"query":"hi"}"#,
+            )
+            .build(),
+        );
+        OpenaiLLMService::set_channel_to_ctx(&mut ctx);
+        let ctx = ctx.arc();
+        ctx.clone().block_on::<Value, _>(()).await.unwrap();
+        assert_eq!(
+            Some("hello".to_string()),
+            OpenaiLLMService::try_recv_from_channel(&ctx)
+        );
+
+        let cfg = r#"{"profile":"local","model":"m","is_stream":true,"query":"hi","response_schema":{"type":"object","properties":{"city":{"type":"string"}},"required":["city"]}}"#;
+        let mut ctx = rt.ctx(
+            "test_llm_stream_schema",
+            PlanBuilder::single_node("llm", cfg).build(),
+        );
+        OpenaiLLMService::set_channel_to_ctx(&mut ctx);
+        let ctx = ctx.arc();
+        let res = ctx.clone().block_on::<Value, _>(()).await.unwrap();
+        let resp = serde_json::from_value::<LLMNodeResponse>(res).unwrap();
+        assert_eq!(Some(serde_json::json!({"city":"杭州"})), resp.answer);
+        //校验用的文本不会发送到channel
+        assert_eq!(
+            Some(String::new()),
+            OpenaiLLMService::try_recv_from_channel(&ctx)
+        );
+    }
+
+    //cargo test rt_node_service::llm::test::test_context_message_convert -- --nocapture
+    #[test]
+    fn test_context_message_convert() {
+        //旧格式：没有call_name时不带工具调用
+        let msg = serde_json::from_value::<LLMContextMessage>(serde_json::json!({
+            "role":"assistant","content":"hello","call_id":""
+        }))
+        .unwrap();
+        assert!(msg.to_provider_message().unwrap().tool_calls.is_empty());
+        let msg = serde_json::from_value::<LLMContextMessage>(serde_json::json!({
+            "role":"assistant","call_id":"c1","call_name":"search","call_args":"{}"
+        }))
+        .unwrap();
+        let chat = msg.to_provider_message().unwrap();
+        assert_eq!(
+            vec![ChatToolCall::new("c1", "search", "{}")],
+            chat.tool_calls
+        );
+        assert!(chat.tool_call_id.is_empty());
+
+        let list = vec![
+            ChatMessage::system("you are a bot"),
+            ChatMessage::user("look").name("tom").parts(vec![
+                ChatContentPart::image_url("https://example.com/a.png"),
+                ChatContentPart::image_base64("image/jpeg", "AA=="),
+            ]),
+            ChatMessage::assistant("").tool_calls(vec![
+                ChatToolCall::new("c1", "search", "{}"),
+                ChatToolCall::new("c2", "weather", "{}"),
+            ]),
+            ChatMessage::tool("c1", "result"),
+            ChatMessage::function("search", "result"),
+        ];
+        for chat in list {
+            let msg = LLMContextMessage::from(chat.clone());
+            let value = serde_json::to_value(&msg).unwrap();
+            let msg = serde_json::from_value::<LLMContextMessage>(value).unwrap();
+            assert_eq!(chat, msg.clone().to_provider_message().unwrap());
+            assert!(msg.to_chat_message().is_some());
+        }
+        let err = LLMContextMessage::new("bot", "hi")
+            .to_provider_message()
+            .unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("unknown context message role[bot]"));
+
+        //context中已有system消息时prompt合并进去
+        let req = serde_json::from_value::<LLMNodeRequest>(serde_json::json!({
+            "prompt":"answer in json",
+            "context":[{"role":"system","content":"you are a bot"}],
+            "query":"hi"
+        }))
+        .unwrap();
+        let req = req.to_chat_request().unwrap();
+        assert_eq!(2, req.messages.len());
+        assert_eq!("answer in json\n\nyou are a bot", req.messages[0].content);
+        let req = serde_json::from_value::<LLMNodeRequest>(serde_json::json!({
+            "context":[{"role":"bot","content":"hi"}],
+            "query":"hi"
+        }))
+        .unwrap();
+        assert!(req.to_chat_request().is_err());
+    }
+}
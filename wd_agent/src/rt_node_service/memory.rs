@@ -0,0 +1,581 @@
+use crate::llm_provider::{ChatMessage, ChatRequest, LLMProvider};
+use crate::rt_node_service::{CfgBound, LLMContextMessage};
+use agent_rt::Context;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use wd_tools::PFErr;
+
+//摘要以user消息的形式放在历史最前面，避免覆盖节点的system prompt
+pub const CHAT_SUMMARY_PREFIX: &str = "以下是之前对话的摘要:\n";
+
+#[async_trait::async_trait]
+pub trait ChatMemoryStore: Send + Sync {
+    async fn load(&self, session_id: &str) -> anyhow::Result<Vec<LLMContextMessage>>;
+    //整体覆盖保存
+    async fn save(&self, session_id: &str, history: Vec<LLMContextMessage>) -> anyhow::Result<()>;
+}
+
+#[async_trait::async_trait]
+pub trait ChatSummarizer: Send + Sync {
+    async fn summarize(&self, history: &[LLMContextMessage]) -> anyhow::Result<String>;
+}
+
+#[derive(Debug, Default)]
+pub struct MemoryChatStore {
+    map: Mutex<HashMap<String, Vec<LLMContextMessage>>>,
+}
+
+#[async_trait::async_trait]
+impl ChatMemoryStore for MemoryChatStore {
+    async fn load(&self, session_id: &str) -> anyhow::Result<Vec<LLMContextMessage>> {
+        let lock = self.map.lock().unwrap();
+        Ok(lock.get(session_id).cloned().unwrap_or_default())
+    }
+
+    async fn save(&self, session_id: &str, history: Vec<LLMContextMessage>) -> anyhow::Result<()> {
+        let mut lock = self.map.lock().unwrap();
+        lock.insert(session_id.to_string(), history);
+        Ok(())
+    }
+}
+
+//每个session一个json文件，重启后依然有效
+#[derive(Debug)]
+pub struct FileChatStore {
+    pub path: String,
+}
+
+impl Default for FileChatStore {
+    fn default() -> Self {
+        Self::new("./chat_memory")
+    }
+}
+
+impl FileChatStore {
+    pub fn new<P: Into<String>>(path: P) -> Self {
+        let path = path.into();
+        Self { path }
+    }
+    fn file_path(&self, session_id: &str) -> String {
+        format!("{}/{}.json", self.path, safe_file_name(session_id))
+    }
+}
+
+//字母数字和-_之外的字节按%XX编码，不会越出存储目录，不同名称也不会映射到同一个文件
+pub(crate) fn safe_file_name(name: &str) -> String {
+    let mut out = String::with_capacity(name.len());
+    for b in name.bytes() {
+        if b.is_ascii_alphanumeric() || b == b'-' || b == b'_' {
+            out.push(b as char);
+        } else {
+            out.push_str(format!("%{:02X}", b).as_str());
+        }
+    }
+    out
+}
+
+#[async_trait::async_trait]
+impl ChatMemoryStore for FileChatStore {
+    async fn load(&self, session_id: &str) -> anyhow::Result<Vec<LLMContextMessage>> {
+        let data = match tokio::fs::read(self.file_path(session_id)).await {
+            Ok(o) => o,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(anyhow::Error::from(e)),
+        };
+        let history = serde_json::from_slice(data.as_slice())?;
+        Ok(history)
+    }
+
+    async fn save(&self, session_id: &str, history: Vec<LLMContextMessage>) -> anyhow::Result<()> {
+        tokio::fs::create_dir_all(self.path.as_str()).await?;
+        let data = serde_json::to_vec(&history)?;
+        tokio::fs::write(self.file_path(session_id), data).await?;
+        Ok(())
+    }
+}
+
+//用llm把旧对话压缩成一段摘要
+pub struct LLMSummarizer {
+    provider: Arc<dyn LLMProvider>,
+    pub model: String,
+    pub prompt: String,
+}
+
+impl LLMSummarizer {
+    pub fn new<P: LLMProvider + 'static>(provider: P) -> Self {
+        Self {
+            model: provider.default_model().to_string(),
+            provider: Arc::new(provider),
+            prompt: "请将下面的对话压缩成一段简洁的摘要，保留用户的关键信息、偏好和未完成的事项。"
+                .into(),
+        }
+    }
+    pub fn model<S: Into<String>>(mut self, model: S) -> Self {
+        self.model = model.into();
+        self
+    }
+    pub fn prompt<S: Into<String>>(mut self, prompt: S) -> Self {
+        self.prompt = prompt.into();
+        self
+    }
+}
+
+#[async_trait::async_trait]
+impl ChatSummarizer for LLMSummarizer {
+    async fn summarize(&self, history: &[LLMContextMessage]) -> anyhow::Result<String> {
+        let transcript = history
+            .iter()
+            .filter(|x| !x.content.is_empty())
+            .map(|x| format!("{}: {}", x.role, x.content))
+            .collect::<Vec<_>>()
+            .join("\n");
+        let req = ChatRequest::new(self.model.as_str())
+            .message(ChatMessage::system(self.prompt.as_str()))
+            .message(ChatMessage::user(transcript));
+        let resp = self.provider.chat(req).await?;
+        Ok(resp.content)
+    }
+}
+
+//粗略估算token数，中日韩字符按一个token，其余按4个字符一个token
+pub fn estimate_tokens(text: &str) -> usize {
+    let mut cjk = 0;
+    let mut other = 0usize;
+    for c in text.chars() {
+        if ('\u{2e80}'..='\u{9fff}').contains(&c) || ('\u{ac00}'..='\u{d7af}').contains(&c) {
+            cjk += 1;
+        } else {
+            other += 1;
+        }
+    }
+    cjk + other.div_ceil(4)
+}
+
+fn message_tokens(msg: &LLMContextMessage) -> usize {
+    //每条消息的格式开销按4个token计算
+    let calls = msg
+        .all_tool_calls()
+        .iter()
+        .map(|x| estimate_tokens(x.arguments.as_str()))
+        .sum::<usize>();
+    4 + estimate_tokens(msg.content.as_str()) + calls
+}
+
+fn is_summary(msg: &LLMContextMessage) -> bool {
+    msg.role == "user" && msg.content.starts_with(CHAT_SUMMARY_PREFIX)
+}
+
+//保留最近的消息，返回(保留的,丢弃的)；0表示不限制
+//已有的摘要总是放在最前面，保留部分不会以孤立的tool消息开头
+pub fn trim_chat_history(
+    mut history: Vec<LLMContextMessage>,
+    max_messages: usize,
+    max_tokens: usize,
+) -> (Vec<LLMContextMessage>, Vec<LLMContextMessage>) {
+    let summary = if history.first().map(is_summary).unwrap_or(false) {
+        Some(history.remove(0))
+    } else {
+        None
+    };
+    let mut tokens = summary.as_ref().map(message_tokens).unwrap_or(0);
+    let mut start = history.len();
+    while start > 0 {
+        let msg = &history[start - 1];
+        let count = history.len() - start + 1;
+        if max_messages > 0 && count > max_messages {
+            break;
+        }
+        let t = message_tokens(msg);
+        if max_tokens > 0 && tokens + t > max_tokens {
+            break;
+        }
+        tokens += t;
+        start -= 1;
+    }
+    while start < history.len() && history[start].role == "tool" {
+        start += 1;
+    }
+    let kept = history.split_off(start);
+    let mut dropped = history;
+    let mut result = vec![];
+    if let Some(s) = summary {
+        //摘要也参与下一次压缩
+        if dropped.is_empty() {
+            result.push(s);
+        } else {
+            dropped.insert(0, s);
+        }
+    }
+    result.extend(kept);
+    (result, dropped)
+}
+
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+pub struct ChatMemoryLoadRequest {
+    pub session_id: String,
+    //保留的最大消息数，0不限制
+    #[serde(default = "usize::default")]
+    pub max_messages: usize,
+    //保留的最大token数，0不限制
+    #[serde(default = "usize::default")]
+    pub max_tokens: usize,
+    //是否把裁掉的旧消息压缩成摘要，需要配置summarizer，不会修改存储的历史
+    #[serde(default = "bool::default")]
+    pub summarize: bool,
+}
+
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+pub struct ChatMemoryLoadResponse {
+    pub context: Vec<LLMContextMessage>,
+}
+
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+pub struct ChatMemorySaveRequest {
+    pub session_id: String,
+    #[serde(default = "String::default")]
+    pub query: String,
+    //query与answer之间的消息，如工具调用
+    #[serde(default = "Vec::default")]
+    pub messages: Vec<LLMContextMessage>,
+    #[serde(default = "String::default")]
+    pub answer: String,
+    //存储的最大消息数，0不限制
+    #[serde(default = "usize::default")]
+    pub max_messages: usize,
+}
+
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+pub struct ChatMemorySaveResponse {
+    pub count: usize,
+}
+
+//读取会话历史，输出可直接用于LLMNodeRequest.context
+pub struct ChatMemoryLoadService {
+    store: Arc<dyn ChatMemoryStore>,
+    summarizer: Option<Arc<dyn ChatSummarizer>>,
+}
+
+//把本轮对话追加到会话历史，同一个session的读改写串行执行
+pub struct ChatMemorySaveService {
+    store: Arc<dyn ChatMemoryStore>,
+    locks: Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>,
+}
+
+impl ChatMemoryLoadService {
+    pub fn new(store: Arc<dyn ChatMemoryStore>) -> Self {
+        Self {
+            store,
+            summarizer: None,
+        }
+    }
+    pub fn summarizer<S: ChatSummarizer + 'static>(mut self, summarizer: S) -> Self {
+        self.summarizer = Some(Arc::new(summarizer));
+        self
+    }
+}
+
+impl ChatMemorySaveService {
+    pub fn new(store: Arc<dyn ChatMemoryStore>) -> Self {
+        let locks = Mutex::new(HashMap::new());
+        Self { store, locks }
+    }
+    async fn append(
+        &self,
+        session_id: &str,
+        turn: Vec<LLMContextMessage>,
+        max_messages: usize,
+    ) -> anyhow::Result<usize> {
+        let mut history = self.store.load(session_id).await?;
+        history.extend(turn);
+        let (history, _) = trim_chat_history(history, max_messages, 0);
+        let count = history.len();
+        self.store.save(session_id, history).await?;
+        Ok(count)
+    }
+}
+
+impl LLMContextMessage {
+    pub fn new<R: Into<String>, C: Into<String>>(role: R, content: C) -> Self {
+        Self {
+            role: role.into(),
+            content: content.into(),
+            ..Default::default()
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl agent_rt::ServiceLayer for ChatMemoryLoadService {
+    type Config = CfgBound<ChatMemoryLoadRequest>;
+    type Output = ChatMemoryLoadResponse;
+
+    async fn call(
+        &self,
+        _code: String,
+        ctx: Arc<Context>,
+        cfg: Self::Config,
+    ) -> anyhow::Result<Self::Output> {
+        let req = cfg.bound(&ctx)?;
+        if req.session_id.is_empty() {
+            return anyhow::anyhow!("ChatMemoryLoadService: session_id can not is nil").err();
+        }
+        if req.summarize && self.summarizer.is_none() {
+            return anyhow::anyhow!("ChatMemoryLoadService: summarize is set but no summarizer")
+                .err();
+        }
+        let history = self.store.load(req.session_id.as_str()).await?;
+        let (mut context, dropped) = trim_chat_history(history, req.max_messages, req.max_tokens);
+        if let (true, false, Some(summarizer)) =
+            (req.summarize, dropped.is_empty(), self.summarizer.as_ref())
+        {
+            let summary = summarizer.summarize(dropped.as_slice()).await?;
+            let content = format!("{}{}", CHAT_SUMMARY_PREFIX, summary);
+            context.insert(0, LLMContextMessage::new("user", content));
+        }
+        Ok(ChatMemoryLoadResponse { context })
+    }
+}
+
+#[async_trait::async_trait]
+impl agent_rt::ServiceLayer for ChatMemorySaveService {
+    type Config = CfgBound<ChatMemorySaveRequest>;
+    type Output = ChatMemorySaveResponse;
+
+    async fn call(
+        &self,
+        _code: String,
+        ctx: Arc<Context>,
+        cfg: Self::Config,
+    ) -> anyhow::Result<Self::Output> {
+        let ChatMemorySaveRequest {
+            session_id,
+            query,
+            messages,
+            answer,
+            max_messages,
+        } = cfg.bound(&ctx)?;
+        if session_id.is_empty() {
+            return anyhow::anyhow!("ChatMemorySaveService: session_id can not is nil").err();
+        }
+        let mut turn = vec![];
+        if !query.is_empty() {
+            turn.push(LLMContextMessage::new("user", query));
+        }
+        turn.extend(messages);
+        if !answer.is_empty() {
+            turn.push(LLMContextMessage::new("assistant", answer));
+        }
+
+        let lock = self
+            .locks
+            .lock()
+            .unwrap()
+            .entry(session_id.clone())
+            .or_default()
+            .clone();
+        let result = {
+            let _guard = lock.lock().await;
+            self.append(session_id.as_str(), turn, max_messages).await
+        };
+        //没有其他请求在等待时释放这个session的锁
+        let mut locks = self.locks.lock().unwrap();
+        if Arc::strong_count(&lock) == 2 {
+            locks.remove(session_id.as_str());
+        }
+        drop(locks);
+        let count = result?;
+        Ok(ChatMemorySaveResponse { count })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::rt_node_service::{
+        trim_chat_history, ChatMemoryLoadService, ChatMemorySaveService, ChatMemoryStore,
+        ChatSummarizer, FileChatStore, LLMContextMessage, MemoryChatStore, CHAT_SUMMARY_PREFIX,
+    };
+    use agent_rt::{Output, PlanBuilder, Runtime, END_NODE_CODE};
+    use serde_json::Value;
+    use std::sync::Arc;
+    use wd_tools::PFArc;
+
+    struct CountSummarizer;
+
+    #[async_trait::async_trait]
+    impl ChatSummarizer for CountSummarizer {
+        async fn summarize(&self, history: &[LLMContextMessage]) -> anyhow::Result<String> {
+            Ok(format!("{} messages", history.len()))
+        }
+    }
+
+    fn history(n: usize) -> Vec<LLMContextMessage> {
+        (0..n)
+            .map(|i| {
+                let role = if i % 2 == 0 { "user" } else { "assistant" };
+                LLMContextMessage::new(role, format!("msg{i}"))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_trim_chat_history() {
+        let (kept, dropped) = trim_chat_history(history(6), 4, 0);
+        assert_eq!(4, kept.len());
+        assert_eq!(2, dropped.len());
+        assert_eq!("msg2", kept[0].content.as_str());
+
+        let mut list = history(4);
+        list[1] = LLMContextMessage::new("tool", "result");
+        let (kept, _) = trim_chat_history(list, 3, 0);
+        assert_eq!(2, kept.len());
+        assert_eq!("msg2", kept[0].content.as_str());
+
+        let (kept, _) = trim_chat_history(history(6), 0, 12);
+        assert_eq!(2, kept.len());
+    }
+
+    //cargo test rt_node_service::memory::test::test_chat_memory -- --nocapture
+    #[tokio::test]
+    async fn test_chat_memory() {
+        let store: Arc<dyn ChatMemoryStore> = Arc::new(MemoryChatStore::default());
+        let rt = Runtime::default()
+            .register_service_layer(
+                "memory_load",
+                ChatMemoryLoadService::new(store.clone()).summarizer(CountSummarizer),
+            )
+            .register_service_layer("memory_save", ChatMemorySaveService::new(store.clone()))
+            .register_service_fn("llm", |f| async move {
+                let cfg: Value = serde_json::from_str(f.node_config.as_str())?;
+                let answer = format!("answer-{}", cfg["query"].as_str().unwrap_or(""));
+                Ok(Output::new(serde_json::json!({ "answer": answer })).raw_to_ctx())
+            })
+            .launch();
+
+        for i in 0..3 {
+            let plan = PlanBuilder::start(
+                (
+                    "load",
+                    "memory_load",
+                    r#"{"session_id":"{{start.session}}","max_messages":2,"summarize":true}"#,
+                ),
+                vec!["llm"],
+            )
+            .sequence(
+                vec![
+                    ("llm", "llm", r#"{"query":"q"}"#),
+                    (
+                        END_NODE_CODE,
+                        "memory_save",
+                        r#"{"session_id":"{{start.session}}","query":"q","answer":"{{llm.answer}}"}"#,
+                    ),
+                ],
+                "",
+            )
+            .check_and_build()
+            .unwrap();
+            let res = rt
+                .ctx(format!("test_chat_memory_{i}"), plan)
+                .arc()
+                .block_on::<Value, _>(serde_json::json!({"session":"s1"}))
+                .await
+                .unwrap();
+            assert!(res["count"].as_u64().unwrap() > 0);
+        }
+
+        //加载不修改历史，保存时按max_messages裁剪
+        let saved = store.load("s1").await.unwrap();
+        println!("{:?}", saved);
+        assert_eq!(6, saved.len());
+        assert_eq!("answer-q", saved[5].content.as_str());
+
+        let load = |cfg: &'static str| {
+            let rt = rt.clone();
+            async move {
+                rt.ctx(
+                    "test_chat_memory_load",
+                    PlanBuilder::single_node("memory_load", cfg).build(),
+                )
+                .arc()
+                .block_on::<Value, _>(serde_json::json!({}))
+                .await
+            }
+        };
+        let res = load(r#"{"session_id":"s1","max_messages":2,"summarize":true}"#)
+            .await
+            .unwrap();
+        let context = res["context"].as_array().unwrap();
+        assert_eq!(3, context.len());
+        assert_eq!(
+            format!("{}4 messages", CHAT_SUMMARY_PREFIX),
+            context[0]["content"].as_str().unwrap()
+        );
+        assert_eq!(6, store.load("s1").await.unwrap().len());
+
+        //没有summarizer时要求摘要直接报错
+        let rt = Runtime::default()
+            .register_service_layer("memory_load", ChatMemoryLoadService::new(store.clone()))
+            .launch();
+        let err = rt
+            .ctx(
+                "test_chat_memory_no_summarizer",
+                PlanBuilder::single_node(
+                    "memory_load",
+                    r#"{"session_id":"s1","max_messages":2,"summarize":true}"#,
+                )
+                .build(),
+            )
+            .arc()
+            .block_on::<Value, _>(serde_json::json!({}))
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("no summarizer"));
+
+        let path = std::env::temp_dir().join("wd_agent_chat_memory_test");
+        let _ = std::fs::remove_dir_all(&path);
+        let file = FileChatStore::new(path.to_string_lossy().to_string());
+        file.save("a/b", saved.clone()).await.unwrap();
+        let file = FileChatStore::new(path.to_string_lossy().to_string());
+        assert_eq!(saved, file.load("a/b").await.unwrap());
+        assert_eq!(0, file.load("a_b").await.unwrap().len());
+        assert_eq!(0, file.load("a.b").await.unwrap().len());
+        assert_eq!(0, file.load("none").await.unwrap().len());
+        let _ = std::fs::remove_dir_all(&path);
+    }
+
+    //cargo test rt_node_service::memory::test::test_chat_memory_concurrent_save -- --nocapture
+    #[tokio::test]
+    async fn test_chat_memory_concurrent_save() {
+        let path = std::env::temp_dir().join("wd_agent_chat_memory_concurrent_test");
+        let _ = std::fs::remove_dir_all(&path);
+        let store: Arc<dyn ChatMemoryStore> =
+            Arc::new(FileChatStore::new(path.to_string_lossy().to_string()));
+        let rt = Runtime::default()
+            .register_service_layer("memory_save", ChatMemorySaveService::new(store.clone()))
+            .launch();
+
+        let mut tasks = vec![];
+        for i in 0..20 {
+            let rt = rt.clone();
+            tasks.push(tokio::spawn(async move {
+                rt.ctx(
+                    format!("test_chat_memory_concurrent_{i}"),
+                    PlanBuilder::single_node(
+                        "memory_save",
+                        r#"{"session_id":"s1","query":"q","answer":"a"}"#,
+                    )
+                    .build(),
+                )
+                .arc()
+                .block_on::<Value, _>(serde_json::json!({}))
+                .await
+                .unwrap()
+            }));
+        }
+        for task in tasks {
+            task.await.unwrap();
+        }
+        assert_eq!(40, store.load("s1").await.unwrap().len());
+        let _ = std::fs::remove_dir_all(&path);
+    }
+}
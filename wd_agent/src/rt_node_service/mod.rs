@@ -1,18 +1,44 @@
+mod agent;
+mod context_window;
+mod document;
+mod embedding;
+mod expression;
+mod http_request;
+mod llm;
+mod memory;
 mod openai_llm;
 mod tool;
 #[macro_use]
 mod in_out_bonding;
 mod injector;
 mod python;
+mod rhai_script;
 mod selector;
+mod structured_output;
+mod template;
 mod var;
+mod vector_store;
 mod workflow;
+mod workflow_registry;
 
+pub use agent::*;
+pub use context_window::*;
+pub use document::*;
+pub use embedding::*;
+pub use expression::*;
+pub use http_request::*;
 pub use in_out_bonding::*;
 pub use injector::*;
+pub use llm::*;
+pub use memory::*;
 pub use openai_llm::*;
 pub use python::*;
+pub use rhai_script::*;
 pub use selector::*;
+pub use structured_output::*;
+pub use template::*;
 pub use tool::*;
 pub use var::*;
+pub use vector_store::*;
 pub use workflow::*;
+pub use workflow_registry::*;
@@ -1,45 +1,90 @@
 #![allow(deprecated)]
-use crate::rt_node_service::{CfgBound, LLMToolCallRequest};
+use crate::llm_provider::{
+    read_json, ChatContentPart, ChatRole, ChatToolCall, ChatUsage, LLMClientProfile, LineReader,
+    ModelPricing, OpenAICompatibleProvider,
+};
+use crate::rt_node_service::{
+    call_with_response_schema, CfgBound, ContextWindowReport, LLMToolCallRequest,
+};
 use agent_rt::Context;
-use async_openai::config::OpenAIConfig;
+use async_openai::config::{Config, OpenAIConfig};
 use async_openai::types::{
     ChatCompletionMessageToolCall, ChatCompletionMessageToolCallChunk,
-    ChatCompletionRequestAssistantMessage, ChatCompletionRequestMessage,
-    ChatCompletionRequestSystemMessage, ChatCompletionRequestSystemMessageArgs,
-    ChatCompletionRequestToolMessage, ChatCompletionRequestUserMessage,
-    ChatCompletionRequestUserMessageArgs, ChatCompletionRequestUserMessageContent,
-    ChatCompletionTool, ChatCompletionToolType, CreateChatCompletionRequest,
-    CreateChatCompletionRequestArgs, FunctionCall, Role,
+    ChatCompletionRequestAssistantMessage, ChatCompletionRequestFunctionMessage,
+    ChatCompletionRequestMessage, ChatCompletionRequestSystemMessage,
+    ChatCompletionRequestSystemMessageArgs, ChatCompletionRequestToolMessage,
+    ChatCompletionRequestUserMessage, ChatCompletionRequestUserMessageArgs,
+    ChatCompletionRequestUserMessageContent, ChatCompletionResponseFormat,
+    ChatCompletionResponseFormatType, ChatCompletionTool, ChatCompletionToolType,
+    CreateChatCompletionRequest, CreateChatCompletionRequestArgs, FunctionCall, Role,
 };
-use async_openai::Client;
-use futures::StreamExt;
 use serde::{Deserialize, Serialize};
-use std::collections::VecDeque;
+use serde_json::Value;
+use std::collections::{HashMap, VecDeque};
 use std::sync::Arc;
+use std::time::Instant;
 use wd_tools::PFErr;
 
 #[derive(Debug)]
 pub struct OpenaiLLMService {
-    openai_client: Client<OpenAIConfig>,
+    openai_client: OpenaiClient,
+    profiles: HashMap<String, OpenaiClient>,
+    //是否支持json_schema类型的response_format，不支持时退化为json_object
+    pub json_schema_format: bool,
+    pricing: ModelPricing,
+}
+
+//请求直接走http，以便拿到refusal等新字段
+#[derive(Debug, Clone)]
+pub struct OpenaiClient {
+    pub config: OpenAIConfig,
+    pub http: reqwest::Client,
 }
 
 #[derive(Debug, Default, Clone, Deserialize, Serialize)]
 pub struct LLMNodeRequest {
     #[serde(default = "String::default")]
     pub prompt: String,
-    #[serde(default = "LLMNodeRequest::default_model_35")]
-    pub model: String,
+    //为空时openai_llm使用gpt-3.5-turbo，llm节点使用厂商的默认模型
+    #[serde(default = "Option::default")]
+    pub model: Option<String>,
     #[serde(default = "Vec::default")]
     pub tools: Vec<ChatCompletionTool>,
     #[serde(default = "Vec::default")]
     pub context: Vec<LLMContextMessage>,
 
     #[serde(default = "LLMNodeRequest::max_tokens_length")]
-    pub max_tokens: u16,
+    pub max_tokens: u32,
     #[serde(default = "LLMNodeRequest::default_temperature")]
     pub temperature: f32,
     #[serde(default = "bool::default")]
     pub is_stream: bool,
+    //返回的候选答案个数，仅非流式有效
+    #[serde(default = "Option::default")]
+    pub n: Option<u8>,
+    #[serde(default = "bool::default")]
+    pub logprobs: bool,
+    #[serde(default = "Option::default")]
+    pub top_logprobs: Option<u8>,
+    //LLMService按名称选择模型厂商，为空使用默认厂商
+    #[serde(default = "String::default")]
+    pub provider: String,
+    //按名称选择客户端配置，为空时OpenaiLLMService使用环境变量中的配置，LLMService使用provider
+    //LLMService中profile优先于provider，按openai兼容协议请求
+    #[serde(default = "String::default")]
+    pub profile: String,
+    //声明后回答必须是符合该json schema的json，answer返回解析后的对象
+    #[serde(default = "Option::default")]
+    pub response_schema: Option<Value>,
+    //回答不符合schema时，带着校验错误重新请求的次数
+    #[serde(default = "LLMNodeRequest::default_response_retries")]
+    pub response_retries: u8,
+    //上下文窗口大小，为0时按模型决定；超出时从最早的消息开始裁剪
+    #[serde(default = "usize::default")]
+    pub context_window: usize,
+    //裁剪掉的消息由服务配置的摘要器压缩成一条摘要
+    #[serde(default = "bool::default")]
+    pub summarize_context: bool,
 
     pub query: String,
 }
@@ -49,116 +94,306 @@ pub struct LLMNodeRequest {
 pub struct LLMContextMessage {
     pub role: String,
     pub content: String,
-
+    //跟在content文本之后的图片等内容
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub parts: Vec<ChatContentPart>,
+    //参与者名称，function消息为函数名
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub name: String,
+    //assistant一次发起的全部工具调用
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tool_calls: Vec<ChatToolCall>,
+    //tool消息对应的调用id
+    #[serde(skip_serializing_if = "String::is_empty")]
     pub call_id: String,
+    //旧格式的单个工具调用，读取时与tool_calls合并
+    #[serde(skip_serializing_if = "String::is_empty")]
     pub call_name: String,
+    #[serde(skip_serializing_if = "String::is_empty")]
     pub call_args: String,
+    //裁剪上下文窗口时不会被丢弃，如agent当前轮的用户问题
+    #[serde(skip)]
+    pub pinned: bool,
 }
 
 impl LLMContextMessage {
+    pub fn pin(mut self) -> Self {
+        self.pinned = true;
+        self
+    }
+    //角色名不区分大小写
+    pub fn is_role(&self, role: ChatRole) -> bool {
+        ChatRole::from_name(self.role.as_str()) == Some(role)
+    }
+    //call_name不为空时，旧格式的调用追加在tool_calls之后
+    pub fn all_tool_calls(&self) -> Vec<ChatToolCall> {
+        let mut list = self.tool_calls.clone();
+        if !self.call_name.is_empty() {
+            list.push(ChatToolCall::new(
+                self.call_id.as_str(),
+                self.call_name.as_str(),
+                self.call_args.as_str(),
+            ));
+        }
+        list
+    }
+    //兼容旧接口，无法转换的消息返回None
     pub fn to_chat_message(self) -> Option<ChatCompletionRequestMessage> {
-        let Self {
-            role,
-            content,
-            call_id,
-            call_name,
-            call_args,
-        } = self;
-        let msg = match role.to_lowercase().as_str() {
-            "system" => ChatCompletionRequestMessage::System(ChatCompletionRequestSystemMessage {
-                content,
-                role: Role::System,
-                name: None,
-            }),
-            "assistant" => {
+        match self.try_to_chat_message() {
+            Ok(o) => o,
+            Err(e) => {
+                wd_log::log_warn_ln!("LLMContextMessage.to_chat_message error:{}", e);
+                None
+            }
+        }
+    }
+    pub fn try_to_chat_message(self) -> anyhow::Result<Option<ChatCompletionRequestMessage>> {
+        let msg = self.to_provider_message()?;
+        let name = if msg.name.is_empty() {
+            None
+        } else {
+            Some(msg.name.clone())
+        };
+        let msg = match msg.role {
+            ChatRole::System => {
+                ChatCompletionRequestMessage::System(ChatCompletionRequestSystemMessage {
+                    content: msg.text(),
+                    role: Role::System,
+                    name,
+                })
+            }
+            ChatRole::User => {
+                let content = if msg.parts.is_empty() {
+                    ChatCompletionRequestUserMessageContent::Text(msg.content)
+                } else {
+                    let mut content = OpenAICompatibleProvider::content_to_json(&msg);
+                    //async_openai要求image_url必须带detail
+                    for part in content.as_array_mut().into_iter().flatten() {
+                        if let Some(image) = part.get_mut("image_url") {
+                            if image.get("detail").is_none() {
+                                image["detail"] = Value::from("auto");
+                            }
+                        }
+                    }
+                    serde_json::from_value(content)?
+                };
+                ChatCompletionRequestMessage::User(ChatCompletionRequestUserMessage {
+                    content,
+                    role: Role::User,
+                    name,
+                })
+            }
+            ChatRole::Assistant => {
+                let content = msg.text();
                 let content = if content.is_empty() {
                     None
                 } else {
                     Some(content)
                 };
+                //没有工具调用时不能带空的tool_calls
+                let tool_calls = if msg.tool_calls.is_empty() {
+                    None
+                } else {
+                    let list = msg
+                        .tool_calls
+                        .into_iter()
+                        .map(|x| ChatCompletionMessageToolCall {
+                            id: x.id,
+                            r#type: ChatCompletionToolType::Function,
+                            function: FunctionCall {
+                                name: x.name,
+                                arguments: x.arguments,
+                            },
+                        })
+                        .collect();
+                    Some(list)
+                };
                 ChatCompletionRequestMessage::Assistant(ChatCompletionRequestAssistantMessage {
                     content,
                     role: Role::Assistant,
-                    name: None,
-                    tool_calls: Option::from(vec![ChatCompletionMessageToolCall {
-                        id: call_id,
-                        r#type: ChatCompletionToolType::Function,
-                        function: FunctionCall {
-                            name: call_name,
-                            arguments: call_args,
-                        },
-                    }]),
-
+                    name,
+                    tool_calls,
                     function_call: None,
                 })
             }
-            "user" => ChatCompletionRequestMessage::User(ChatCompletionRequestUserMessage {
-                content: ChatCompletionRequestUserMessageContent::Text(content),
-                role: Role::User,
-                name: None,
-            }),
-            "tool" => ChatCompletionRequestMessage::Tool(ChatCompletionRequestToolMessage {
-                role: Role::Tool,
-                content,
-                tool_call_id: call_id,
-            }),
-            "function" => return None,
-            _ => return None,
+            ChatRole::Tool => {
+                ChatCompletionRequestMessage::Tool(ChatCompletionRequestToolMessage {
+                    role: Role::Tool,
+                    content: msg.text(),
+                    tool_call_id: msg.tool_call_id,
+                })
+            }
+            ChatRole::Function => {
+                ChatCompletionRequestMessage::Function(ChatCompletionRequestFunctionMessage {
+                    role: Role::Function,
+                    content: Some(msg.text()),
+                    name: msg.name,
+                })
+            }
         };
-        Some(msg)
+        Ok(Some(msg))
+    }
+    //按openai的消息格式解析，与try_to_chat_message互逆
+    pub fn from_chat_message(msg: &ChatCompletionRequestMessage) -> anyhow::Result<Self> {
+        let value = serde_json::to_value(msg)?;
+        let msg = OpenAICompatibleProvider::message_from_json(&value)?;
+        Ok(msg.into())
     }
 }
 
 impl LLMNodeRequest {
-    fn max_tokens_length() -> u16 {
+    fn max_tokens_length() -> u32 {
         1024
     }
     fn default_temperature() -> f32 {
         0.7f32
     }
-    fn default_model_35() -> String {
+    pub(crate) fn default_model_35() -> String {
         "gpt-3.5-turbo".into()
     }
+    pub fn model_name(&self) -> &str {
+        self.model.as_deref().unwrap_or("gpt-3.5-turbo")
+    }
+    fn default_response_retries() -> u8 {
+        2
+    }
 }
 
 #[derive(Debug, Default, Clone, Deserialize, Serialize)]
 pub struct LLMNodeResponse {
+    //声明了response_schema时为解析后的json，否则为文本
+    pub answer: Option<Value>,
+    pub tools: Option<Vec<LLMToolCallRequest>>,
+    pub finish_reason: Option<String>,
+    //模型拒绝回答时的说明
+    pub refusal: Option<String>,
+    pub logprobs: Option<Value>,
+    //实际响应的模型
+    pub model: Option<String>,
+    pub usage: Option<ChatUsage>,
+    //请求耗时，毫秒
+    pub latency_ms: Option<u64>,
+    //上下文窗口裁剪情况
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub context_window: Option<ContextWindowReport>,
+    //n>1时的全部候选，第一个候选同时展开在上面的字段中
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub choices: Vec<LLMNodeChoice>,
+}
+
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct LLMNodeChoice {
+    pub index: u32,
     pub answer: Option<String>,
     pub tools: Option<Vec<LLMToolCallRequest>>,
+    pub finish_reason: Option<String>,
+    pub refusal: Option<String>,
+    pub logprobs: Option<Value>,
+}
+
+impl LLMNodeChoice {
+    fn from_openai(value: &Value) -> Self {
+        let text = |v: Option<&Value>| v.and_then(|x| x.as_str()).map(|x| x.to_string());
+        let msg = value.get("message").cloned().unwrap_or_default();
+        let tools = msg
+            .get("tool_calls")
+            .and_then(|x| x.as_array())
+            .map(|list| {
+                list.iter()
+                    .map(|i| LLMToolCallRequest {
+                        call_id: text(i.get("id")),
+                        name: text(i.pointer("/function/name")).unwrap_or_default(),
+                        args: text(i.pointer("/function/arguments")).unwrap_or_default(),
+                        input: None,
+                    })
+                    .collect::<Vec<_>>()
+            });
+        Self {
+            index: value.get("index").and_then(|x| x.as_u64()).unwrap_or(0) as u32,
+            answer: text(msg.get("content")),
+            tools: tools.filter(|x| !x.is_empty()),
+            finish_reason: text(value.get("finish_reason")),
+            refusal: text(msg.get("refusal")),
+            logprobs: value.get("logprobs").filter(|x| !x.is_null()).cloned(),
+        }
+    }
 }
 
 impl LLMNodeResponse {
+    //解析完整的chat completion响应
+    pub fn from_openai_completion(value: Value) -> anyhow::Result<Self> {
+        if let Some(e) = value.get("error") {
+            return anyhow::anyhow!("openai chat completion error:{}", e).err();
+        }
+        let mut choices = match value.get("choices") {
+            Some(Value::Array(list)) => list
+                .iter()
+                .map(LLMNodeChoice::from_openai)
+                .collect::<Vec<_>>(),
+            _ => return anyhow::anyhow!("openai response no choices:{}", value).err(),
+        };
+        choices.sort_by_key(|x| x.index);
+        let mut resp = LLMNodeResponse {
+            model: value
+                .get("model")
+                .and_then(|x| x.as_str())
+                .map(|x| x.to_string()),
+            usage: OpenAICompatibleProvider::parse_usage(&value),
+            ..Default::default()
+        };
+        if let Some(first) = choices.first().cloned() {
+            resp.answer = first.answer.map(Value::String);
+            resp.tools = first.tools;
+            resp.finish_reason = first.finish_reason;
+            resp.refusal = first.refusal;
+            resp.logprobs = first.logprobs;
+        }
+        if choices.len() > 1 {
+            resp.choices = choices;
+        }
+        Ok(resp)
+    }
     pub fn append_answer(&mut self, msg: &str) {
         if self.answer.is_none() {
-            self.answer = Some("".into())
+            self.answer = Some(Value::String("".into()))
         }
-        if let Some(ref mut s) = self.answer {
+        if let Some(Value::String(ref mut s)) = self.answer {
             s.push_str(msg)
         }
     }
+    pub fn answer_text(&self) -> Option<&str> {
+        self.answer.as_ref().and_then(|x| x.as_str())
+    }
+    //上报token用量和费用到ctx的预算统计
+    pub fn report_usage(&self, ctx: &Context, pricing: &ModelPricing) -> anyhow::Result<()> {
+        if let Some(ref usage) = self.usage {
+            let model = self.model.as_deref().unwrap_or_default();
+            ctx.report_usage(pricing.usage(model, usage))?;
+        }
+        Ok(())
+    }
+    //流式的工具调用分片按index拼接，并行的多个调用互不干扰
     pub fn append_tools(&mut self, tools: Vec<ChatCompletionMessageToolCallChunk>) {
         if tools.is_empty() {
             return;
         }
-        if self.tools.is_none() {
-            self.tools = Some(vec![])
-        }
+        let vec = self.tools.get_or_insert_with(Vec::new);
         for i in tools {
-            if let Some(ref mut vec) = self.tools {
-                let id = i.id;
-                if let Some(fcs) = i.function {
-                    if fcs.name.is_some() {
-                        vec.push(LLMToolCallRequest {
-                            call_id: id,
-                            name: fcs.name.unwrap(),
-                            args: fcs.arguments.unwrap_or("".into()),
-                            input: None,
-                        })
-                    } else {
-                        if let Some(t) = vec.last_mut() {
-                            t.args.push_str(fcs.arguments.unwrap_or("".into()).as_str());
-                        }
-                    }
+            let index = usize::try_from(i.index).unwrap_or(0);
+            while vec.len() <= index {
+                vec.push(LLMToolCallRequest::default());
+            }
+            let call = &mut vec[index];
+            if let Some(id) = i.id {
+                call.call_id = Some(id);
+            }
+            if let Some(f) = i.function {
+                if let Some(name) = f.name {
+                    call.name.push_str(name.as_str());
+                }
+                if let Some(args) = f.arguments {
+                    call.args.push_str(args.as_str());
                 }
             }
         }
@@ -175,35 +410,25 @@ impl LLMNodeRequest {
             query,
             max_tokens,
             temperature,
+            n,
+            logprobs,
+            top_logprobs,
+            response_schema,
             ..
         } = self;
 
         let mut msg_list = vec![];
         for i in context {
-            if let Some(s) = i.to_chat_message() {
+            if let Some(s) = i.try_to_chat_message()? {
                 msg_list.push(s);
             }
         }
         let mut context = msg_list;
 
         if !prompt.is_empty() {
-            if let Some(p) = context.get_mut(0) {
-                if let ChatCompletionRequestMessage::System(_pe) = p {
-                    // pe.content = {
-                    //     prompt.push_str("\r\n");
-                    //     prompt.push_str(pe.content.as_str());
-                    //     prompt
-                    // }
-                } else {
-                    context.insert(
-                        0,
-                        ChatCompletionRequestSystemMessageArgs::default()
-                            .content(prompt)
-                            .build()
-                            .unwrap()
-                            .into(),
-                    )
-                }
+            if let Some(ChatCompletionRequestMessage::System(pe)) = context.get_mut(0) {
+                //已有system消息时合并，不丢弃节点的prompt
+                pe.content = format!("{}\n\n{}", prompt, pe.content);
             } else {
                 context.insert(
                     0,
@@ -228,26 +453,92 @@ impl LLMNodeRequest {
 
         let mut req = CreateChatCompletionRequestArgs::default();
 
+        //openai接口的max_tokens为u16，超出时报错而不是静默截断
+        let Ok(max_tokens) = u16::try_from(max_tokens) else {
+            return anyhow::anyhow!("max_tokens[{}] exceeds {}", max_tokens, u16::MAX).err();
+        };
         req.max_tokens(max_tokens);
         req.temperature(temperature);
-        req.model(model);
+        req.model(model.unwrap_or_else(Self::default_model_35));
         req.messages(context);
         if !tools.is_empty() {
             req.tools(tools);
         }
+        if let Some(n) = n {
+            req.n(n);
+        }
+        if logprobs {
+            req.logprobs(true);
+            if let Some(top) = top_logprobs {
+                req.top_logprobs(top);
+            }
+        }
+        //async_openai不支持json_schema，这里先打开json模式，由chat_once改写请求体
+        if response_schema.is_some() {
+            req.response_format(ChatCompletionResponseFormat {
+                r#type: ChatCompletionResponseFormatType::JsonObject,
+            });
+        }
         let req = req.build()?;
         Ok(req)
     }
 }
 impl Default for OpenaiLLMService {
     fn default() -> Self {
-        let openai_client = Client::new();
-        Self { openai_client }
+        let config = OpenAIConfig::default();
+        let openai_client = OpenaiClient {
+            config,
+            http: reqwest::Client::new(),
+        };
+        let profiles = HashMap::new();
+        Self {
+            openai_client,
+            profiles,
+            json_schema_format: true,
+            pricing: ModelPricing::default(),
+        }
     }
 }
 impl OpenaiLLMService {
+    pub fn json_schema_format(mut self, enable: bool) -> Self {
+        self.json_schema_format = enable;
+        self
+    }
+    pub fn pricing(mut self, pricing: ModelPricing) -> Self {
+        self.pricing = pricing;
+        self
+    }
+    pub fn register_profile<S: Into<String>>(
+        mut self,
+        name: S,
+        profile: LLMClientProfile,
+    ) -> anyhow::Result<Self> {
+        let mut config = OpenAIConfig::new();
+        if !profile.base_url.is_empty() {
+            config = config.with_api_base(profile.base_url.as_str());
+        }
+        if !profile.api_key.is_empty() {
+            config = config.with_api_key(profile.api_key.as_str());
+        }
+        if !profile.org.is_empty() {
+            config = config.with_org_id(profile.org.as_str());
+        }
+        let http = profile.http_client()?;
+        let client = OpenaiClient { config, http };
+        self.profiles.insert(name.into(), client);
+        Ok(self)
+    }
+    pub fn client(&self, profile: &str) -> anyhow::Result<&OpenaiClient> {
+        if profile.is_empty() {
+            return Ok(&self.openai_client);
+        }
+        match self.profiles.get(profile) {
+            Some(s) => Ok(s),
+            None => anyhow::anyhow!("openai client profile[{}] not found", profile).err(),
+        }
+    }
     pub fn check(req: &LLMNodeRequest) -> anyhow::Result<()> {
-        if req.model.is_empty() {
+        if req.model.as_deref() == Some("") {
             return anyhow::anyhow!("module can not is nil").err();
         }
         Ok(())
@@ -284,6 +575,85 @@ impl OpenaiLLMService {
     }
 }
 
+impl OpenaiClient {
+    async fn post(&self, body: &Value) -> anyhow::Result<reqwest::Response> {
+        let resp = self
+            .http
+            .post(self.config.url("/chat/completions"))
+            .headers(self.config.headers())
+            .query(&self.config.query())
+            .header("Content-Type", "application/json")
+            .body(serde_json::to_vec(body)?)
+            .send()
+            .await?;
+        let status = resp.status();
+        if !status.is_success() {
+            let text = resp.text().await.unwrap_or_default();
+            return anyhow::anyhow!("openai chat failed status[{}]:{}", status, text).err();
+        }
+        Ok(resp)
+    }
+    pub async fn chat(&self, body: &Value) -> anyhow::Result<LLMNodeResponse> {
+        let resp = self.post(body).await?;
+        LLMNodeResponse::from_openai_completion(read_json(resp).await?)
+    }
+    //流式请求，to_channel为true时文本片段发送到ctx的channel，否则拼接到answer
+    pub async fn chat_stream(
+        &self,
+        ctx: &Context,
+        mut body: Value,
+        to_channel: bool,
+    ) -> anyhow::Result<LLMNodeResponse> {
+        body["stream"] = Value::Bool(true);
+        //流式响应默认不带用量，需要显式要求最后一个分片带上
+        body["stream_options"] = serde_json::json!({"include_usage":true});
+        let mut reader = LineReader::new(self.post(&body).await?);
+
+        let mut resp = LLMNodeResponse::default();
+        while let Some(data) = reader.next_sse_data().await? {
+            if data == "[DONE]" {
+                break;
+            }
+            let chunk = serde_json::from_str::<Value>(data.as_str())?;
+            if let Some(e) = chunk.get("error") {
+                return anyhow::anyhow!("openai chat stream error:{}", e).err();
+            }
+            if resp.model.is_none() {
+                resp.model = chunk
+                    .get("model")
+                    .and_then(|x| x.as_str())
+                    .map(|x| x.to_string());
+            }
+            if let Some(usage) = OpenAICompatibleProvider::parse_usage(&chunk) {
+                resp.usage = Some(usage);
+            }
+            let Some(Value::Array(choices)) = chunk.get("choices") else {
+                continue;
+            };
+            for i in choices {
+                //文本消息
+                if let Some(s) = i.pointer("/delta/content").and_then(|x| x.as_str()) {
+                    if !to_channel {
+                        resp.append_answer(s);
+                    } else if let Some(s) =
+                        OpenaiLLMService::try_send_to_channel(ctx, s.to_string())
+                    {
+                        resp.append_answer(s.as_str());
+                    }
+                }
+                //工具调用
+                if let Some(tools) = i.pointer("/delta/tool_calls") {
+                    resp.append_tools(serde_json::from_value(tools.clone())?);
+                }
+                if let Some(reason) = i.get("finish_reason").and_then(|x| x.as_str()) {
+                    resp.finish_reason = Some(reason.to_string());
+                }
+            }
+        }
+        Ok(resp)
+    }
+}
+
 #[async_trait::async_trait]
 impl agent_rt::ServiceLayer for OpenaiLLMService {
     type Config = CfgBound<LLMNodeRequest>;
@@ -297,36 +667,50 @@ impl agent_rt::ServiceLayer for OpenaiLLMService {
     ) -> anyhow::Result<Self::Output> {
         // wd_log::log_debug_ln!("start call code[{}.{}.openai_llm]",ctx.code,code);
         let cfg = cfg.bound(&ctx)?;
-        let req = cfg.to_openai_chat_request()?;
-        let mut stream = self.openai_client.chat().create_stream(req).await?;
+        let resp = call_with_response_schema(cfg, |cfg| self.chat_once(&ctx, cfg)).await?;
+        // wd_log::log_debug_ln!("over call code[{}.{}.openai_llm]",ctx.code,code);
+        Ok(resp)
+    }
+}
 
-        let mut resp = LLMNodeResponse::default();
-        while let Some(msg) = stream.next().await {
-            let msg = match msg {
-                Ok(o) => o,
-                Err(e) => return Err(anyhow::Error::from(e)),
-            };
-            for i in msg.choices {
-                //文本消息
-                if let Some(s) = i.delta.content {
-                    if let Some(s) = Self::try_send_to_channel(&ctx, s) {
-                        resp.append_answer(s.as_str());
-                    }
-                }
-                //工具调用
-                if let Some(tools) = i.delta.tool_calls {
-                    resp.append_tools(tools);
-                }
+impl OpenaiLLMService {
+    async fn chat_once(
+        &self,
+        ctx: &Arc<Context>,
+        mut cfg: LLMNodeRequest,
+    ) -> anyhow::Result<LLMNodeResponse> {
+        let client = self.client(cfg.profile.as_str())?;
+        let is_stream = cfg.is_stream;
+        let report = cfg.fit_context_window(None).await?;
+        let schema = cfg.response_schema.clone();
+        let mut body = serde_json::to_value(cfg.to_openai_chat_request()?)?;
+        if let Some(ref schema) = schema {
+            if self.json_schema_format {
+                body["response_format"] = serde_json::json!({
+                    "type":"json_schema",
+                    "json_schema":{"name":"response","schema":schema},
+                });
             }
         }
-        // wd_log::log_debug_ln!("over call code[{}.{}.openai_llm]",ctx.code,code);
+        let start = Instant::now();
+        //声明了response_schema时需要完整文本做校验，流式片段不再发送到channel
+        let mut resp = if is_stream {
+            client.chat_stream(ctx, body, schema.is_none()).await?
+        } else {
+            client.chat(&body).await?
+        };
+        resp.latency_ms = Some(start.elapsed().as_millis() as u64);
+        resp.context_window = Some(report);
+        resp.report_usage(ctx, &self.pricing)?;
         Ok(resp)
     }
 }
 
 #[cfg(test)]
 mod test {
-    use crate::rt_node_service::{LLMNodeResponse, OpenaiLLMService};
+    use crate::llm_provider::mock::MockServer;
+    use crate::llm_provider::LLMClientProfile;
+    use crate::rt_node_service::{LLMNodeRequest, LLMNodeResponse, OpenaiLLMService};
     use agent_rt::{CtxStatus, PlanBuilder, Runtime};
     use serde_json::Value;
     use std::io::{BufRead, Write};
@@ -363,7 +747,7 @@ mod test {
                 .unwrap();
             let resp = serde_json::from_value::<LLMNodeResponse>(resp).unwrap();
 
-            print!("{}\nuser --->", resp.answer.unwrap_or("".into()));
+            print!("{}\nuser --->", resp.answer_text().unwrap_or(""));
             std::io::stdout().flush().unwrap();
         }
     }
@@ -383,7 +767,9 @@ mod test {
             print!("ai   --->");
             std::io::stdout().flush().unwrap();
 
-            let msg = format!("{{\"prompt\":\"你是一个智能助手\" ,\"query\":\"{query}\"}} ");
+            let msg = format!(
+                "{{\"prompt\":\"你是一个智能助手\",\"is_stream\":true,\"query\":\"{query}\"}} "
+            );
             let ctx = rt
                 .ctx(
                     "test001",
@@ -436,4 +822,247 @@ mod test {
 
         println!("{}", res);
     }
+
+    fn mock_stream(answer: &str) -> crate::llm_provider::mock::MockResponse {
+        let chunk = serde_json::json!({
+            "id":"chatcmpl-1",
+            "object":"chat.completion.chunk",
+            "created":1700000000,
+            "model":"mock",
+            "choices":[{"index":0,"delta":{"role":"assistant","content":answer},"finish_reason":"stop"}]
+        })
+        .to_string();
+        MockServer::sse(vec![chunk.as_str(), "[DONE]"])
+    }
+
+    //cargo test openai_llm::test::test_llm_profile -- --nocapture
+    #[tokio::test]
+    async fn test_llm_profile() {
+        let local = MockServer::start(vec![mock_stream("local")]).await;
+        let hosted = MockServer::start(vec![mock_stream("hosted")]).await;
+        let llm = OpenaiLLMService::default()
+            .register_profile(
+                "local",
+                LLMClientProfile::new(local.url.as_str(), "sk-local")
+                    .timeout(Duration::from_secs(3)),
+            )
+            .unwrap()
+            .register_profile(
+                "hosted",
+                LLMClientProfile::new(hosted.url.as_str(), "sk-hosted").org("org-1"),
+            )
+            .unwrap();
+        let rt = Runtime::default()
+            .register_service_layer("openai_llm", llm)
+            .launch();
+
+        for (profile, expect) in [("local", "local"), ("hosted", "hosted")] {
+            let cfg = format!(r#"{{"profile":"{profile}","is_stream":true,"query":"hi"}}"#);
+            let res = rt
+                .ctx(
+                    "test_llm_profile",
+                    PlanBuilder::single_node("openai_llm", cfg).build(),
+                )
+                .arc()
+                .block_on::<Value, _>(())
+                .await
+                .unwrap();
+            let resp = serde_json::from_value::<LLMNodeResponse>(res).unwrap();
+            assert_eq!(Some(expect), resp.answer_text());
+            assert_eq!("stop", resp.finish_reason.unwrap().as_str());
+        }
+        assert_eq!(
+            "Bearer sk-local",
+            local.requests()[0].header("authorization")
+        );
+        assert_eq!(
+            "Bearer sk-hosted",
+            hosted.requests()[0].header("authorization")
+        );
+        assert_eq!("org-1", hosted.requests()[0].header("openai-organization"));
+
+        let res = rt
+            .ctx(
+                "test_llm_profile",
+                PlanBuilder::single_node("openai_llm", r#"{"profile":"other","query":"hi"}"#)
+                    .build(),
+            )
+            .arc()
+            .block_on::<Value, _>(())
+            .await;
+        assert!(format!("{:?}", res).contains("profile[other] not found"));
+    }
+
+    //cargo test openai_llm::test::test_llm_stream_tools -- --nocapture
+    #[tokio::test]
+    async fn test_llm_stream_tools() {
+        let chunk = |calls: Value| {
+            serde_json::json!({
+                "id":"chatcmpl-3",
+                "object":"chat.completion.chunk",
+                "created":1700000000,
+                "model":"mock",
+                "choices":[{"index":0,"delta":{"tool_calls":calls},"finish_reason":null}]
+            })
+            .to_string()
+        };
+        //两个并行调用的参数交替到达
+        let chunks = [
+            chunk(
+                serde_json::json!([{"index":0,"id":"call_a","type":"function","function":{"name":"weather","arguments":""}}]),
+            ),
+            chunk(
+                serde_json::json!([{"index":1,"id":"call_b","type":"function","function":{"name":"time","arguments":"{\"tz\":"}}]),
+            ),
+            chunk(serde_json::json!([{"index":0,"function":{"arguments":"{\"city\":\"杭州\"}"}}])),
+            chunk(serde_json::json!([{"index":1,"function":{"arguments":"\"UTC\"}"}}])),
+        ];
+        let mut data = chunks.iter().map(|x| x.as_str()).collect::<Vec<_>>();
+        data.push("[DONE]");
+        let server = MockServer::start(vec![MockServer::sse(data)]).await;
+        let llm = OpenaiLLMService::default()
+            .register_profile(
+                "mock",
+                LLMClientProfile::new(server.url.as_str(), "sk-mock"),
+            )
+            .unwrap();
+        let rt = Runtime::default()
+            .register_service_layer("openai_llm", llm)
+            .launch();
+
+        let res = rt
+            .ctx(
+                "test_llm_stream_tools",
+                PlanBuilder::single_node(
+                    "openai_llm",
+                    r#"{"profile":"mock","is_stream":true,"query":"hi"}"#,
+                )
+                .build(),
+            )
+            .arc()
+            .block_on::<Value, _>(())
+            .await
+            .unwrap();
+        let resp = serde_json::from_value::<LLMNodeResponse>(res).unwrap();
+        let tools = resp.tools.unwrap();
+        assert_eq!(2, tools.len());
+        assert_eq!(Some("call_a"), tools[0].call_id.as_deref());
+        assert_eq!("weather", tools[0].name.as_str());
+        assert_eq!(r#"{"city":"杭州"}"#, tools[0].args.as_str());
+        assert_eq!(Some("call_b"), tools[1].call_id.as_deref());
+        assert_eq!("time", tools[1].name.as_str());
+        assert_eq!(r#"{"tz":"UTC"}"#, tools[1].args.as_str());
+    }
+
+    //cargo test openai_llm::test::test_llm_no_stream -- --nocapture
+    #[tokio::test]
+    async fn test_llm_no_stream() {
+        let server = MockServer::start(vec![MockServer::json(serde_json::json!({
+            "id":"chatcmpl-2",
+            "object":"chat.completion",
+            "created":1700000000,
+            "model":"mock",
+            "choices":[
+                {"index":1,"finish_reason":"tool_calls","logprobs":null,"message":{
+                    "role":"assistant","content":null,
+                    "tool_calls":[{"id":"call_1","type":"function","function":{"name":"taobao_shop","arguments":"{\"gift\":\"LV\"}"}}]
+                }},
+                {"index":0,"finish_reason":"stop","logprobs":{"content":[{"token":"no","logprob":-0.1,"top_logprobs":[]}]},"message":{
+                    "role":"assistant","content":null,"refusal":"I can't help with that"
+                }}
+            ]
+        }))])
+        .await;
+        let llm = OpenaiLLMService::default()
+            .register_profile(
+                "mock",
+                LLMClientProfile::new(server.url.as_str(), "sk-mock"),
+            )
+            .unwrap();
+        let rt = Runtime::default()
+            .register_service_layer("openai_llm", llm)
+            .launch();
+
+        let cfg = r#"{"profile":"mock","n":2,"logprobs":true,"top_logprobs":2,"query":"hi"}"#;
+        let res = rt
+            .ctx(
+                "test_llm_no_stream",
+                PlanBuilder::single_node("openai_llm", cfg).build(),
+            )
+            .arc()
+            .block_on::<Value, _>(())
+            .await
+            .unwrap();
+        let resp = serde_json::from_value::<LLMNodeResponse>(res).unwrap();
+        assert_eq!(None, resp.answer);
+        assert_eq!("stop", resp.finish_reason.unwrap().as_str());
+        assert_eq!("I can't help with that", resp.refusal.unwrap().as_str());
+        assert!(resp.logprobs.is_some());
+        assert_eq!(2, resp.choices.len());
+        let tools = resp.choices[1].tools.clone().unwrap();
+        assert_eq!("taobao_shop", tools[0].name.as_str());
+
+        let body = &server.requests()[0].body;
+        assert!(!body["stream"].as_bool().unwrap_or(false));
+        assert_eq!(2, body["n"]);
+        assert_eq!(2, body["top_logprobs"]);
+    }
+
+    //cargo test openai_llm::test::test_openai_max_tokens -- --nocapture
+    #[test]
+    fn test_openai_max_tokens() {
+        let req = serde_json::from_value::<LLMNodeRequest>(
+            serde_json::json!({"query":"hi","max_tokens":65535}),
+        )
+        .unwrap();
+        assert_eq!(
+            Some(65535),
+            req.to_openai_chat_request().unwrap().max_tokens
+        );
+
+        let req = serde_json::from_value::<LLMNodeRequest>(
+            serde_json::json!({"query":"hi","max_tokens":65536}),
+        )
+        .unwrap();
+        let res = req.to_openai_chat_request();
+        assert!(format!("{:?}", res).contains("max_tokens[65536] exceeds 65535"));
+    }
+
+    //cargo test openai_llm::test::test_llm_stream_schema -- --nocapture
+    #[tokio::test]
+    async fn test_llm_stream_schema() {
+        let server = MockServer::start(vec![mock_stream(r#"{"city":"杭州"}"#)]).await;
+        let llm = OpenaiLLMService::default()
+            .register_profile(
+                "mock",
+                LLMClientProfile::new(server.url.as_str(), "sk-mock"),
+            )
+            .unwrap();
+        let rt = Runtime::default()
+            .register_service_layer("openai_llm", llm)
+            .launch();
+
+        let cfg = r#"{"profile":"mock","is_stream":true,"query":"hi","response_schema":{"type":"object","properties":{"city":{"type":"string"}},"required":["city"]}}"#;
+        let mut ctx = rt.ctx(
+            "test_llm_stream_schema",
+            PlanBuilder::single_node("openai_llm", cfg).build(),
+        );
+        OpenaiLLMService::set_channel_to_ctx(&mut ctx);
+        let ctx = ctx.arc();
+        let res = ctx.clone().block_on::<Value, _>(()).await.unwrap();
+        let resp = serde_json::from_value::<LLMNodeResponse>(res).unwrap();
+        assert_eq!(Some(serde_json::json!({"city":"杭州"})), resp.answer);
+        //校验用的文本不会发送到channel
+        assert_eq!(
+            Some(String::new()),
+            OpenaiLLMService::try_recv_from_channel(&ctx)
+        );
+
+        let body = &server.requests()[0].body;
+        assert_eq!("json_schema", body["response_format"]["type"]);
+        assert_eq!(
+            "object",
+            body["response_format"]["json_schema"]["schema"]["type"]
+        );
+    }
 }
@@ -0,0 +1,248 @@
+use crate::rt_node_service::CfgBound;
+use agent_rt::{Context, ServiceLayer};
+use rhai::module_resolvers::StaticModuleResolver;
+use rhai::packages::{Package, StandardPackage};
+use rhai::{Dynamic, Engine, EvalAltResult, Module, Scope, Shared};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use wd_tools::PFErr;
+
+//进程内执行rhai脚本，简单的数据处理不需要再走python服务
+pub struct RhaiScriptService {
+    //标准库，每次创建engine时复用
+    std: Shared<Module>,
+    //允许脚本import的模块，不支持从文件import
+    modules: StaticModuleResolver,
+    pub max_operations: u64,
+    pub timeout: Duration,
+    pub max_call_levels: usize,
+    pub max_string_size: usize,
+    pub max_array_size: usize,
+    pub max_map_size: usize,
+}
+
+impl Default for RhaiScriptService {
+    fn default() -> Self {
+        Self {
+            std: StandardPackage::new().as_shared_module(),
+            modules: StaticModuleResolver::new(),
+            max_operations: 1_000_000,
+            timeout: Duration::from_secs(1),
+            max_call_levels: 32,
+            max_string_size: 1 << 20,
+            max_array_size: 10_000,
+            max_map_size: 10_000,
+        }
+    }
+}
+
+impl RhaiScriptService {
+    pub fn max_operations(mut self, max_operations: u64) -> Self {
+        self.max_operations = max_operations;
+        self
+    }
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+    //加入白名单，脚本中通过 import "name" as m; 使用
+    pub fn module<S: Into<String>>(mut self, name: S, module: Module) -> Self {
+        self.modules.insert(name.into(), module);
+        self
+    }
+    fn engine(&self, ctx: Arc<Context>, max_operations: u64) -> Engine {
+        let mut engine = Engine::new_raw();
+        engine.register_global_module(self.std.clone());
+        engine.set_module_resolver(self.modules.clone());
+        engine.set_max_operations(max_operations);
+        engine.set_max_call_levels(self.max_call_levels);
+        engine.set_max_string_size(self.max_string_size);
+        engine.set_max_array_size(self.max_array_size);
+        engine.set_max_map_size(self.max_map_size);
+        engine.on_print(|s| wd_log::log_debug_ln!("rhai print: {}", s));
+        engine.on_debug(|s, _, pos| wd_log::log_debug_ln!("rhai debug {}: {}", pos, s));
+        //只读访问ctx，返回的是拷贝
+        engine.register_fn("ctx", move |path: &str| -> Dynamic {
+            CfgBound::<Value>::get_value_from_ctx(path, &ctx)
+                .and_then(|x| rhai::serde::to_dynamic(x).ok())
+                .unwrap_or(Dynamic::UNIT)
+        });
+        engine
+    }
+    fn script_error(code: &str, err: EvalAltResult) -> anyhow::Error {
+        match err {
+            EvalAltResult::ErrorTerminated(reason, pos) => {
+                anyhow::anyhow!(
+                    "node[{}] rhai script terminated: {} ({})",
+                    code,
+                    reason,
+                    pos
+                )
+            }
+            e => anyhow::anyhow!("node[{}] rhai script error: {}", code, e),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl ServiceLayer for RhaiScriptService {
+    type Config = CfgBound<RhaiScriptServiceRequest>;
+    type Output = Value;
+
+    async fn call(
+        &self,
+        code: String,
+        ctx: Arc<Context>,
+        cfg: Self::Config,
+    ) -> anyhow::Result<Self::Output> {
+        let RhaiScriptServiceRequest {
+            script,
+            input,
+            max_operations,
+            timeout_ms,
+        } = cfg.bound(&ctx)?;
+        if script.trim().is_empty() {
+            return anyhow::anyhow!("node[{}] rhai script is empty", code).err();
+        }
+        //节点配置只能收紧服务的限制
+        let max_operations = match max_operations {
+            0 => self.max_operations,
+            n => n.min(self.max_operations),
+        };
+        let timeout = match timeout_ms {
+            0 => self.timeout,
+            n => Duration::from_millis(n).min(self.timeout),
+        };
+        let mut engine = self.engine(ctx, max_operations);
+        let input = rhai::serde::to_dynamic(input).map_err(|e| Self::script_error(&code, *e))?;
+
+        let result = tokio::task::spawn_blocking(move || {
+            let start = Instant::now();
+            engine.on_progress(move |_| {
+                if start.elapsed() > timeout {
+                    return Some(format!("timeout after {}ms", timeout.as_millis()).into());
+                }
+                None
+            });
+            let mut scope = Scope::new();
+            scope.push("input", input);
+            let output = engine.eval_with_scope::<Dynamic>(&mut scope, script.as_str())?;
+            rhai::serde::from_dynamic::<Value>(&output)
+        })
+        .await?;
+        result.map_err(|e| Self::script_error(&code, *e))
+    }
+}
+
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct RhaiScriptServiceRequest {
+    pub script: String,
+    //脚本中的input变量
+    pub input: Value,
+    //为0时使用服务的限制
+    pub max_operations: u64,
+    pub timeout_ms: u64,
+}
+
+#[cfg(test)]
+mod test {
+    use crate::rt_node_service::RhaiScriptService;
+    use agent_rt::{PlanBuilder, Runtime};
+    use rhai::Module;
+    use serde_json::Value;
+    use std::time::Duration;
+    use wd_tools::PFArc;
+
+    const RHAI_SCRIPT: &str = r#"
+let start = ctx("start");
+start.answer = "changed";
+#{
+    answer: "AI:" + input.answer,
+    count: ctx("start.list").len(),
+    first: ctx("start.list[0]"),
+    missing: ctx("start.none"),
+    origin: ctx("start.answer")
+}
+"#;
+
+    //cargo test rt_node_service::rhai_script::test::test_rhai_script -- --nocapture
+    #[tokio::test]
+    async fn test_rhai_script() {
+        let mut math = Module::new();
+        math.set_native_fn("double", |x: i64| Ok(x * 2));
+        let rt = Runtime::default()
+            .register_service_layer(
+                "rhai",
+                RhaiScriptService::default()
+                    .timeout(Duration::from_millis(200))
+                    .module("math", math),
+            )
+            .launch();
+        let run = |cfg: Value| {
+            let rt = rt.clone();
+            async move {
+                let cfg = serde_json::to_string(&cfg).unwrap();
+                rt.ctx(
+                    "rhai-test-001",
+                    PlanBuilder::single_node("rhai", cfg).build(),
+                )
+                .arc()
+                .block_on::<Value, _>(serde_json::json!({
+                    "answer":"this is a input",
+                    "list":[3,4]
+                }))
+                .await
+            }
+        };
+
+        let output = run(serde_json::json!({
+            "script":RHAI_SCRIPT,
+            "input":{"answer":"{{start.answer}}"}
+        }))
+        .await
+        .unwrap();
+        println!("--> {}", output);
+        assert_eq!(
+            output,
+            serde_json::json!({
+                "answer":"AI:this is a input",
+                "count":2,
+                "first":3,
+                "missing":null,
+                "origin":"this is a input"
+            })
+        );
+
+        //白名单中的模块
+        let output = run(serde_json::json!({
+            "script":"import \"math\" as m; m::double(input.x)",
+            "input":{"x":21}
+        }))
+        .await
+        .unwrap();
+        assert_eq!(output, serde_json::json!(42));
+        let err = run(serde_json::json!({"script":"import \"fs\" as fs; 1"}))
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("rhai script error"));
+
+        //操作数和时间限制
+        let err = run(serde_json::json!({"script":"loop {}","max_operations":1000}))
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("Too many operations"));
+        let err = run(serde_json::json!({"script":"loop {}","timeout_ms":50}))
+            .await
+            .unwrap_err();
+        println!("--> {}", err);
+        assert!(err.to_string().contains("timeout after 50ms"));
+
+        let err = run(serde_json::json!({"script":"let x = ;"}))
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("node[end] rhai script error"));
+    }
+}
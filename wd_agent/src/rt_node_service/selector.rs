@@ -1,8 +1,9 @@
-use crate::rt_node_service::CfgBound;
+use crate::rt_node_service::{contains, CfgBound, Expression};
 use agent_rt::Context;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use std::collections::VecDeque;
+use std::str::FromStr;
 use std::sync::Arc;
 use wd_tools::PFErr;
 
@@ -10,6 +11,22 @@ use wd_tools::PFErr;
 pub struct SelectorService {}
 
 impl SelectorService {
+    //按顺序匹配case，都不满足时走default_goto
+    pub fn switch(ctx: &Context, cases: &[SelectorCase]) -> anyhow::Result<Option<usize>> {
+        let lookup = |path: &str| CfgBound::<Value>::get_value_from_ctx(path, ctx);
+        for (i, case) in cases.iter().enumerate() {
+            let expr = match Expression::from_str(case.when.as_str()) {
+                Ok(o) => o,
+                Err(e) => return anyhow::anyhow!("case[{}] when parse failed:{}", i, e).err(),
+            };
+            match expr.test(&lookup) {
+                Ok(true) => return Ok(Some(i)),
+                Ok(false) => {}
+                Err(e) => return anyhow::anyhow!("case[{}] when eval failed:{}", i, e).err(),
+            }
+        }
+        Ok(None)
+    }
     pub fn judge(vars: &mut VecDeque<Value>) -> anyhow::Result<bool> {
         let var1 = if let Some(s) = vars.pop_front() {
             s
@@ -80,13 +97,14 @@ impl SelectorService {
             "<=" => {
                 if let Some(f1) = var1.as_f64() {
                     if let Some(f2) = var2.as_f64() {
-                        return Ok(f1 < f2);
+                        return Ok(f1 <= f2);
                     }
                 }
                 Ok(false)
             }
-            // todo "contain"=>{}
-            // todo "no_contain"=>{}
+            //和表达式中的contains一致，数组元素按数值相等比较
+            "contain" => Ok(contains(&var1, &var2)),
+            "no_contain" => Ok(!contains(&var1, &var2)),
             _ => anyhow::anyhow!("unknown comparator[{}]", comparator).err(),
         };
     }
@@ -104,6 +122,21 @@ impl agent_rt::ServiceLayer for SelectorService {
         cfg: Self::Config,
     ) -> anyhow::Result<Self::Output> {
         let cfg = cfg.bound(&ctx)?;
+        if !cfg.cases.is_empty() {
+            let (case, go_next_node) = match Self::switch(&ctx, &cfg.cases)? {
+                Some(i) => (i as i64, cfg.cases[i].goto.clone()),
+                None if cfg.default_goto.is_empty() => {
+                    return anyhow::anyhow!(
+                        "node[{}] no case matched and default_goto is empty",
+                        code
+                    )
+                    .err()
+                }
+                None => (-1, cfg.default_goto),
+            };
+            Self::goto(&ctx, code.as_str(), go_next_node.clone())?;
+            return Ok(serde_json::json!({"case":case,"goto":go_next_node}));
+        }
         let mut result = false;
         let all_true = cfg.condition == "且";
 
@@ -130,8 +163,15 @@ impl agent_rt::ServiceLayer for SelectorService {
         } else {
             cfg.false_goto
         };
+        Self::goto(&ctx, code.as_str(), go_next_node)?;
+        Ok(Value::Null)
+    }
+}
+
+impl SelectorService {
+    fn goto(ctx: &Context, code: &str, go_next_node: String) -> anyhow::Result<()> {
         ctx.plan.update(
-            code.as_str(),
+            code,
             Box::new(|x| {
                 if let Some(p) = x {
                     p.go = vec![go_next_node];
@@ -139,18 +179,34 @@ impl agent_rt::ServiceLayer for SelectorService {
                 Ok(())
             }),
         )?;
-        Ok(Value::Null)
+        Ok(())
     }
 }
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+pub struct SelectorCase {
+    //布尔表达式，直接写变量路径，如 start.intent == 'refund' and length(start.items) > 0
+    pub when: String,
+    pub goto: String,
+}
+
 #[derive(Debug, Default, Clone, Deserialize, Serialize)]
 pub struct SelectorServiceConfig {
+    #[serde(default)]
     pub condition: String, //或，且
     //三段式 var1 [comparator] var2
     //comparator ==,!=,>,>=,<,<=,is_null,no_null,contain,no_contain
     // if comparator is [is_null,no_null], do not need var2
+    #[serde(default)]
     pub vars: Vec<Value>,
+    #[serde(default)]
     pub true_goto: String,
+    #[serde(default)]
     pub false_goto: String,
+    //不为空时按switch处理：按顺序取第一个满足的case，忽略上面的字段
+    #[serde(default)]
+    pub cases: Vec<SelectorCase>,
+    #[serde(default)]
+    pub default_goto: String,
 }
 
 #[cfg(test)]
@@ -159,6 +215,7 @@ mod test {
     use crate::rt_node_service::VarFlowChartService;
     use agent_rt::{PlanBuilder, Runtime, END_NODE_CODE};
     use serde_json::Value;
+    use std::collections::VecDeque;
     use wd_tools::PFArc;
 
     //cargo test rt_node_service::selector::test::test_selector_service -- --nocapture
@@ -231,4 +288,100 @@ mod test {
 
         println!("--> {}", output);
     }
+
+    //cargo test rt_node_service::selector::test::test_selector_judge -- --nocapture
+    #[test]
+    fn test_selector_judge() {
+        let judge = |vars: Value| {
+            let mut vars = serde_json::from_value::<VecDeque<Value>>(vars).unwrap();
+            SelectorService::judge(&mut vars).unwrap()
+        };
+        assert!(judge(serde_json::json!([1, "<=", 1])));
+        assert!(!judge(serde_json::json!([2, "<=", 1])));
+        assert!(judge(serde_json::json!([
+            "hello world",
+            "contain",
+            "world"
+        ])));
+        assert!(judge(serde_json::json!([[1, 2], "contain", 2])));
+        assert!(judge(serde_json::json!([[1.0, 2], "contain", 1])));
+        assert!(judge(serde_json::json!([{"a":1}, "contain", "a"])));
+        assert!(judge(serde_json::json!(["hello", "no_contain", "x"])));
+        assert!(!judge(serde_json::json!([[1, 2], "no_contain", 1])));
+    }
+
+    //cargo test rt_node_service::selector::test::test_selector_switch -- --nocapture
+    #[tokio::test]
+    pub async fn test_selector_switch() {
+        let rt = Runtime::default()
+            .register_service_layer("flow_chart_selector", SelectorService::default())
+            .register_service_layer("flow_chart_var", VarFlowChartService::default())
+            .launch();
+
+        let cfg = serde_json::json!({
+            "cases":[
+                {"when":"start.intent == 'refund' and length(start.items) > 0","goto":"refund"},
+                {"when":"contains(start.query, '发票') or startsWith(start.query, 'invoice')","goto":"invoice"},
+                {"when":"matches(start.query, '^\\\\d+$')","goto":"order"},
+                {"when":"start.level in ['vip', 'svip'] and not start.blocked","goto":"vip"},
+            ],
+            "default_goto":"chat"
+        });
+        let branches = ["refund", "invoice", "order", "vip", "chat"];
+        let plan = || {
+            let mut builder = PlanBuilder::start::<_, String>(
+                ("switch", "flow_chart_selector", cfg.to_string()),
+                vec![],
+            );
+            for i in branches {
+                builder.fission_from_code(
+                    "switch",
+                    (i, "flow_chart_var", format!(r#"{{"result":"{}"}}"#, i)),
+                    vec![END_NODE_CODE],
+                );
+            }
+            let result = branches
+                .iter()
+                .map(|x| format!("{{{{{}.result}}}}", x))
+                .collect::<String>();
+            builder
+                .end::<String, _>(
+                    vec![],
+                    (
+                        "end",
+                        "flow_chart_var",
+                        format!(r#"{{"result":"{}"}}"#, result),
+                    ),
+                )
+                .build()
+        };
+
+        let inputs = [
+            (
+                serde_json::json!({"intent":"refund","items":[1],"query":"发票"}),
+                "refund",
+            ),
+            (
+                serde_json::json!({"intent":"refund","items":[],"query":"开发票"}),
+                "invoice",
+            ),
+            (serde_json::json!({"query":"invoice please"}), "invoice"),
+            (serde_json::json!({"query":"12345"}), "order"),
+            (serde_json::json!({"query":"hi","level":"svip"}), "vip"),
+            (
+                serde_json::json!({"query":"hi","level":"vip","blocked":true}),
+                "chat",
+            ),
+            (serde_json::json!({"query":"hi"}), "chat"),
+        ];
+        for (i, (input, expect)) in inputs.into_iter().enumerate() {
+            let output = rt
+                .ctx(format!("selector-switch-{i}"), plan())
+                .arc()
+                .block_on::<Value, _>(input)
+                .await
+                .unwrap();
+            assert_eq!(serde_json::json!({"result":expect}), output, "{}", i);
+        }
+    }
 }
@@ -0,0 +1,207 @@
+use crate::rt_node_service::{LLMContextMessage, LLMNodeRequest, LLMNodeResponse};
+use agent_rt::validate_json_schema;
+use serde_json::Value;
+use std::future::Future;
+use wd_tools::PFErr;
+
+//模型经常把json包在```json代码块中
+pub fn parse_json_answer(answer: &str) -> anyhow::Result<Value> {
+    let mut s = answer.trim();
+    if let Some(body) = s.strip_prefix("```") {
+        let body = body.trim_start_matches(|c: char| c.is_ascii_alphabetic());
+        s = body.trim_end().strip_suffix("```").unwrap_or(body).trim();
+    }
+    Ok(serde_json::from_str(s)?)
+}
+
+impl LLMNodeRequest {
+    fn append_schema_prompt(&mut self, schema: &Value) {
+        if !self.prompt.is_empty() {
+            self.prompt.push_str("\n\n");
+        }
+        self.prompt.push_str(
+            format!(
+                "请只输出一个符合下面json schema的json，不要输出其他内容：\n{}",
+                schema
+            )
+            .as_str(),
+        );
+    }
+    //把上一次的回答和校验错误追加到上下文，要求模型修正
+    fn append_schema_errors(&mut self, answer: String, errors: &[String]) {
+        let query = std::mem::take(&mut self.query);
+        if !query.is_empty() {
+            self.context.push(LLMContextMessage::new("user", query));
+        }
+        self.context
+            .push(LLMContextMessage::new("assistant", answer));
+        self.query = format!(
+            "你的回答不符合要求的json schema，错误如下：\n{}\n请修正后重新输出json。",
+            errors.join("\n")
+        );
+    }
+}
+
+//声明了response_schema时校验回答，不通过则带着错误重试，通过后answer替换为解析后的json
+pub async fn call_with_response_schema<F, Fut>(
+    mut req: LLMNodeRequest,
+    call: F,
+) -> anyhow::Result<LLMNodeResponse>
+where
+    F: Fn(LLMNodeRequest) -> Fut,
+    Fut: Future<Output = anyhow::Result<LLMNodeResponse>>,
+{
+    let schema = match req.response_schema.clone() {
+        Some(s) => s,
+        None => return call(req).await,
+    };
+    req.append_schema_prompt(&schema);
+
+    let mut errors = vec![];
+    for _ in 0..=req.response_retries {
+        let mut resp = call(req.clone()).await?;
+        //模型选择调用工具时原样返回
+        if resp.tools.is_some() {
+            return Ok(resp);
+        }
+        let answer = resp.answer_text().unwrap_or_default().to_string();
+        errors = match parse_json_answer(answer.as_str()) {
+            Ok(value) => {
+                let list = validate_json_schema(&schema, &value);
+                if list.is_empty() {
+                    resp.answer = Some(value);
+                    return Ok(resp);
+                }
+                list
+            }
+            Err(e) => vec![format!("answer is not a json:{}", e)],
+        };
+        wd_log::log_debug_ln!("llm answer not match response_schema:{:?}", errors);
+        req.append_schema_errors(answer, errors.as_slice());
+    }
+    anyhow::anyhow!(
+        "llm answer not match response_schema after {} retries:{}",
+        req.response_retries,
+        errors.join("; ")
+    )
+    .err()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::llm_provider::{ChatRequest, ChatResponse, LLMProvider};
+    use crate::rt_node_service::{parse_json_answer, LLMService, VarFlowChartService};
+    use agent_rt::{PlanBuilder, Runtime};
+    use serde_json::Value;
+    use std::sync::{Arc, Mutex};
+    use wd_tools::PFArc;
+
+    //依次返回预设的回答，并记录收到的请求
+    struct ScriptProvider {
+        answers: Mutex<Vec<&'static str>>,
+        requests: Arc<Mutex<Vec<ChatRequest>>>,
+    }
+
+    #[async_trait::async_trait]
+    impl LLMProvider for ScriptProvider {
+        fn name(&self) -> &str {
+            "script"
+        }
+        fn default_model(&self) -> &str {
+            "script-model"
+        }
+        async fn chat(&self, req: ChatRequest) -> anyhow::Result<ChatResponse> {
+            self.requests.lock().unwrap().push(req);
+            let content = self.answers.lock().unwrap().remove(0).to_string();
+            Ok(ChatResponse {
+                content,
+                ..Default::default()
+            })
+        }
+    }
+
+    //cargo test rt_node_service::structured_output::test::test_parse_json_answer -- --nocapture
+    #[test]
+    fn test_parse_json_answer() {
+        let value = parse_json_answer("```json\n{\"a\":1}\n```").unwrap();
+        assert_eq!(serde_json::json!({"a":1}), value);
+        let value = parse_json_answer(" [1,2] ").unwrap();
+        assert_eq!(serde_json::json!([1, 2]), value);
+        assert!(parse_json_answer("hello").is_err());
+    }
+
+    //cargo test rt_node_service::structured_output::test::test_response_schema -- --nocapture
+    #[tokio::test]
+    async fn test_response_schema() {
+        let requests = Arc::new(Mutex::new(vec![]));
+        let provider = ScriptProvider {
+            answers: Mutex::new(vec![
+                "sorry",
+                r#"{"city":"beijing"}"#,
+                "```json\n{\"city\":\"beijing\",\"temp\":25}\n```",
+                "still wrong",
+            ]),
+            requests: requests.clone(),
+        };
+        let rt = Runtime::default()
+            .register_service_layer(
+                "llm",
+                LLMService::new().register_provider("script", provider),
+            )
+            .register_service_layer("var", VarFlowChartService::default())
+            .launch();
+
+        let cfg = serde_json::json!({
+            "provider":"script",
+            "prompt":"you are a weather bot",
+            "query":"{{start.query}}",
+            "response_schema":{
+                "type":"object",
+                "required":["city","temp"],
+                "properties":{"city":{"type":"string"},"temp":{"type":"number"}}
+            }
+        })
+        .to_string();
+        let plan = PlanBuilder::start(("llm", "llm", cfg.as_str()), vec!["end"])
+            .sequence(
+                vec![("end", "var", r#"{"temp":"{{llm.answer.temp}}"}"#)],
+                "",
+            )
+            .check_and_build()
+            .unwrap();
+        let res = rt
+            .ctx("test_response_schema", plan)
+            .arc()
+            .block_on::<Value, _>(serde_json::json!({"query":"weather?"}))
+            .await
+            .unwrap();
+        assert_eq!(serde_json::json!({"temp":25}), res);
+
+        let list = requests.lock().unwrap().clone();
+        assert_eq!(3, list.len());
+        assert!(list[0].messages[0].content.contains("json schema"));
+        assert!(list[0].response_schema.is_some());
+        //第三次请求带着前两次的回答和错误
+        assert_eq!(6, list[2].messages.len());
+        assert!(list[2].messages[5]
+            .content
+            .contains("$.temp: required field is missing"));
+
+        let cfg = serde_json::json!({
+            "provider":"script",
+            "query":"weather?",
+            "response_retries":0,
+            "response_schema":{"type":"object"}
+        })
+        .to_string();
+        let res = rt
+            .ctx(
+                "test_response_schema_err",
+                PlanBuilder::single_node("llm", cfg).build(),
+            )
+            .arc()
+            .block_on::<Value, _>(())
+            .await;
+        assert!(format!("{:?}", res).contains("not match response_schema"));
+    }
+}
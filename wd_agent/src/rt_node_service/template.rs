@@ -0,0 +1,925 @@
+use serde_json::{Map, Value};
+use std::cell::RefCell;
+use std::str::FromStr;
+use wd_tools::PFErr;
+
+//模板中的常量或变量路径
+#[derive(Debug, Clone, PartialEq)]
+enum Operand {
+    Literal(Value),
+    Path(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Filter {
+    name: String,
+    args: Vec<Operand>,
+}
+
+//变量加过滤器: a.b | default:"x" | upper
+#[derive(Debug, Clone, PartialEq)]
+struct Expr {
+    operand: Operand,
+    filters: Vec<Filter>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Cond {
+    Expr(Expr),
+    Not(Box<Cond>),
+    Compare(Expr, String, Expr),
+    And(Vec<Cond>),
+    Or(Vec<Cond>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Node {
+    Text(String),
+    Output(Expr),
+    If(Vec<(Cond, Vec<Node>)>, Vec<Node>),
+    For {
+        var: String,
+        expr: Expr,
+        body: Vec<Node>,
+        //列表为空时输出
+        empty: Vec<Node>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Text(String),
+    Output(String),
+    Tag(String),
+}
+
+//配置中的模板语法：
+//  {{ a.b | default:"n/a" | upper }}  输出变量，支持过滤器 default json upper lower trim join length
+//  {% if a.ok and b.n > 1 %} {% elif not c %} {% else %} {% endif %}
+//  {% for i in a.list %} {{loop.index}}:{{i.name}} {% else %} 空列表 {% endfor %}
+//  {% raw %}{{不解析}}{% endraw %}，或用 \{{ \{% 输出字面量
+#[derive(Debug, Clone, PartialEq)]
+pub struct Template {
+    nodes: Vec<Node>,
+}
+
+//是否需要按模板解析
+pub fn is_template(s: &str) -> bool {
+    s.contains("{{") || s.contains("{%")
+}
+
+//按引号跳过，在s中查找pat第一次出现的位置
+fn find_outside_quote(s: &str, pat: &str) -> Option<usize> {
+    let mut quote: Option<char> = None;
+    let mut escape = false;
+    for (i, c) in s.char_indices() {
+        if let Some(q) = quote {
+            if escape {
+                escape = false;
+            } else if c == '\\' {
+                escape = true;
+            } else if c == q {
+                quote = None;
+            }
+            continue;
+        }
+        if c == '"' || c == '\'' {
+            quote = Some(c);
+            continue;
+        }
+        if s[i..].starts_with(pat) {
+            return Some(i);
+        }
+    }
+    None
+}
+
+fn split_outside_quote<'a>(s: &'a str, pat: &str) -> Vec<&'a str> {
+    let mut list = vec![];
+    let mut rest = s;
+    while let Some(i) = find_outside_quote(rest, pat) {
+        list.push(&rest[..i]);
+        rest = &rest[i + pat.len()..];
+    }
+    list.push(rest);
+    list
+}
+
+fn tokenize(s: &str) -> anyhow::Result<Vec<Token>> {
+    let mut list = vec![];
+    let mut text = String::new();
+    let mut rest = s;
+    while !rest.is_empty() {
+        if rest.starts_with("\\{{") || rest.starts_with("\\{%") {
+            text.push_str(&rest[1..3]);
+            rest = &rest[3..];
+            continue;
+        }
+        let (end, output) = if rest.starts_with("{{") {
+            ("}}", true)
+        } else if rest.starts_with("{%") {
+            ("%}", false)
+        } else {
+            let c = rest.chars().next().unwrap_or_default();
+            text.push(c);
+            rest = &rest[c.len_utf8()..];
+            continue;
+        };
+        let Some(e) = find_outside_quote(&rest[2..], end) else {
+            return anyhow::anyhow!("template unclosed {}", &rest[..2]).err();
+        };
+        let inner = rest[2..2 + e].trim();
+        rest = &rest[4 + e..];
+        if !text.is_empty() {
+            list.push(Token::Text(std::mem::take(&mut text)));
+        }
+        if output {
+            list.push(Token::Output(inner.to_string()));
+            continue;
+        }
+        if inner != "raw" {
+            list.push(Token::Tag(inner.to_string()));
+            continue;
+        }
+        //raw块原样输出，直到endraw
+        let mut raw = String::new();
+        loop {
+            let Some(i) = rest.find("{%") else {
+                return anyhow::anyhow!("template raw block need endraw").err();
+            };
+            let tag_end = rest[i..].find("%}").map(|x| x + i);
+            if let Some(e) = tag_end {
+                if rest[i + 2..e].trim() == "endraw" {
+                    raw.push_str(&rest[..i]);
+                    rest = &rest[e + 2..];
+                    break;
+                }
+            }
+            raw.push_str(&rest[..i + 2]);
+            rest = &rest[i + 2..];
+        }
+        list.push(Token::Text(raw));
+    }
+    if !text.is_empty() {
+        list.push(Token::Text(text));
+    }
+    Ok(list)
+}
+
+fn parse_operand(s: &str) -> anyhow::Result<Operand> {
+    let s = s.trim();
+    if s.is_empty() {
+        return anyhow::anyhow!("template empty expression").err();
+    }
+    if s.len() >= 2
+        && ((s.starts_with('"') && s.ends_with('"')) || (s.starts_with('\'') && s.ends_with('\'')))
+    {
+        let mut out = String::new();
+        let mut escape = false;
+        for c in s[1..s.len() - 1].chars() {
+            if escape {
+                out.push(match c {
+                    'n' => '\n',
+                    't' => '\t',
+                    c => c,
+                });
+                escape = false;
+            } else if c == '\\' {
+                escape = true;
+            } else {
+                out.push(c);
+            }
+        }
+        return Ok(Operand::Literal(Value::String(out)));
+    }
+    match s {
+        "true" => return Ok(Operand::Literal(Value::Bool(true))),
+        "false" => return Ok(Operand::Literal(Value::Bool(false))),
+        "null" => return Ok(Operand::Literal(Value::Null)),
+        _ => {}
+    }
+    if s.starts_with(|c: char| c.is_ascii_digit() || c == '-') {
+        if let Ok(v) = serde_json::from_str::<Value>(s) {
+            if v.is_number() {
+                return Ok(Operand::Literal(v));
+            }
+        }
+    }
+    match parse_path(s)?.first() {
+        Some(PathSegment::Key(_)) => Ok(Operand::Path(s.to_string())),
+        _ => anyhow::anyhow!("template path[{}] must start with a name", s).err(),
+    }
+}
+
+fn parse_expr(s: &str) -> anyhow::Result<Expr> {
+    let mut parts = split_outside_quote(s, "|").into_iter();
+    let operand = parse_operand(parts.next().unwrap_or_default())?;
+    let mut filters = vec![];
+    for i in parts {
+        let (name, args) = match find_outside_quote(i, ":") {
+            Some(n) => (&i[..n], Some(&i[n + 1..])),
+            None => (i, None),
+        };
+        let args = match args {
+            Some(args) => split_outside_quote(args, ",")
+                .into_iter()
+                .map(parse_operand)
+                .collect::<anyhow::Result<Vec<_>>>()?,
+            None => vec![],
+        };
+        filters.push(Filter {
+            name: name.trim().to_string(),
+            args,
+        });
+    }
+    Ok(Expr { operand, filters })
+}
+
+fn parse_cond(s: &str) -> anyhow::Result<Cond> {
+    let list = split_outside_quote(s, " or ");
+    if list.len() > 1 {
+        return Ok(Cond::Or(
+            list.into_iter()
+                .map(parse_cond)
+                .collect::<anyhow::Result<_>>()?,
+        ));
+    }
+    let list = split_outside_quote(s, " and ");
+    if list.len() > 1 {
+        return Ok(Cond::And(
+            list.into_iter()
+                .map(parse_cond)
+                .collect::<anyhow::Result<_>>()?,
+        ));
+    }
+    let s = s.trim();
+    if let Some(s) = s.strip_prefix("not ") {
+        return Ok(Cond::Not(Box::new(parse_cond(s)?)));
+    }
+    for op in ["==", "!=", ">=", "<=", ">", "<"] {
+        if let Some(i) = find_outside_quote(s, op) {
+            let left = parse_expr(&s[..i])?;
+            let right = parse_expr(&s[i + op.len()..])?;
+            return Ok(Cond::Compare(left, op.to_string(), right));
+        }
+    }
+    Ok(Cond::Expr(parse_expr(s)?))
+}
+
+//解析到ends中的某个结束标签为止，返回节点和遇到的结束标签
+fn parse_nodes(
+    tokens: &[Token],
+    pos: &mut usize,
+    ends: &[&str],
+) -> anyhow::Result<(Vec<Node>, Option<String>)> {
+    let mut nodes = vec![];
+    while *pos < tokens.len() {
+        let token = &tokens[*pos];
+        *pos += 1;
+        let tag = match token {
+            Token::Text(s) => {
+                nodes.push(Node::Text(s.clone()));
+                continue;
+            }
+            Token::Output(s) => {
+                nodes.push(Node::Output(parse_expr(s)?));
+                continue;
+            }
+            Token::Tag(s) => s.as_str(),
+        };
+        let keyword = tag.split_whitespace().next().unwrap_or_default();
+        if ends.contains(&keyword) {
+            return Ok((nodes, Some(tag.to_string())));
+        }
+        match keyword {
+            "if" => {
+                let mut branches = vec![];
+                let mut cond = parse_cond(&tag[2..])?;
+                let mut other = vec![];
+                loop {
+                    let (body, end) = parse_nodes(tokens, pos, &["elif", "else", "endif"])?;
+                    let end = end.unwrap_or_default();
+                    branches.push((cond, body));
+                    if let Some(s) = end.strip_prefix("elif") {
+                        cond = parse_cond(s)?;
+                        continue;
+                    }
+                    if end == "else" {
+                        let (body, end) = parse_nodes(tokens, pos, &["endif"])?;
+                        if end.is_none() {
+                            return anyhow::anyhow!("template if need endif").err();
+                        }
+                        other = body;
+                    } else if end != "endif" {
+                        return anyhow::anyhow!("template if need endif").err();
+                    }
+                    break;
+                }
+                nodes.push(Node::If(branches, other));
+            }
+            "for" => {
+                let s = tag[3..].trim();
+                let Some(i) = s.find(" in ") else {
+                    return anyhow::anyhow!("template for[{}] need 'in'", s).err();
+                };
+                let var = s[..i].trim().to_string();
+                let expr = parse_expr(&s[i + 4..])?;
+                let (body, end) = parse_nodes(tokens, pos, &["else", "endfor"])?;
+                let mut empty = vec![];
+                match end.as_deref() {
+                    Some("endfor") => {}
+                    Some(_) => {
+                        let (list, end) = parse_nodes(tokens, pos, &["endfor"])?;
+                        if end.is_none() {
+                            return anyhow::anyhow!("template for need endfor").err();
+                        }
+                        empty = list;
+                    }
+                    None => return anyhow::anyhow!("template for need endfor").err(),
+                }
+                nodes.push(Node::For {
+                    var,
+                    expr,
+                    body,
+                    empty,
+                });
+            }
+            _ => return anyhow::anyhow!("template unknown tag[{}]", tag).err(),
+        }
+    }
+    Ok((nodes, None))
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PathSegment {
+    Key(String),
+    //负数从末尾开始
+    Index(i64),
+    //[*] 或 .* 展开数组元素或对象的值
+    Wildcard,
+}
+
+//解析 a.b[0].c、a[-1]、a[*].title、a["k.with.dot"] 形式的路径
+pub fn parse_path(path: &str) -> anyhow::Result<Vec<PathSegment>> {
+    let err = || anyhow::anyhow!("invalid path[{}]", path).err();
+    let chars = path.chars().collect::<Vec<_>>();
+    let mut list = vec![];
+    let mut key = String::new();
+    //上一段是否以]结束，此时允许不带key直接跟.或[
+    let mut closed = false;
+    let mut i = 0;
+    let push_key = |key: &mut String, list: &mut Vec<PathSegment>| {
+        let key = std::mem::take(key);
+        if key == "*" {
+            list.push(PathSegment::Wildcard);
+        } else {
+            list.push(PathSegment::Key(key));
+        }
+    };
+    while i < chars.len() {
+        let c = chars[i];
+        match c {
+            '.' => {
+                if key.is_empty() && !closed {
+                    return err();
+                }
+                if !key.is_empty() {
+                    push_key(&mut key, &mut list);
+                }
+                closed = false;
+                i += 1;
+                if i == chars.len() {
+                    return err();
+                }
+            }
+            '[' => {
+                if !key.is_empty() {
+                    push_key(&mut key, &mut list);
+                }
+                let Some(end) = chars[i..].iter().position(|x| *x == ']').map(|x| x + i) else {
+                    return err();
+                };
+                let inner = chars[i + 1..end].iter().collect::<String>();
+                let inner = inner.trim();
+                let quoted = inner.len() >= 2
+                    && ((inner.starts_with('"') && inner.ends_with('"'))
+                        || (inner.starts_with('\'') && inner.ends_with('\'')));
+                if inner == "*" {
+                    list.push(PathSegment::Wildcard);
+                } else if quoted {
+                    list.push(PathSegment::Key(inner[1..inner.len() - 1].to_string()));
+                } else if let Ok(index) = i64::from_str(inner) {
+                    list.push(PathSegment::Index(index));
+                } else {
+                    return err();
+                }
+                closed = true;
+                i = end + 1;
+                if i < chars.len() && chars[i] != '.' && chars[i] != '[' {
+                    return err();
+                }
+            }
+            c if c.is_whitespace() || c == ']' || c == '"' || c == '\'' => return err(),
+            c => {
+                key.push(c);
+                i += 1;
+            }
+        }
+    }
+    if !key.is_empty() {
+        push_key(&mut key, &mut list);
+    }
+    if list.is_empty() {
+        return err();
+    }
+    Ok(list)
+}
+
+fn select_into(value: &Value, path: &[PathSegment], out: &mut Vec<Value>) {
+    let Some((seg, rest)) = path.split_first() else {
+        out.push(value.clone());
+        return;
+    };
+    let index = |list: &Vec<Value>, i: i64| {
+        let i = if i < 0 { list.len() as i64 + i } else { i };
+        usize::try_from(i).ok().and_then(|i| list.get(i)).cloned()
+    };
+    let next = match (seg, value) {
+        (PathSegment::Wildcard, Value::Array(list)) => {
+            list.iter().for_each(|x| select_into(x, rest, out));
+            return;
+        }
+        (PathSegment::Wildcard, Value::Object(obj)) => {
+            obj.values().for_each(|x| select_into(x, rest, out));
+            return;
+        }
+        (PathSegment::Key(k), Value::Object(obj)) => obj.get(k).cloned(),
+        (PathSegment::Key(k), Value::Array(list)) => {
+            i64::from_str(k).ok().and_then(|i| index(list, i))
+        }
+        (PathSegment::Index(i), Value::Array(list)) => index(list, *i),
+        (PathSegment::Index(i), Value::Object(obj)) => obj.get(&i.to_string()).cloned(),
+        _ => None,
+    };
+    if let Some(next) = next {
+        select_into(&next, rest, out);
+    }
+}
+
+//按路径取值，路径中有通配符时返回所有匹配值组成的数组
+//第一个通配符之前的路径取不到数组或对象时视为找不到
+pub fn select_path(value: &Value, path: &[PathSegment]) -> Option<Value> {
+    let mut out = vec![];
+    let Some(pos) = path.iter().position(|x| x == &PathSegment::Wildcard) else {
+        select_into(value, path, &mut out);
+        return out.pop();
+    };
+    select_into(value, &path[..pos], &mut out);
+    match out.pop() {
+        Some(Value::Array(_)) | Some(Value::Object(_)) => {}
+        _ => return None,
+    }
+    select_into(value, path, &mut out);
+    Some(Value::Array(out))
+}
+
+//模板中输出的文本，字符串原样输出，其他类型输出json
+pub fn value_to_text(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(s) => s.clone(),
+        v => v.to_string(),
+    }
+}
+
+pub(crate) fn truthy(value: &Value) -> bool {
+    match value {
+        Value::Null => false,
+        Value::Bool(b) => *b,
+        Value::Number(n) => n.as_f64().map(|x| x != 0.0).unwrap_or(true),
+        Value::String(s) => !s.is_empty(),
+        Value::Array(list) => !list.is_empty(),
+        Value::Object(obj) => !obj.is_empty(),
+    }
+}
+
+pub(crate) fn compare(left: &Value, op: &str, right: &Value) -> bool {
+    let ord = match (left, right) {
+        (Value::Number(a), Value::Number(b)) => a.as_f64().partial_cmp(&b.as_f64()),
+        (Value::String(a), Value::String(b)) => Some(a.cmp(b)),
+        _ => None,
+    };
+    match op {
+        "==" => ord.map(|x| x.is_eq()).unwrap_or(left == right),
+        "!=" => !ord.map(|x| x.is_eq()).unwrap_or(left == right),
+        ">" => ord.map(|x| x.is_gt()).unwrap_or(false),
+        ">=" => ord.map(|x| x.is_ge()).unwrap_or(false),
+        "<" => ord.map(|x| x.is_lt()).unwrap_or(false),
+        "<=" => ord.map(|x| x.is_le()).unwrap_or(false),
+        _ => false,
+    }
+}
+
+struct Scope<'a> {
+    lookup: &'a dyn Fn(&str) -> Option<Value>,
+    //for循环中定义的变量，后定义的优先
+    locals: Vec<(String, Value)>,
+    //输出时找不到的变量，条件判断和带default的不算
+    missing: RefCell<Vec<String>>,
+}
+
+impl Scope<'_> {
+    fn get(&self, path: &str) -> Option<Value> {
+        let list = parse_path(path).ok()?;
+        if let Some(PathSegment::Key(name)) = list.first() {
+            for (local, value) in self.locals.iter().rev() {
+                if local == name {
+                    return select_path(value, &list[1..]);
+                }
+            }
+        }
+        (self.lookup)(path)
+    }
+    fn operand(&self, operand: &Operand) -> Option<Value> {
+        match operand {
+            Operand::Literal(v) => Some(v.clone()),
+            Operand::Path(p) => self.get(p),
+        }
+    }
+    fn filter(&self, value: Value, filter: &Filter) -> anyhow::Result<Value> {
+        let arg = |i: usize| {
+            filter
+                .args
+                .get(i)
+                .map(|x| self.operand(x).unwrap_or_default())
+        };
+        let value = match filter.name.as_str() {
+            "default" => match value {
+                Value::Null => arg(0).unwrap_or_default(),
+                Value::String(ref s) if s.is_empty() => arg(0).unwrap_or_default(),
+                v => v,
+            },
+            "json" => Value::String(value.to_string()),
+            "upper" => Value::String(value_to_text(&value).to_uppercase()),
+            "lower" => Value::String(value_to_text(&value).to_lowercase()),
+            "trim" => Value::String(value_to_text(&value).trim().to_string()),
+            "join" => {
+                let sep = arg(0).map(|x| value_to_text(&x)).unwrap_or(",".into());
+                match value {
+                    Value::Array(list) => Value::String(
+                        list.iter()
+                            .map(value_to_text)
+                            .collect::<Vec<_>>()
+                            .join(sep.as_str()),
+                    ),
+                    v => Value::String(value_to_text(&v)),
+                }
+            }
+            "length" => Value::from(match value {
+                Value::Null => 0,
+                Value::Array(ref list) => list.len(),
+                Value::Object(ref obj) => obj.len(),
+                Value::String(ref s) => s.chars().count(),
+                ref v => v.to_string().chars().count(),
+            }),
+            name => return anyhow::anyhow!("template unknown filter[{}]", name).err(),
+        };
+        Ok(value)
+    }
+    fn expr(&self, expr: &Expr, required: bool) -> anyhow::Result<Value> {
+        let mut value = match self.operand(&expr.operand) {
+            Some(v) => v,
+            None => {
+                if let Operand::Path(ref p) = expr.operand {
+                    let default = expr.filters.iter().any(|x| x.name == "default");
+                    let mut missing = self.missing.borrow_mut();
+                    if required && !default && !missing.contains(p) {
+                        missing.push(p.clone());
+                    }
+                }
+                Value::Null
+            }
+        };
+        for i in expr.filters.iter() {
+            value = self.filter(value, i)?;
+        }
+        Ok(value)
+    }
+    fn cond(&self, cond: &Cond) -> anyhow::Result<bool> {
+        let b = match cond {
+            Cond::Expr(e) => truthy(&self.expr(e, false)?),
+            Cond::Not(c) => !self.cond(c)?,
+            Cond::Compare(l, op, r) => compare(&self.expr(l, false)?, op, &self.expr(r, false)?),
+            Cond::And(list) => {
+                for i in list {
+                    if !self.cond(i)? {
+                        return Ok(false);
+                    }
+                }
+                true
+            }
+            Cond::Or(list) => {
+                for i in list {
+                    if self.cond(i)? {
+                        return Ok(true);
+                    }
+                }
+                false
+            }
+        };
+        Ok(b)
+    }
+    fn render(&mut self, nodes: &[Node], out: &mut String) -> anyhow::Result<()> {
+        for node in nodes {
+            match node {
+                Node::Text(s) => out.push_str(s),
+                Node::Output(e) => out.push_str(value_to_text(&self.expr(e, true)?).as_str()),
+                Node::If(branches, other) => {
+                    let mut body = other;
+                    for (cond, nodes) in branches {
+                        if self.cond(cond)? {
+                            body = nodes;
+                            break;
+                        }
+                    }
+                    self.render(body, out)?;
+                }
+                Node::For {
+                    var,
+                    expr,
+                    body,
+                    empty,
+                } => {
+                    //对象按 {key,value} 遍历
+                    let list = match self.expr(expr, true)? {
+                        Value::Array(list) => list,
+                        Value::Object(obj) => obj
+                            .into_iter()
+                            .map(|(k, v)| serde_json::json!({"key":k,"value":v}))
+                            .collect(),
+                        Value::Null => vec![],
+                        v => vec![v],
+                    };
+                    if list.is_empty() {
+                        self.render(empty, out)?;
+                        continue;
+                    }
+                    let length = list.len();
+                    for (i, item) in list.into_iter().enumerate() {
+                        let mut lp = Map::new();
+                        lp.insert("index".into(), Value::from(i + 1));
+                        lp.insert("index0".into(), Value::from(i));
+                        lp.insert("first".into(), Value::Bool(i == 0));
+                        lp.insert("last".into(), Value::Bool(i + 1 == length));
+                        lp.insert("length".into(), Value::from(length));
+                        self.locals.push(("loop".into(), Value::Object(lp)));
+                        self.locals.push((var.clone(), item));
+                        let res = self.render(body, out);
+                        self.locals.truncate(self.locals.len() - 2);
+                        res?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Template {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let tokens = tokenize(s)?;
+        let mut pos = 0;
+        let (nodes, _) = parse_nodes(&tokens, &mut pos, &[])?;
+        Ok(Template { nodes })
+    }
+}
+
+impl Template {
+    //只有一个 {{expr}} 时返回原始的值，否则返回渲染后的字符串
+    pub fn render(&self, lookup: &dyn Fn(&str) -> Option<Value>) -> anyhow::Result<Value> {
+        let (value, _) = self.render_checked(lookup)?;
+        Ok(value)
+    }
+    //同render，并返回输出时找不到的变量路径
+    pub fn render_checked(
+        &self,
+        lookup: &dyn Fn(&str) -> Option<Value>,
+    ) -> anyhow::Result<(Value, Vec<String>)> {
+        let mut scope = Scope {
+            lookup,
+            locals: vec![],
+            missing: RefCell::new(vec![]),
+        };
+        let value = if let [Node::Output(expr)] = self.nodes.as_slice() {
+            scope.expr(expr, true)?
+        } else {
+            let mut out = String::new();
+            scope.render(&self.nodes, &mut out)?;
+            Value::String(out)
+        };
+        Ok((value, scope.missing.into_inner()))
+    }
+    //模板中引用的变量路径，不含循环变量
+    pub fn paths(&self) -> Vec<String> {
+        fn expr_paths(e: &Expr, locals: &[String], out: &mut Vec<String>) {
+            let operands =
+                std::iter::once(&e.operand).chain(e.filters.iter().flat_map(|x| &x.args));
+            for i in operands {
+                if let Operand::Path(p) = i {
+                    let name = match parse_path(p).ok().and_then(|x| x.into_iter().next()) {
+                        Some(PathSegment::Key(name)) => name,
+                        _ => continue,
+                    };
+                    if !locals.contains(&name) && !out.contains(p) {
+                        out.push(p.clone());
+                    }
+                }
+            }
+        }
+        fn cond_paths(c: &Cond, locals: &[String], out: &mut Vec<String>) {
+            match c {
+                Cond::Expr(e) => expr_paths(e, locals, out),
+                Cond::Not(c) => cond_paths(c, locals, out),
+                Cond::Compare(l, _, r) => {
+                    expr_paths(l, locals, out);
+                    expr_paths(r, locals, out);
+                }
+                Cond::And(list) | Cond::Or(list) => {
+                    list.iter().for_each(|x| cond_paths(x, locals, out))
+                }
+            }
+        }
+        fn nodes_paths(nodes: &[Node], locals: &mut Vec<String>, out: &mut Vec<String>) {
+            for node in nodes {
+                match node {
+                    Node::Text(_) => {}
+                    Node::Output(e) => expr_paths(e, locals, out),
+                    Node::If(branches, other) => {
+                        for (c, body) in branches {
+                            cond_paths(c, locals, out);
+                            nodes_paths(body, locals, out);
+                        }
+                        nodes_paths(other, locals, out);
+                    }
+                    Node::For {
+                        var,
+                        expr,
+                        body,
+                        empty,
+                    } => {
+                        expr_paths(expr, locals, out);
+                        nodes_paths(empty, locals, out);
+                        locals.push(var.clone());
+                        locals.push("loop".into());
+                        nodes_paths(body, locals, out);
+                        locals.truncate(locals.len() - 2);
+                    }
+                }
+            }
+        }
+        let mut out = vec![];
+        nodes_paths(&self.nodes, &mut vec![], &mut out);
+        out
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::rt_node_service::{parse_path, select_path, PathSegment, Template};
+    use serde_json::Value;
+    use std::str::FromStr;
+
+    fn render(tpl: &str, data: &Value) -> Value {
+        let lookup = |path: &str| select_path(data, &parse_path(path).ok()?);
+        Template::from_str(tpl).unwrap().render(&lookup).unwrap()
+    }
+
+    //cargo test rt_node_service::template::test::test_template -- --nocapture
+    #[test]
+    fn test_template() {
+        let data = serde_json::json!({
+            "a":{"name":" wd ","n":3,"tags":["x","y"],"ok":true,"obj":{"k":1}},
+            "users":[{"name":"tom","age":20},{"name":"amy","age":17}],
+            "empty":[]
+        });
+
+        //单个表达式保留原始类型
+        assert_eq!(serde_json::json!(3), render("{{a.n}}", &data));
+        assert_eq!(serde_json::json!(["x", "y"]), render("{{ a.tags }}", &data));
+        assert_eq!(serde_json::json!(2), render("{{a.tags | length}}", &data));
+        assert_eq!(Value::Null, render("{{a.none}}", &data));
+        //拼接时数字和对象不再输出空字符串
+        assert_eq!(
+            serde_json::json!(r#"n=3 obj={"k":1} tags=["x","y"]"#),
+            render("n={{a.n}} obj={{a.obj}} tags={{a.tags}}", &data)
+        );
+        assert_eq!(
+            serde_json::json!("n/a|WD|x; y|\"x\""),
+            render(
+                r#"{{a.none | default:"n/a"}}|{{a.name | trim | upper}}|{{a.tags | join:"; "}}|{{a.tags.0 | json}}"#,
+                &data
+            )
+        );
+        assert_eq!(
+            serde_json::json!(5),
+            render("{{a.none | default:5}}", &data)
+        );
+
+        let tpl = "{% for u in users %}{{loop.index}}.{{u.name}}{% if u.age >= 18 %}(adult){% elif u.age > 10 and not a.none %}(teen){% else %}(kid){% endif %}{% if not loop.last %}, {% endif %}{% endfor %}";
+        assert_eq!(
+            serde_json::json!("1.tom(adult), 2.amy(teen)"),
+            render(tpl, &data)
+        );
+        let tpl = "{% for i in empty %}{{i}}{% else %}none{% endfor %}|{% for i in a.obj %}{{i.key}}={{i.value}}{% endfor %}";
+        assert_eq!(serde_json::json!("none|k=1"), render(tpl, &data));
+        let tpl =
+            r#"{% if a.name == " wd " or a.none %}yes{% endif %}{% if a.n != 3 %}no{% endif %}"#;
+        assert_eq!(serde_json::json!("yes"), render(tpl, &data));
+
+        //转义
+        assert_eq!(
+            serde_json::json!("{{a.n}} {% x %} {{y}} 3"),
+            render(
+                r"\{{a.n}} \{% x %} {% raw %}{{y}}{% endraw %} {{a.n}}",
+                &data
+            )
+        );
+        assert_eq!(serde_json::json!("a}}b"), render(r#"{{"a}}b"}}"#, &data));
+
+        let tpl = Template::from_str("{{a.b}}{% for i in c %}{{i.x}}{{d | default:e}}{% endfor %}")
+            .unwrap();
+        assert_eq!(vec!["a.b", "c", "d", "e"], tpl.paths());
+
+        for i in [
+            "{{a",
+            "{% if a %}x",
+            "{% for a %}{% endfor %}",
+            "{% endif %}",
+            "{% what %}",
+            "{% raw %}x",
+        ] {
+            assert!(Template::from_str(i).is_err(), "{}", i);
+        }
+        let tpl = Template::from_str("{{a | nope}}").unwrap();
+        assert!(tpl.render(&|_| None).is_err());
+    }
+
+    //cargo test rt_node_service::template::test::test_template_path -- --nocapture
+    #[test]
+    fn test_template_path() {
+        assert_eq!(
+            vec![
+                PathSegment::Key("docs".into()),
+                PathSegment::Wildcard,
+                PathSegment::Key("a.b".into()),
+                PathSegment::Index(-1),
+                PathSegment::Key("0".into()),
+                PathSegment::Wildcard,
+            ],
+            parse_path(r#"docs[*]["a.b"][-1].0.*"#).unwrap()
+        );
+        for i in ["", "a..b", "a.", ".a", "a[x]", "a[1", "a b", "a[0]b"] {
+            assert!(parse_path(i).is_err(), "{}", i);
+        }
+
+        let data = serde_json::json!({
+            "docs":[
+                {"title":"a","tags":["x","y"]},
+                {"title":"b","tags":["z"]},
+                {"other":1}
+            ],
+            "k":{"a.b":{"c":1}}
+        });
+        assert_eq!(
+            serde_json::json!(["a", "b"]),
+            render("{{docs[*].title}}", &data)
+        );
+        assert_eq!(
+            serde_json::json!(["x", "y", "z"]),
+            render("{{docs[*].tags[*]}}", &data)
+        );
+        assert_eq!(serde_json::json!("b"), render("{{docs[-2].title}}", &data));
+        assert_eq!(serde_json::json!("y"), render("{{docs.0.tags.-1}}", &data));
+        assert_eq!(serde_json::json!(1), render(r#"{{k["a.b"].c}}"#, &data));
+        assert_eq!(
+            serde_json::json!("a,b"),
+            render("{{docs[*].title | join}}", &data)
+        );
+        assert_eq!(Value::Null, render("{{docs[9]}}", &data));
+        assert_eq!(Value::Null, render("{{docz[*].title}}", &data));
+        assert_eq!(Value::Null, render("{{k.c[*]}}", &data));
+        assert!(Template::from_str("{{a b}}").is_err());
+        assert!(Template::from_str("{{[0]}}").is_err());
+
+        //只统计输出用到的变量，条件判断和带default的不算
+        let lookup = |path: &str| select_path(&data, &parse_path(path).ok()?);
+        let tpl = Template::from_str(
+            "{{docs[0].title}}{{x.y}}{{x.z | default:1}}{% if x.w %}{{x.w}}{% endif %}{% for d in docs %}{{d.title}}{% endfor %}{{x.y}}",
+        )
+        .unwrap();
+        let (_, missing) = tpl.render_checked(&lookup).unwrap();
+        assert_eq!(vec!["x.y", "d.title"], missing);
+    }
+}
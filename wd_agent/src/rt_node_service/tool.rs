@@ -1,10 +1,14 @@
+use crate::llm_provider::ChatToolCall;
 use crate::plugin_tools::PluginControlSchedule;
 use crate::rt_node_service::in_out_bonding::CfgBound;
-use agent_rt::{Context, ServiceLayer};
+use crate::rt_node_service::LLMContextMessage;
+use agent_rt::{Context, ServiceLayer, Usage};
+use futures::{StreamExt, TryStreamExt};
 use serde::{Deserialize, Serialize};
 use std::fmt::Debug;
 use std::sync::Arc;
 use serde_json::Value;
+use wd_tools::PFErr;
 
 #[async_trait::async_trait]
 pub trait ToolEvent: Send {
@@ -49,6 +53,30 @@ impl LLMToolCallRequest {
     pub fn as_json(&self) -> String {
         serde_json::to_string(self).unwrap()
     }
+    //input不为空时替代args作为工具参数，flatten在没有多余字段时会得到空对象
+    pub fn tool_args(&self) -> anyhow::Result<String> {
+        match self.input {
+            Some(Value::Object(ref m)) if m.is_empty() => Ok(self.args.clone()),
+            Some(Value::Null) | None => Ok(self.args.clone()),
+            Some(ref s) => Ok(serde_json::to_string(s)?),
+        }
+    }
+    //和实际调用的参数保持一致
+    pub fn to_chat_tool_call(&self) -> ChatToolCall {
+        let args = self.tool_args().unwrap_or_else(|_| self.args.clone());
+        ChatToolCall::new(
+            self.call_id.clone().unwrap_or_default(),
+            self.name.as_str(),
+            args.as_str(),
+        )
+    }
+    pub fn to_context_message(&self) -> LLMContextMessage {
+        LLMContextMessage {
+            role: "assistant".into(),
+            tool_calls: vec![self.to_chat_tool_call()],
+            ..Default::default()
+        }
+    }
 }
 
 #[derive(Debug, Default, Clone, Deserialize, Serialize)]
@@ -56,6 +84,73 @@ pub struct LLMToolCallResponse {
     pub call_id: Option<String>,
     // pub code: isize,
     pub content: String,
+    //工具执行失败时的错误信息，此时content为回填给模型的错误描述
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl LLMToolCallResponse {
+    pub fn to_context_message(&self) -> LLMContextMessage {
+        LLMContextMessage {
+            role: "tool".into(),
+            content: self.content.clone(),
+            call_id: self.call_id.clone().unwrap_or_default(),
+            ..Default::default()
+        }
+    }
+}
+
+//执行一个工具调用，工具执行失败时将错误写入结果而不是中断，超出预算时返回错误
+pub async fn call_tool_event(
+    event: &(dyn ToolEvent + Sync),
+    ctx: &Context,
+    call: &LLMToolCallRequest,
+) -> anyhow::Result<LLMToolCallResponse> {
+    ctx.report_usage(Usage::tool_call())?;
+    let result = match call.tool_args() {
+        Ok(args) => event.call(call.name.as_str(), args).await,
+        Err(e) => Err(e),
+    };
+    let resp = match result {
+        Ok(content) => LLMToolCallResponse {
+            call_id: call.call_id.clone(),
+            content,
+            error: None,
+        },
+        Err(e) => LLMToolCallResponse {
+            call_id: call.call_id.clone(),
+            content: format!("tool[{}] error:{}", call.name, e),
+            error: Some(e.to_string()),
+        },
+    };
+    Ok(resp)
+}
+
+//并发执行多个工具调用，结果顺序与请求一致
+//fail_fast为true时遇到第一个失败的工具即返回错误，不再执行后面的调用
+pub async fn call_tool_batch(
+    event: &(dyn ToolEvent + Sync),
+    ctx: &Context,
+    calls: &[LLMToolCallRequest],
+    concurrency: usize,
+    fail_fast: bool,
+) -> anyhow::Result<Vec<LLMToolCallResponse>> {
+    let list = calls
+        .iter()
+        .map(|call| async move {
+            let resp = call_tool_event(event, ctx, call).await?;
+            match resp.error {
+                Some(ref e) if fail_fast => {
+                    anyhow::anyhow!("tool[{}] error:{}", call.name, e).err()
+                }
+                _ => Ok(resp),
+            }
+        })
+        .collect::<Vec<_>>();
+    futures::stream::iter(list)
+        .buffered(concurrency.max(1))
+        .try_collect()
+        .await
 }
 
 #[async_trait::async_trait]
@@ -70,35 +165,136 @@ impl ServiceLayer for ToolService {
         cfg: Self::Config,
     ) -> anyhow::Result<Self::Output> {
         let cfg = cfg.bound(&ctx)?;
+        let args = cfg.tool_args()?;
 
-        let LLMToolCallRequest {
-            call_id,
-            name,
-            mut args,
-            input,
-        } = cfg;
+        let LLMToolCallRequest { call_id, name, .. } = cfg;
 
-        wd_log::log_debug_ln!("code[{}] exec tool[{}] args:{:?} input:{:?}", code, name, args,input);
+        wd_log::log_debug_ln!("code[{}] exec tool[{}] args:{:?}", code, name, args);
 
-        if let Some(s) = input {
-            args = serde_json::to_string(&s)?;
-        }
+        ctx.report_usage(Usage::tool_call())?;
 
         let content = self.loader.call(name.as_str(), args).await?;
 
-        let resp = LLMToolCallResponse { call_id, content };
+        let resp = LLMToolCallResponse {
+            call_id,
+            content,
+            error: None,
+        };
         wd_log::log_debug_ln!("code[{}] exec tool[{}] result[{:?}]", code, name, resp);
         Ok(resp)
     }
 }
 
+pub struct ToolBatchService {
+    loader: Box<dyn ToolEvent + Sync + 'static>,
+}
+
+impl<T: ToolEvent + Sync + 'static> From<T> for ToolBatchService {
+    fn from(value: T) -> Self {
+        let loader = Box::new(value);
+        Self { loader }
+    }
+}
+impl Default for ToolBatchService {
+    fn default() -> Self {
+        Self::from(PluginControlSchedule::default().to_tool_event())
+    }
+}
+
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+pub struct ToolBatchRequest {
+    //通常绑定为 {{llm.tools}}，为空时不执行
+    #[serde(default = "Option::default")]
+    pub tools: Option<Vec<LLMToolCallRequest>>,
+    #[serde(default = "ToolBatchRequest::default_concurrency")]
+    pub concurrency: usize,
+    //任意一个工具失败时整个节点失败，不再执行后面的调用
+    #[serde(default = "bool::default")]
+    pub fail_fast: bool,
+}
+
+impl ToolBatchRequest {
+    pub fn default_concurrency() -> usize {
+        4
+    }
+}
+
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ToolBatchResponse {
+    pub results: Vec<LLMToolCallResponse>,
+    //assistant工具调用与tool结果消息，可直接追加到llm.context
+    pub context: Vec<LLMContextMessage>,
+    pub errors: usize,
+}
+
+#[async_trait::async_trait]
+impl ServiceLayer for ToolBatchService {
+    type Config = CfgBound<ToolBatchRequest>;
+    type Output = ToolBatchResponse;
+
+    async fn call(
+        &self,
+        code: String,
+        ctx: Arc<Context>,
+        cfg: Self::Config,
+    ) -> anyhow::Result<Self::Output> {
+        let ToolBatchRequest {
+            tools,
+            concurrency,
+            fail_fast,
+        } = cfg.bound(&ctx)?;
+        let tools = tools.unwrap_or_default();
+
+        wd_log::log_debug_ln!(
+            "code[{}] exec {} tools concurrency[{}]",
+            code,
+            tools.len(),
+            concurrency
+        );
+
+        let results = call_tool_batch(
+            self.loader.as_ref(),
+            &ctx,
+            tools.as_slice(),
+            concurrency,
+            fail_fast,
+        )
+        .await
+        .map_err(|e| anyhow::anyhow!("code[{}] {}", code, e))?;
+
+        let mut resp = ToolBatchResponse::default();
+        if !tools.is_empty() {
+            //一条assistant消息带上全部调用，后面跟各自的tool结果
+            resp.context.push(LLMContextMessage {
+                role: "assistant".into(),
+                tool_calls: tools.iter().map(|x| x.to_chat_tool_call()).collect(),
+                ..Default::default()
+            });
+        }
+        for result in results.iter() {
+            if result.error.is_some() {
+                resp.errors += 1;
+            }
+            resp.context.push(result.to_context_message());
+        }
+        resp.results = results;
+        Ok(resp)
+    }
+}
+
 #[cfg(test)]
 mod test {
 
     use crate::plugin_tools::PluginControlSchedule;
-    use crate::rt_node_service::{LLMNodeResponse, OpenaiLLMService, ToolService};
-    use agent_rt::{PlanBuilder, Runtime};
+    use crate::rt_node_service::{
+        LLMNodeResponse, OpenaiLLMService, ToolBatchResponse, ToolBatchService, ToolService,
+    };
+    use agent_rt::{Budget, PlanBuilder, Runtime};
     use serde_json::Value;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
     use wd_tools::PFArc;
 
     //cargo test rt_node_service::tool::test::test_llm_tools -- --nocapture
@@ -150,4 +346,125 @@ mod test {
             println!("tool resp --->{}", tool_resp.to_string());
         }
     }
+
+    //cargo test rt_node_service::tool::test::test_tool_service_args -- --nocapture
+    #[tokio::test]
+    async fn test_tool_service_args() {
+        let tools = PluginControlSchedule::default()
+            .register_plugin("echo", |x: String| async move { Ok(x) })
+            .to_tool_event();
+        let rt = Runtime::default()
+            .register_service_layer("tool", ToolService::from(tools))
+            .launch();
+
+        //没有多余字段时使用args，否则使用展开的input
+        for (cfg, expect) in [
+            (r#"{"call_id":"c0","name":"echo","args":"raw"}"#, "raw"),
+            (r#"{"call_id":"c1","name":"echo","q":"x"}"#, r#"{"q":"x"}"#),
+        ] {
+            let res = rt
+                .ctx("test_tool_service_args", PlanBuilder::single_node("tool", cfg).build())
+                .arc()
+                .block_on::<Value, _>(())
+                .await
+                .unwrap();
+            assert_eq!(expect, res["content"].as_str().unwrap());
+        }
+    }
+
+    //cargo test rt_node_service::tool::test::test_tool_batch -- --nocapture
+    #[tokio::test]
+    async fn test_tool_batch() {
+        let running = Arc::new(AtomicUsize::new(0));
+        let max_running = Arc::new(AtomicUsize::new(0));
+        let started = Arc::new(AtomicUsize::new(0));
+        let (r, m, st) = (running.clone(), max_running.clone(), started.clone());
+        let tools = PluginControlSchedule::default()
+            .register_plugin("sleep", move |x: String| {
+                let (r, m) = (r.clone(), m.clone());
+                st.fetch_add(1, Ordering::SeqCst);
+                async move {
+                    let n = r.fetch_add(1, Ordering::SeqCst) + 1;
+                    m.fetch_max(n, Ordering::SeqCst);
+                    let ms = x.parse::<u64>().unwrap_or(0);
+                    tokio::time::sleep(Duration::from_millis(ms)).await;
+                    r.fetch_sub(1, Ordering::SeqCst);
+                    Ok(format!("slept {ms}"))
+                }
+            })
+            .to_tool_event();
+        let rt = Runtime::default()
+            .register_service_layer("tool_batch", ToolBatchService::from(tools))
+            .launch();
+
+        let calls = serde_json::json!([
+            {"call_id":"c0","name":"sleep","args":"60"},
+            {"call_id":"c1","name":"sleep","args":"10"},
+            {"call_id":"c2","name":"missing","q":"x"},
+            {"call_id":"c3","name":"sleep","args":"30"},
+        ]);
+        let cfg = r#"{"tools":"{{start.tools}}","concurrency":2}"#;
+        let res = rt
+            .ctx("test_tool_batch", PlanBuilder::single_node("tool_batch", cfg).build())
+            .arc()
+            .block_on::<Value, _>(serde_json::json!({ "tools": calls }))
+            .await
+            .unwrap();
+        let resp = serde_json::from_value::<ToolBatchResponse>(res).unwrap();
+        let ids = resp
+            .results
+            .iter()
+            .map(|x| x.call_id.clone().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(vec!["c0", "c1", "c2", "c3"], ids);
+        assert_eq!("slept 60", resp.results[0].content.as_str());
+        assert!(resp.results[2].error.is_some());
+        assert_eq!(1, resp.errors);
+        assert_eq!(5, resp.context.len());
+        assert_eq!(4, resp.context[0].tool_calls.len());
+        assert_eq!("c2", resp.context[0].tool_calls[2].id.as_str());
+        assert_eq!(
+            r#"{"q":"x"}"#,
+            resp.context[0].tool_calls[2].arguments.as_str()
+        );
+        assert_eq!("tool", resp.context[1].role.as_str());
+        assert_eq!("c0", resp.context[1].call_id.as_str());
+        assert_eq!(2, max_running.load(Ordering::SeqCst));
+
+        let cfg = r#"{"tools":"{{start.tools}}","fail_fast":true}"#;
+        let res = rt
+            .ctx("test_tool_batch_fail", PlanBuilder::single_node("tool_batch", cfg).build())
+            .arc()
+            .block_on::<Value, _>(serde_json::json!({ "tools": calls }))
+            .await;
+        assert!(format!("{:?}", res).contains("tool[missing] error"));
+
+        //第一个失败后不再执行后面的调用
+        let calls = serde_json::json!([
+            {"call_id":"c0","name":"missing"},
+            {"call_id":"c1","name":"sleep","args":"10"},
+        ]);
+        let cfg = r#"{"tools":"{{start.tools}}","concurrency":1,"fail_fast":true}"#;
+        let before = started.load(Ordering::SeqCst);
+        let res = rt
+            .ctx("test_tool_batch_fail", PlanBuilder::single_node("tool_batch", cfg).build())
+            .arc()
+            .block_on::<Value, _>(serde_json::json!({ "tools": calls }))
+            .await;
+        assert!(format!("{:?}", res).contains("tool[missing] error"));
+        assert_eq!(before, started.load(Ordering::SeqCst));
+
+        //超出预算时节点失败，而不是写入工具结果
+        let cfg = r#"{"tools":"{{start.tools}}"}"#;
+        let ctx = rt.ctx(
+            "test_tool_batch_budget",
+            PlanBuilder::single_node("tool_batch", cfg).build(),
+        );
+        ctx.set_budget(Budget::new().max_tool_calls(1));
+        let res = ctx
+            .arc()
+            .block_on::<Value, _>(serde_json::json!({ "tools": calls }))
+            .await;
+        assert!(format!("{:?}", res).contains("budget exceeded"));
+    }
 }
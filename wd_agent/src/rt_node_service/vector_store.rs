@@ -0,0 +1,606 @@
+use crate::llm_provider::fnv1a;
+use crate::rt_node_service::memory::safe_file_name;
+use crate::rt_node_service::{CfgBound, EmbeddingService};
+use agent_rt::Context;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use wd_tools::PFErr;
+
+#[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct VectorDocument {
+    //为空时按text的hash生成，相同id覆盖写入
+    pub id: String,
+    pub text: String,
+    pub metadata: Map<String, Value>,
+    //为空时由upsert节点调用embedding生成
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub vector: Vec<f32>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct VectorMatch {
+    pub id: String,
+    pub text: String,
+    pub score: f32,
+    pub metadata: Map<String, Value>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct VectorQuery {
+    pub vector: Vec<f32>,
+    pub top_k: usize,
+    //metadata按key相等匹配，值为数组时匹配其中任意一个
+    pub filter: Map<String, Value>,
+    //低于该相似度的结果丢弃
+    pub min_score: f32,
+}
+
+#[async_trait::async_trait]
+pub trait VectorStore: Send + Sync {
+    async fn upsert(&self, collection: &str, docs: Vec<VectorDocument>) -> anyhow::Result<()>;
+    //删除metadata匹配filter的旧文档并写入docs，返回删除的数量
+    async fn replace(
+        &self,
+        collection: &str,
+        filter: &Map<String, Value>,
+        docs: Vec<VectorDocument>,
+    ) -> anyhow::Result<usize>;
+    //按余弦相似度从高到低返回
+    async fn query(
+        &self,
+        collection: &str,
+        query: &VectorQuery,
+    ) -> anyhow::Result<Vec<VectorMatch>>;
+}
+
+pub fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    let mut dot = 0f32;
+    let mut na = 0f32;
+    let mut nb = 0f32;
+    for (x, y) in a.iter().zip(b.iter()) {
+        dot += x * y;
+        na += x * x;
+        nb += y * y;
+    }
+    if na == 0.0 || nb == 0.0 {
+        return 0.0;
+    }
+    dot / (na.sqrt() * nb.sqrt())
+}
+
+fn match_filter(metadata: &Map<String, Value>, filter: &Map<String, Value>) -> bool {
+    filter.iter().all(|(k, v)| {
+        let Some(m) = metadata.get(k) else {
+            return false;
+        };
+        match (v, m) {
+            (Value::Array(list), _) => list.contains(m),
+            (_, Value::Array(list)) => list.contains(v),
+            _ => m == v,
+        }
+    })
+}
+
+//进程内的暴力检索索引，设置path后每个collection保存为一个json文件
+#[derive(Debug, Default)]
+pub struct LocalVectorStore {
+    pub path: Option<String>,
+    collections: Mutex<HashMap<String, Vec<VectorDocument>>>,
+    //同一个collection的写入串行执行，保证文件内容与内存一致
+    writers: Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>,
+}
+
+impl LocalVectorStore {
+    pub fn new<P: Into<String>>(path: P) -> Self {
+        Self {
+            path: Some(path.into()),
+            collections: Mutex::new(HashMap::new()),
+            writers: Mutex::new(HashMap::new()),
+        }
+    }
+    fn writer(&self, collection: &str) -> Arc<tokio::sync::Mutex<()>> {
+        let mut lock = self.writers.lock().unwrap();
+        lock.entry(collection.to_string()).or_default().clone()
+    }
+    fn file_path(&self, collection: &str) -> Option<String> {
+        self.path
+            .as_ref()
+            .map(|p| format!("{}/{}.json", p, safe_file_name(collection)))
+    }
+    //首次访问时从磁盘加载
+    async fn load(&self, collection: &str) -> anyhow::Result<()> {
+        if self.collections.lock().unwrap().contains_key(collection) {
+            return Ok(());
+        }
+        let docs = match self.file_path(collection) {
+            Some(path) => match tokio::fs::read(path).await {
+                Ok(data) => serde_json::from_slice(data.as_slice())?,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => vec![],
+                Err(e) => return Err(anyhow::Error::from(e)),
+            },
+            None => vec![],
+        };
+        let mut lock = self.collections.lock().unwrap();
+        lock.entry(collection.to_string()).or_insert(docs);
+        Ok(())
+    }
+    fn snapshot(&self, collection: &str) -> anyhow::Result<Option<Vec<u8>>> {
+        if self.path.is_none() {
+            return Ok(None);
+        }
+        let lock = self.collections.lock().unwrap();
+        let list = lock
+            .get(collection)
+            .map(|x| x.as_slice())
+            .unwrap_or_default();
+        Ok(Some(serde_json::to_vec(list)?))
+    }
+    //调用方需持有collection的writer锁
+    async fn persist(&self, collection: &str) -> anyhow::Result<()> {
+        let (Some(path), Some(data)) = (self.path.as_ref(), self.snapshot(collection)?) else {
+            return Ok(());
+        };
+        tokio::fs::create_dir_all(path).await?;
+        //先写临时文件再改名，避免写一半时进程退出损坏索引
+        let file = self.file_path(collection).unwrap_or_default();
+        let tmp = format!("{}.tmp", file);
+        tokio::fs::write(tmp.as_str(), data).await?;
+        tokio::fs::rename(tmp, file).await?;
+        Ok(())
+    }
+    async fn write(
+        &self,
+        collection: &str,
+        filter: Option<&Map<String, Value>>,
+        docs: Vec<VectorDocument>,
+    ) -> anyhow::Result<usize> {
+        let writer = self.writer(collection);
+        let _guard = writer.lock().await;
+        self.load(collection).await?;
+        let removed = {
+            let mut lock = self.collections.lock().unwrap();
+            let list = lock.entry(collection.to_string()).or_default();
+            let stale = |x: &VectorDocument| filter.is_some_and(|f| match_filter(&x.metadata, f));
+            //整批校验通过后再写入，避免部分文档生效
+            let mut dims = list.iter().find(|x| !stale(x)).map(|x| x.vector.len());
+            for doc in docs.iter() {
+                let n = *dims.get_or_insert(doc.vector.len());
+                if doc.vector.is_empty() || doc.vector.len() != n {
+                    return anyhow::anyhow!(
+                        "collection[{}] doc[{}] vector dims[{}] expect[{}]",
+                        collection,
+                        doc.id,
+                        doc.vector.len(),
+                        n
+                    )
+                    .err();
+                }
+            }
+            let count = list.len();
+            list.retain(|x| !stale(x));
+            let removed = count - list.len();
+            for doc in docs {
+                match list.iter_mut().find(|x| x.id == doc.id) {
+                    Some(old) => *old = doc,
+                    None => list.push(doc),
+                }
+            }
+            removed
+        };
+        self.persist(collection).await?;
+        Ok(removed)
+    }
+}
+
+#[async_trait::async_trait]
+impl VectorStore for LocalVectorStore {
+    async fn upsert(&self, collection: &str, docs: Vec<VectorDocument>) -> anyhow::Result<()> {
+        self.write(collection, None, docs).await?;
+        Ok(())
+    }
+
+    async fn replace(
+        &self,
+        collection: &str,
+        filter: &Map<String, Value>,
+        docs: Vec<VectorDocument>,
+    ) -> anyhow::Result<usize> {
+        self.write(collection, Some(filter), docs).await
+    }
+
+    async fn query(
+        &self,
+        collection: &str,
+        query: &VectorQuery,
+    ) -> anyhow::Result<Vec<VectorMatch>> {
+        self.load(collection).await?;
+        let lock = self.collections.lock().unwrap();
+        let list = match lock.get(collection) {
+            Some(s) => s,
+            None => return Ok(vec![]),
+        };
+        if let Some(doc) = list.first() {
+            if doc.vector.len() != query.vector.len() {
+                return anyhow::anyhow!(
+                    "collection[{}] vector dims[{}] query dims[{}]",
+                    collection,
+                    doc.vector.len(),
+                    query.vector.len()
+                )
+                .err();
+            }
+        }
+        let mut result = list
+            .iter()
+            .filter(|x| match_filter(&x.metadata, &query.filter))
+            .map(|x| VectorMatch {
+                id: x.id.clone(),
+                text: x.text.clone(),
+                score: cosine_similarity(&x.vector, &query.vector),
+                metadata: x.metadata.clone(),
+            })
+            .filter(|x| x.score >= query.min_score)
+            .collect::<Vec<_>>();
+        result.sort_by(|a, b| b.score.total_cmp(&a.score));
+        result.truncate(query.top_k);
+        Ok(result)
+    }
+}
+
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+pub struct VectorUpsertRequest {
+    pub collection: String,
+    pub docs: Vec<VectorDocument>,
+    //生成向量使用的embedding厂商和模型，为空使用默认
+    #[serde(default = "String::default")]
+    pub provider: String,
+    #[serde(default = "String::default")]
+    pub model: String,
+    //删除metadata.source相同的旧文档后写入，重新导入变更过的文档时使用
+    #[serde(default = "bool::default")]
+    pub replace: bool,
+}
+
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+pub struct VectorUpsertResponse {
+    pub collection: String,
+    pub count: usize,
+    pub ids: Vec<String>,
+    //replace删除的旧文档数
+    #[serde(default = "usize::default")]
+    pub removed: usize,
+}
+
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+pub struct VectorQueryRequest {
+    pub collection: String,
+    //查询文本，vector为空时用它生成向量
+    #[serde(default = "String::default")]
+    pub query: String,
+    #[serde(default = "Vec::default")]
+    pub vector: Vec<f32>,
+    #[serde(default = "VectorQueryRequest::default_top_k")]
+    pub top_k: usize,
+    #[serde(default = "Map::default")]
+    pub filter: Map<String, Value>,
+    #[serde(default = "f32::default")]
+    pub min_score: f32,
+    #[serde(default = "String::default")]
+    pub provider: String,
+    #[serde(default = "String::default")]
+    pub model: String,
+}
+
+impl VectorQueryRequest {
+    pub fn default_top_k() -> usize {
+        4
+    }
+}
+
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+pub struct VectorQueryResponse {
+    //命中文本按相似度拼接，可直接在prompt中使用 {{retrieve.docs}}
+    pub docs: String,
+    pub matches: Vec<VectorMatch>,
+}
+
+//把文档生成向量后写入collection
+pub struct VectorUpsertService {
+    store: Arc<dyn VectorStore>,
+    embedding: EmbeddingService,
+}
+
+//按查询文本检索collection中最相似的文档
+pub struct VectorQueryService {
+    store: Arc<dyn VectorStore>,
+    embedding: EmbeddingService,
+}
+
+impl VectorUpsertService {
+    pub fn new(store: Arc<dyn VectorStore>, embedding: EmbeddingService) -> Self {
+        Self { store, embedding }
+    }
+}
+
+impl VectorQueryService {
+    pub fn new(store: Arc<dyn VectorStore>, embedding: EmbeddingService) -> Self {
+        Self { store, embedding }
+    }
+}
+
+#[async_trait::async_trait]
+impl agent_rt::ServiceLayer for VectorUpsertService {
+    type Config = CfgBound<VectorUpsertRequest>;
+    type Output = VectorUpsertResponse;
+
+    async fn call(
+        &self,
+        _code: String,
+        ctx: Arc<Context>,
+        cfg: Self::Config,
+    ) -> anyhow::Result<Self::Output> {
+        let VectorUpsertRequest {
+            collection,
+            mut docs,
+            provider,
+            model,
+            replace,
+        } = cfg.bound(&ctx)?;
+        for doc in docs.iter_mut() {
+            if doc.id.is_empty() {
+                doc.id = format!("{:016x}", fnv1a(doc.text.as_str()));
+            }
+        }
+        let index = docs
+            .iter()
+            .enumerate()
+            .filter(|(_, x)| x.vector.is_empty())
+            .map(|(i, _)| i)
+            .collect::<Vec<_>>();
+        let input = index.iter().map(|i| docs[*i].text.clone()).collect();
+        let resp = self
+            .embedding
+            .embed(&ctx, provider.as_str(), model.as_str(), input)
+            .await?;
+        for (i, vector) in index.into_iter().zip(resp.vectors) {
+            docs[i].vector = vector;
+        }
+
+        let mut sources = vec![];
+        for source in docs.iter().filter_map(|x| x.metadata.get("source")) {
+            if replace && !sources.contains(source) {
+                sources.push(source.clone());
+            }
+        }
+
+        let ids = docs.iter().map(|x| x.id.clone()).collect::<Vec<_>>();
+        let removed = if sources.is_empty() {
+            self.store.upsert(collection.as_str(), docs).await?;
+            0
+        } else {
+            let mut filter = Map::new();
+            filter.insert("source".into(), Value::Array(sources));
+            self.store
+                .replace(collection.as_str(), &filter, docs)
+                .await?
+        };
+        Ok(VectorUpsertResponse {
+            collection,
+            count: ids.len(),
+            ids,
+            removed,
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl agent_rt::ServiceLayer for VectorQueryService {
+    type Config = CfgBound<VectorQueryRequest>;
+    type Output = VectorQueryResponse;
+
+    async fn call(
+        &self,
+        _code: String,
+        ctx: Arc<Context>,
+        cfg: Self::Config,
+    ) -> anyhow::Result<Self::Output> {
+        let cfg = cfg.bound(&ctx)?;
+        let mut vector = cfg.vector;
+        if vector.is_empty() {
+            if cfg.query.is_empty() {
+                return anyhow::anyhow!("vector query need query or vector").err();
+            }
+            let resp = self
+                .embedding
+                .embed(
+                    &ctx,
+                    cfg.provider.as_str(),
+                    cfg.model.as_str(),
+                    vec![cfg.query],
+                )
+                .await?;
+            vector = resp.vectors.into_iter().next().unwrap_or_default();
+        }
+        let query = VectorQuery {
+            vector,
+            top_k: cfg.top_k,
+            filter: cfg.filter,
+            min_score: cfg.min_score,
+        };
+        let matches = self.store.query(cfg.collection.as_str(), &query).await?;
+        let docs = matches
+            .iter()
+            .map(|x| x.text.as_str())
+            .collect::<Vec<_>>()
+            .join("\n\n");
+        Ok(VectorQueryResponse { docs, matches })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::llm_provider::HashEmbeddingProvider;
+    use crate::rt_node_service::{
+        cosine_similarity, EmbeddingService, LocalVectorStore, VarFlowChartService, VectorDocument,
+        VectorQuery, VectorQueryService, VectorStore, VectorUpsertResponse, VectorUpsertService,
+    };
+    use agent_rt::{PlanBuilder, Runtime};
+    use serde_json::Value;
+    use std::sync::Arc;
+    use wd_tools::PFArc;
+
+    fn doc(id: &str, vector: Vec<f32>, lang: &str) -> VectorDocument {
+        VectorDocument {
+            id: id.into(),
+            text: id.into(),
+            metadata: serde_json::json!({ "lang": lang })
+                .as_object()
+                .unwrap()
+                .clone(),
+            vector,
+        }
+    }
+
+    //cargo test rt_node_service::vector_store::test::test_local_vector_store -- --nocapture
+    #[tokio::test]
+    async fn test_local_vector_store() {
+        assert!((cosine_similarity(&[1.0, 0.0], &[2.0, 0.0]) - 1.0).abs() < 1e-6);
+        assert_eq!(0.0, cosine_similarity(&[0.0, 0.0], &[1.0, 0.0]));
+
+        let path = std::env::temp_dir().join("wd_agent_vector_store_test");
+        let _ = std::fs::remove_dir_all(&path);
+        let store = LocalVectorStore::new(path.to_string_lossy().to_string());
+        let docs = vec![
+            doc("a", vec![1.0, 0.0], "en"),
+            doc("b", vec![0.8, 0.2], "zh"),
+            doc("c", vec![0.0, 1.0], "en"),
+        ];
+        store.upsert("kb/1", docs).await.unwrap();
+        store
+            .upsert("kb/1", vec![doc("c", vec![0.6, 0.4], "en")])
+            .await
+            .unwrap();
+        assert!(store
+            .upsert("kb/1", vec![doc("d", vec![1.0], "en")])
+            .await
+            .is_err());
+        //批次中有一个维度不对时整批不生效
+        assert!(store
+            .upsert(
+                "kb/1",
+                vec![doc("e", vec![1.0, 0.0], "en"), doc("f", vec![1.0], "en")]
+            )
+            .await
+            .is_err());
+
+        //并发写入同一个collection，文件与内存一致
+        let store = Arc::new(store);
+        let mut tasks = vec![];
+        for i in 0..20 {
+            let store = store.clone();
+            tasks.push(tokio::spawn(async move {
+                let id = format!("p{i}");
+                store
+                    .upsert("kb_1", vec![doc(id.as_str(), vec![1.0, i as f32], "en")])
+                    .await
+            }));
+        }
+        for task in tasks {
+            task.await.unwrap().unwrap();
+        }
+
+        //重新打开后从磁盘加载
+        let store = LocalVectorStore::new(path.to_string_lossy().to_string());
+        let mut query = VectorQuery {
+            vector: vec![1.0, 0.0],
+            top_k: 2,
+            ..Default::default()
+        };
+        let res = store.query("kb/1", &query).await.unwrap();
+        let ids = res.iter().map(|x| x.id.as_str()).collect::<Vec<_>>();
+        assert_eq!(vec!["a", "b"], ids);
+        query.top_k = 100;
+        assert_eq!(3, store.query("kb/1", &query).await.unwrap().len());
+        assert_eq!(20, store.query("kb_1", &query).await.unwrap().len());
+        query.top_k = 2;
+
+        query.filter = serde_json::json!({"lang":"en"})
+            .as_object()
+            .unwrap()
+            .clone();
+        let res = store.query("kb/1", &query).await.unwrap();
+        let ids = res.iter().map(|x| x.id.as_str()).collect::<Vec<_>>();
+        assert_eq!(vec!["a", "c"], ids);
+
+        query.filter = serde_json::json!({"lang":["zh","fr"]})
+            .as_object()
+            .unwrap()
+            .clone();
+        query.min_score = 0.99;
+        assert!(store.query("kb/1", &query).await.unwrap().is_empty());
+        assert!(store.query("empty", &query).await.unwrap().is_empty());
+        let _ = std::fs::remove_dir_all(&path);
+    }
+
+    //cargo test rt_node_service::vector_store::test::test_vector_nodes -- --nocapture
+    #[tokio::test]
+    async fn test_vector_nodes() {
+        let store = Arc::new(LocalVectorStore::default());
+        let embedding = EmbeddingService::new()
+            .register_provider("hash", HashEmbeddingProvider::default())
+            .default_provider("hash");
+        let rt = Runtime::default()
+            .register_service_layer(
+                "vector_upsert",
+                VectorUpsertService::new(store.clone(), embedding.clone()),
+            )
+            .register_service_layer("retrieve", VectorQueryService::new(store, embedding))
+            .register_service_layer("var", VarFlowChartService::default())
+            .launch();
+
+        let docs = serde_json::json!([
+            {"text":"rust is a systems programming language","metadata":{"topic":"rust"}},
+            {"text":"the cat sleeps on the sofa","metadata":{"topic":"pet"}},
+            {"id":"dog","text":"the dog plays with the cat","metadata":{"topic":"pet"}},
+        ]);
+        let cfg = r#"{"collection":"kb","docs":"{{start.docs}}"}"#;
+        let res = rt
+            .ctx(
+                "test_vector_upsert",
+                PlanBuilder::single_node("vector_upsert", cfg).build(),
+            )
+            .arc()
+            .block_on::<Value, _>(serde_json::json!({ "docs": docs }))
+            .await
+            .unwrap();
+        let resp = serde_json::from_value::<VectorUpsertResponse>(res).unwrap();
+        assert_eq!(3, resp.count);
+        assert_eq!(16, resp.ids[0].len());
+        assert_eq!("dog", resp.ids[2].as_str());
+
+        let cfg =
+            r#"{"collection":"kb","query":"{{start.query}}","top_k":1,"filter":{"topic":"pet"}}"#;
+        let plan = PlanBuilder::start(("retrieve", "retrieve", cfg), vec!["end"])
+            .sequence(
+                vec![("end", "var", r#"{"prompt":"资料：{{retrieve.docs}}"}"#)],
+                "",
+            )
+            .check_and_build()
+            .unwrap();
+        let res = rt
+            .ctx("test_vector_query", plan)
+            .arc()
+            .block_on::<Value, _>(serde_json::json!({"query":"cat on the sofa"}))
+            .await
+            .unwrap();
+        assert_eq!(
+            serde_json::json!({"prompt":"资料：the cat sleeps on the sofa"}),
+            res
+        );
+    }
+}
@@ -1,22 +1,84 @@
-use crate::rt_node_service::CfgBound;
-use agent_rt::{Context, Plan};
-use serde::{Deserialize, Serialize};
+use crate::rt_node_service::{
+    CfgBound, WorkflowDefinition, WorkflowRef, WorkflowRegistry, WorkflowSourceFile,
+};
+use agent_rt::{fill_json_schema_default, validate_json_schema, Context, Plan};
 use serde_json::Value;
+use std::collections::HashMap;
+use std::str::FromStr;
 use std::sync::Arc;
 use wd_tools::{PFArc, PFErr};
 
 #[async_trait::async_trait]
 pub trait WorkflowLoader: Sync + Send {
     async fn load(&self, name: &str) -> anyhow::Result<Box<dyn Plan>>;
+    //一次读取同时返回计划和工作流定义，定义用于校验输入输出，默认没有
+    async fn load_with_definition(
+        &self,
+        name: &str,
+    ) -> anyhow::Result<(Box<dyn Plan>, Option<Arc<WorkflowDefinition>>)> {
+        Ok((self.load(name).await?, None))
+    }
 }
 pub struct WorkflowService {
     pub loader: Box<dyn WorkflowLoader + 'static>,
+    //未指定版本时使用的固定版本
+    pub pins: HashMap<String, u32>,
 }
 
 impl WorkflowService {
     pub fn new<I: WorkflowLoader + 'static>(loader: I) -> Self {
         let loader = Box::new(loader);
-        Self { loader }
+        let pins = HashMap::new();
+        Self { loader, pins }
+    }
+    pub fn pin<S: Into<String>>(mut self, name: S, version: u32) -> Self {
+        self.pins.insert(name.into(), version);
+        self
+    }
+    //优先级: workflow_name中的@版本 > workflow_version > pin
+    pub fn reference(&self, name: &str, version: Option<u32>) -> anyhow::Result<String> {
+        let mut reference = WorkflowRef::from_str(name)?;
+        if reference.version.is_none() && !name.ends_with("@latest") {
+            reference.version = version.or_else(|| self.pins.get(&reference.name).copied());
+        }
+        Ok(reference.to_string())
+    }
+    //声明了input时去掉调用参数，补全默认值后校验；未声明时原样传入
+    pub fn check_input(
+        name: &str,
+        def: Option<&WorkflowDefinition>,
+        mut input: Value,
+    ) -> anyhow::Result<Value> {
+        let Some(schema) = def.and_then(|x| x.input.as_ref()) else {
+            return Ok(input);
+        };
+        if let Value::Object(ref mut map) = input {
+            map.remove("workflow_name");
+            map.remove("workflow_version");
+        }
+        fill_json_schema_default(schema, &mut input);
+        let errors = validate_json_schema(schema, &input);
+        if !errors.is_empty() {
+            return anyhow::anyhow!("workflow[{}] input mismatch: {}", name, errors.join("; "))
+                .err();
+        }
+        Ok(input)
+    }
+    pub fn check_output(
+        name: &str,
+        def: Option<&WorkflowDefinition>,
+        mut output: Value,
+    ) -> anyhow::Result<Value> {
+        let Some(schema) = def.and_then(|x| x.output.as_ref()) else {
+            return Ok(output);
+        };
+        fill_json_schema_default(schema, &mut output);
+        let errors = validate_json_schema(schema, &output);
+        if !errors.is_empty() {
+            return anyhow::anyhow!("workflow[{}] output mismatch: {}", name, errors.join("; "))
+                .err();
+        }
+        Ok(output)
     }
 }
 
@@ -32,24 +94,42 @@ impl agent_rt::ServiceLayer for WorkflowService {
         cfg: Self::Config,
     ) -> anyhow::Result<Self::Output> {
         let input = cfg.bound(&ctx)?;
-        let name = if let Value::Object(ref map) = input {
-            map.get("workflow_name")
+        let (name, version) = if let Value::Object(ref map) = input {
+            let name = map
+                .get("workflow_name")
                 .map(|x| x.as_str().map(|x| x.to_string()).unwrap_or("".to_string()))
-                .unwrap_or("".to_string())
+                .unwrap_or("".to_string());
+            let version = match map.get("workflow_version") {
+                None | Some(Value::Null) => None,
+                Some(v) => {
+                    let version = v
+                        .as_u64()
+                        .and_then(|x| u32::try_from(x).ok())
+                        .or_else(|| v.as_str().and_then(|x| x.parse::<u32>().ok()));
+                    if version.is_none() {
+                        return anyhow::anyhow!("WorkflowService: invalid workflow_version[{}]", v)
+                            .err();
+                    }
+                    version
+                }
+            };
+            (name, version)
         } else {
             return anyhow::anyhow!("WorkflowService.config must is object").err();
         };
         if name.is_empty() {
             return anyhow::anyhow!("WorkflowService: workflow_name is nil").err();
         }
-        let plan = self.loader.load(name.as_str()).await?;
+        let name = self.reference(name.as_str(), version)?;
+        let (plan, def) = self.loader.load_with_definition(name.as_str()).await?;
+        let input = Self::check_input(name.as_str(), def.as_deref(), input)?;
         let sub_task_code = format!("{}-{}", ctx.code, name);
         let output = ctx
             .sub_ctx(sub_task_code, plan)
             .arc()
             .block_on::<Value, _>(input)
             .await?;
-        Ok(output)
+        Self::check_output(name.as_str(), def.as_deref(), output)
     }
 }
 
@@ -65,61 +145,42 @@ impl Default for WorkflowLoaderFile {
 
 impl Default for WorkflowService {
     fn default() -> Self {
-        WorkflowService::new(WorkflowLoaderFile::default())
+        WorkflowService::new(WorkflowRegistry::new(WorkflowSourceFile::default()))
     }
 }
 
-#[derive(Debug, Default, Clone, Deserialize, Serialize)]
-struct WorkflowNodePlan {
-    pub plan: Vec<WorkflowNode>,
-}
-#[derive(Debug, Default, Clone, Deserialize, Serialize)]
-#[serde(default)]
-struct WorkflowNode {
-    pub code: String,
-    pub service_type: String,
-    pub cfg: String,
-    pub ready_nodes: Vec<String>,
-    pub goto_nodes: Vec<String>,
-}
-
 #[async_trait::async_trait]
 impl WorkflowLoader for WorkflowLoaderFile {
     async fn load(&self, name: &str) -> anyhow::Result<Box<dyn Plan>> {
-        let path = format!("{}/{}", self.path, name);
-        let data = tokio::fs::read(path).await?;
-
-        let plan = serde_json::from_slice::<WorkflowNodePlan>(data.as_slice())?;
-
-        let nodes = plan
-            .plan
-            .into_iter()
-            .map(|x| {
-                let WorkflowNode {
-                    code,
-                    service_type,
-                    cfg,
-                    ready_nodes,
-                    goto_nodes,
-                } = x;
-                (
-                    ready_nodes,
-                    agent_rt::Node::new(code, service_type, cfg),
-                    goto_nodes,
-                )
-            })
-            .collect::<Vec<_>>();
-
-        let plan = agent_rt::PlanBuilder::from(nodes).build();
+        let plan = self.read(name).await?.builder().build();
 
         Ok(Box::new(plan) as Box<dyn Plan>)
     }
+    async fn load_with_definition(
+        &self,
+        name: &str,
+    ) -> anyhow::Result<(Box<dyn Plan>, Option<Arc<WorkflowDefinition>>)> {
+        let def = self.read(name).await?;
+        let plan = def.builder().build();
+        Ok((Box::new(plan) as Box<dyn Plan>, Some(def.arc())))
+    }
+}
+
+impl WorkflowLoaderFile {
+    async fn read(&self, name: &str) -> anyhow::Result<WorkflowDefinition> {
+        let path = format!("{}/{}", self.path, name);
+        let data = tokio::fs::read(path).await?;
+        WorkflowDefinition::parse(data.as_slice(), "")
+    }
 }
 
 #[cfg(test)]
 mod test {
     use crate::rt_node_service::workflow::WorkflowService;
-    use crate::rt_node_service::{InjectorService, PythonCodeService, SelectorService};
+    use crate::rt_node_service::{
+        InjectorService, PythonCodeService, SelectorService, VarFlowChartService,
+        WorkflowDefinition, WorkflowRegistry, WorkflowSourceMemory,
+    };
     use agent_rt::PlanBuilder;
     use serde_json::Value;
     use wd_tools::PFArc;
@@ -162,4 +223,92 @@ mod test {
 
         println!("{}", output);
     }
+
+    //cargo test rt_node_service::workflow::test::test_workflow_contract -- --nocapture
+    #[tokio::test]
+    async fn test_workflow_contract() {
+        let yaml = r#"
+name: search
+input:
+  type: object
+  required: [query]
+  additionalProperties: false
+  properties:
+    query: {type: string, minLength: 1}
+    top_k: {type: integer, default: 3}
+output:
+  type: object
+  required: [answer]
+  properties:
+    answer: {type: string}
+plan:
+  - code: start
+    service_type: flow_chart_var
+    goto_nodes: [end]
+  - code: end
+    service_type: flow_chart_var
+    cfg:
+      answer: "{{start.query}}"
+      top_k: "{{start.top_k}}"
+"#;
+        let mut def = WorkflowDefinition::parse(yaml.as_bytes(), "").unwrap();
+        let source = WorkflowSourceMemory::default().workflow(def.clone());
+        //v1的输出不满足output声明
+        def.version = 1;
+        def.plan[1].cfg = r#"{"answer":"{{start.top_k}}"}"#.into();
+        source.insert(def);
+
+        let rt = agent_rt::Runtime::default()
+            .register_service_layer(
+                "workflow",
+                WorkflowService::new(WorkflowRegistry::new(source)).pin("search", 0),
+            )
+            .register_service_layer("flow_chart_var", VarFlowChartService::default())
+            .launch();
+        let call = |cfg: Value| {
+            let rt = rt.clone();
+            async move {
+                let cfg = serde_json::to_string(&cfg).unwrap();
+                rt.ctx(
+                    "test-wf-contract",
+                    PlanBuilder::single_node("workflow", cfg).build(),
+                )
+                .arc()
+                .block_on::<Value, _>(serde_json::json!({"query":"hello"}))
+                .await
+            }
+        };
+
+        //workflow_name不会传给子流程，top_k使用默认值
+        let out = call(serde_json::json!({"workflow_name":"search","query":"{{start.query}}"}))
+            .await
+            .unwrap();
+        assert_eq!(out, serde_json::json!({"answer":"hello","top_k":3}));
+
+        let err = call(serde_json::json!({"workflow_name":"search","query":"","lang":"zh"}))
+            .await
+            .unwrap_err()
+            .to_string();
+        println!("{}", err);
+        assert!(err.contains("workflow[search@0] input mismatch"));
+        assert!(err.contains("$.query: length 0 < minLength 1"));
+        assert!(err.contains("$.lang: additional property is not allowed"));
+
+        let err = call(
+            serde_json::json!({"workflow_name":"search","query":"{{start.query}}","top_k":"3"}),
+        )
+        .await
+        .unwrap_err()
+        .to_string();
+        assert!(err.contains("$.top_k: expect type \"integer\", found string"));
+
+        let err = call(serde_json::json!({"workflow_name":"search@1","query":"{{start.query}}"}))
+            .await
+            .unwrap_err()
+            .to_string();
+        println!("{}", err);
+        assert!(err.contains(
+            "workflow[search@1] output mismatch: $.answer: expect type \"string\", found integer"
+        ));
+    }
 }
@@ -0,0 +1,616 @@
+use crate::rt_node_service::WorkflowLoader;
+use agent_rt::{Plan, PlanBuilder};
+use notify::Watcher;
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{Display, Formatter};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+use wd_tools::PFErr;
+
+//工作流引用: name 或 name@latest 表示最新版本，name@3 表示固定版本
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct WorkflowRef {
+    pub name: String,
+    pub version: Option<u32>,
+}
+
+impl FromStr for WorkflowRef {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (name, version) = match s.rsplit_once('@') {
+            Some((name, "")) | Some((name, "latest")) => (name, None),
+            Some((name, v)) => match v.parse::<u32>() {
+                Ok(v) => (name, Some(v)),
+                Err(_) => {
+                    return anyhow::anyhow!("workflow[{}] invalid version[{}]", s, v).err();
+                }
+            },
+            None => (s, None),
+        };
+        if name.is_empty() {
+            return anyhow::anyhow!("workflow[{}] name is empty", s).err();
+        }
+        Ok(Self {
+            name: name.to_string(),
+            version,
+        })
+    }
+}
+
+impl Display for WorkflowRef {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.version {
+            Some(v) => write!(f, "{}@{}", self.name, v),
+            None => write!(f, "{}", self.name),
+        }
+    }
+}
+
+//工作流定义，支持json和yaml
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct WorkflowDefinition {
+    pub name: String,
+    pub version: u32,
+    //输入输出的json schema，支持default
+    pub input: Option<Value>,
+    pub output: Option<Value>,
+    pub plan: Vec<WorkflowNode>,
+}
+
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct WorkflowNode {
+    pub code: String,
+    pub service_type: String,
+    //可以是json字符串，也可以直接写对象
+    #[serde(deserialize_with = "node_cfg")]
+    pub cfg: String,
+    pub ready_nodes: Vec<String>,
+    pub goto_nodes: Vec<String>,
+}
+
+fn node_cfg<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    let cfg = match Value::deserialize(deserializer)? {
+        Value::Null => String::new(),
+        Value::String(s) => s,
+        v => v.to_string(),
+    };
+    Ok(cfg)
+}
+
+impl WorkflowDefinition {
+    //format为空时先按json解析，失败再按yaml解析
+    pub fn parse(data: &[u8], format: &str) -> anyhow::Result<Self> {
+        let def = match format {
+            "json" => serde_json::from_slice::<Self>(data)?,
+            "yaml" | "yml" => serde_yaml::from_slice::<Self>(data)?,
+            _ => match serde_json::from_slice::<Self>(data) {
+                Ok(o) => o,
+                Err(_) => serde_yaml::from_slice::<Self>(data)?,
+            },
+        };
+        Ok(def)
+    }
+    pub fn builder(&self) -> PlanBuilder {
+        let nodes = self
+            .plan
+            .iter()
+            .map(|x| {
+                (
+                    x.ready_nodes.clone(),
+                    agent_rt::Node::new(x.code.as_str(), x.service_type.as_str(), x.cfg.as_str()),
+                    x.goto_nodes.clone(),
+                )
+            })
+            .collect::<Vec<_>>();
+        PlanBuilder::from(nodes)
+    }
+}
+
+pub type WorkflowWatchCallback = Arc<dyn Fn(&str) + Send + Sync + 'static>;
+
+#[async_trait::async_trait]
+pub trait WorkflowSource: Sync + Send {
+    //version为空时返回最新版本
+    async fn fetch(&self, name: &str, version: Option<u32>) -> anyhow::Result<WorkflowDefinition>;
+    //定义变化时通过callback通知工作流名称，默认不监听
+    fn watch(&self, _callback: WorkflowWatchCallback) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+struct WorkflowCache {
+    def: Arc<WorkflowDefinition>,
+    builder: PlanBuilder,
+    time: Instant,
+}
+
+//带缓存的工作流加载器，缓存解析后的plan，source变化或过期后重新加载
+pub struct WorkflowRegistry {
+    source: Box<dyn WorkflowSource + 'static>,
+    cache: Arc<Mutex<HashMap<WorkflowRef, WorkflowCache>>>,
+    ttl: Option<Duration>,
+}
+
+impl WorkflowRegistry {
+    pub fn new<S: WorkflowSource + 'static>(source: S) -> Self {
+        let cache: Arc<Mutex<HashMap<WorkflowRef, WorkflowCache>>> = Arc::default();
+        let watch_cache = cache.clone();
+        let callback: WorkflowWatchCallback = Arc::new(move |name: &str| {
+            let mut lock = watch_cache.lock().unwrap();
+            lock.retain(|k, _| k.name != name);
+        });
+        if let Err(e) = source.watch(callback) {
+            wd_log::log_warn_ln!("WorkflowRegistry watch source failed:{}", e);
+        }
+        let source = Box::new(source);
+        Self {
+            source,
+            cache,
+            ttl: None,
+        }
+    }
+    //缓存过期时间，适用于无法监听变化的source
+    pub fn ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = Some(ttl);
+        self
+    }
+    pub fn invalidate(&self, name: &str) {
+        let mut lock = self.cache.lock().unwrap();
+        lock.retain(|k, _| k.name != name);
+    }
+    async fn get(&self, reference: &str) -> anyhow::Result<(Arc<WorkflowDefinition>, PlanBuilder)> {
+        let key = WorkflowRef::from_str(reference)?;
+        {
+            let lock = self.cache.lock().unwrap();
+            if let Some(cache) = lock.get(&key) {
+                if self.ttl.map(|x| cache.time.elapsed() < x).unwrap_or(true) {
+                    return Ok((cache.def.clone(), cache.builder.clone()));
+                }
+            }
+        }
+        let def = self
+            .source
+            .fetch(key.name.as_str(), key.version)
+            .await
+            .map_err(|e| anyhow::anyhow!("workflow[{}] load failed:{}", key, e))?;
+        let def = Arc::new(def);
+        let builder = def.builder();
+        let mut lock = self.cache.lock().unwrap();
+        lock.insert(
+            key,
+            WorkflowCache {
+                def: def.clone(),
+                builder: builder.clone(),
+                time: Instant::now(),
+            },
+        );
+        Ok((def, builder))
+    }
+    pub async fn definition(&self, reference: &str) -> anyhow::Result<Arc<WorkflowDefinition>> {
+        let (def, _) = self.get(reference).await?;
+        Ok(def)
+    }
+}
+
+#[async_trait::async_trait]
+impl WorkflowLoader for WorkflowRegistry {
+    async fn load(&self, name: &str) -> anyhow::Result<Box<dyn Plan>> {
+        let (_, mut builder) = self.get(name).await?;
+        Ok(Box::new(builder.build()) as Box<dyn Plan>)
+    }
+    async fn load_with_definition(
+        &self,
+        name: &str,
+    ) -> anyhow::Result<(Box<dyn Plan>, Option<Arc<WorkflowDefinition>>)> {
+        let (def, mut builder) = self.get(name).await?;
+        Ok((Box::new(builder.build()) as Box<dyn Plan>, Some(def)))
+    }
+}
+
+//目录下的工作流文件: name、name@3，可带.json/.yaml/.yml后缀，无版本号视为0
+pub struct WorkflowSourceFile {
+    pub path: String,
+    watcher: Mutex<Option<notify::RecommendedWatcher>>,
+}
+
+impl WorkflowSourceFile {
+    pub fn new<S: Into<String>>(path: S) -> Self {
+        let path = path.into();
+        let watcher = Mutex::new(None);
+        Self { path, watcher }
+    }
+    //返回 (name,version,format)
+    pub fn parse_file_name(file: &str) -> Option<(String, u32, String)> {
+        let (stem, format) = match file.rsplit_once('.') {
+            Some((stem, ext)) if ["json", "yaml", "yml"].contains(&ext) => (stem, ext),
+            _ => (file, ""),
+        };
+        if stem.is_empty() || stem.starts_with('.') {
+            return None;
+        }
+        let (name, version) = match stem.rsplit_once('@') {
+            Some((name, v)) => (name, v.parse::<u32>().ok()?),
+            None => (stem, 0),
+        };
+        Some((name.to_string(), version, format.to_string()))
+    }
+}
+
+impl Default for WorkflowSourceFile {
+    fn default() -> Self {
+        WorkflowSourceFile::new("./workflow")
+    }
+}
+
+#[async_trait::async_trait]
+impl WorkflowSource for WorkflowSourceFile {
+    async fn fetch(&self, name: &str, version: Option<u32>) -> anyhow::Result<WorkflowDefinition> {
+        let mut dir = tokio::fs::read_dir(self.path.as_str()).await?;
+        let mut found: Option<(u32, PathBuf, String)> = None;
+        while let Some(entry) = dir.next_entry().await? {
+            let file = entry.file_name();
+            let Some((n, v, format)) = Self::parse_file_name(file.to_string_lossy().as_ref())
+            else {
+                continue;
+            };
+            if n != name || version.map(|x| x != v).unwrap_or(false) {
+                continue;
+            }
+            if found.as_ref().map(|x| v > x.0).unwrap_or(true) {
+                found = Some((v, entry.path(), format));
+            }
+        }
+        let Some((version, path, format)) = found else {
+            return anyhow::anyhow!("not found in dir[{}]", self.path).err();
+        };
+        let data = tokio::fs::read(path).await?;
+        let mut def = WorkflowDefinition::parse(data.as_slice(), format.as_str())?;
+        def.name = name.to_string();
+        def.version = version;
+        Ok(def)
+    }
+
+    fn watch(&self, callback: WorkflowWatchCallback) -> anyhow::Result<()> {
+        let mut watcher =
+            notify::recommended_watcher(move |res: notify::Result<notify::Event>| {
+                let event = match res {
+                    Ok(o) => o,
+                    Err(e) => {
+                        wd_log::log_warn_ln!("WorkflowSourceFile watch error:{}", e);
+                        return;
+                    }
+                };
+                for path in event.paths.iter() {
+                    let file = path.file_name().map(|x| x.to_string_lossy());
+                    if let Some((name, _, _)) = file.and_then(|x| Self::parse_file_name(&x)) {
+                        callback(name.as_str());
+                    }
+                }
+            })?;
+        watcher.watch(
+            Path::new(self.path.as_str()),
+            notify::RecursiveMode::NonRecursive,
+        )?;
+        *self.watcher.lock().unwrap() = Some(watcher);
+        Ok(())
+    }
+}
+
+//内存中的工作流，clone后共享同一份数据，便于测试中修改
+#[derive(Clone, Default)]
+pub struct WorkflowSourceMemory {
+    map: Arc<RwLock<HashMap<String, BTreeMap<u32, WorkflowDefinition>>>>,
+    callback: Arc<Mutex<Option<WorkflowWatchCallback>>>,
+}
+
+impl WorkflowSourceMemory {
+    pub fn workflow(self, def: WorkflowDefinition) -> Self {
+        self.insert(def);
+        self
+    }
+    pub fn insert(&self, def: WorkflowDefinition) {
+        let name = def.name.clone();
+        self.map
+            .write()
+            .unwrap()
+            .entry(name.clone())
+            .or_default()
+            .insert(def.version, def);
+        self.notify(name.as_str());
+    }
+    pub fn remove(&self, name: &str, version: Option<u32>) {
+        {
+            let mut lock = self.map.write().unwrap();
+            match version {
+                Some(v) => {
+                    if let Some(versions) = lock.get_mut(name) {
+                        versions.remove(&v);
+                    }
+                }
+                None => {
+                    lock.remove(name);
+                }
+            }
+        }
+        self.notify(name);
+    }
+    fn notify(&self, name: &str) {
+        let callback = self.callback.lock().unwrap().clone();
+        if let Some(callback) = callback {
+            callback(name);
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl WorkflowSource for WorkflowSourceMemory {
+    async fn fetch(&self, name: &str, version: Option<u32>) -> anyhow::Result<WorkflowDefinition> {
+        let lock = self.map.read().unwrap();
+        let def = lock.get(name).and_then(|x| match version {
+            Some(v) => x.get(&v),
+            None => x.values().next_back(),
+        });
+        match def {
+            Some(def) => Ok(def.clone()),
+            None => anyhow::anyhow!("not found in memory").err(),
+        }
+    }
+
+    fn watch(&self, callback: WorkflowWatchCallback) -> anyhow::Result<()> {
+        *self.callback.lock().unwrap() = Some(callback);
+        Ok(())
+    }
+}
+
+//从配置服务拉取: GET {url}/{name}?version=3，按content-type解析json或yaml
+pub struct WorkflowSourceHttp {
+    pub url: String,
+    pub headers: HashMap<String, String>,
+    client: reqwest::Client,
+}
+
+impl WorkflowSourceHttp {
+    pub fn new<S: Into<String>>(url: S) -> Self {
+        let url = url.into().trim_end_matches('/').to_string();
+        Self {
+            url,
+            headers: HashMap::new(),
+            client: reqwest::Client::new(),
+        }
+    }
+    pub fn header<K: Into<String>, V: Into<String>>(mut self, key: K, value: V) -> Self {
+        self.headers.insert(key.into(), value.into());
+        self
+    }
+    pub fn timeout(mut self, timeout: Duration) -> anyhow::Result<Self> {
+        self.client = reqwest::Client::builder().timeout(timeout).build()?;
+        Ok(self)
+    }
+}
+
+#[async_trait::async_trait]
+impl WorkflowSource for WorkflowSourceHttp {
+    async fn fetch(&self, name: &str, version: Option<u32>) -> anyhow::Result<WorkflowDefinition> {
+        //name作为一个路径段编码，避免其中的/、?、#改变请求的地址
+        let mut url = reqwest::Url::parse(self.url.as_str())?;
+        match url.path_segments_mut() {
+            Ok(mut path) => {
+                path.pop_if_empty().push(name);
+            }
+            Err(_) => return anyhow::anyhow!("invalid workflow url[{}]", self.url).err(),
+        }
+        let mut req = self.client.get(url);
+        if let Some(v) = version {
+            req = req.query(&[("version", v)]);
+        }
+        for (k, v) in self.headers.iter() {
+            req = req.header(k, v);
+        }
+        let resp = req.send().await?;
+        let status = resp.status();
+        let content_type = resp
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|x| x.to_str().ok())
+            .unwrap_or("")
+            .to_lowercase();
+        let data = resp.bytes().await?;
+        if !status.is_success() {
+            return anyhow::anyhow!(
+                "http status[{}] {}",
+                status,
+                String::from_utf8_lossy(data.as_ref())
+            )
+            .err();
+        }
+        let format = if content_type.contains("yaml") {
+            "yaml"
+        } else if content_type.contains("json") {
+            "json"
+        } else {
+            ""
+        };
+        let mut def = WorkflowDefinition::parse(data.as_ref(), format)?;
+        if def.name.is_empty() {
+            def.name = name.to_string();
+        }
+        if let Some(v) = version {
+            if def.version != v {
+                return anyhow::anyhow!("want version[{}] but got[{}]", v, def.version).err();
+            }
+        }
+        Ok(def)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::llm_provider::mock::MockServer;
+    use crate::rt_node_service::{
+        VarFlowChartService, WorkflowDefinition, WorkflowRef, WorkflowRegistry, WorkflowService,
+        WorkflowSource, WorkflowSourceFile, WorkflowSourceHttp, WorkflowSourceMemory,
+    };
+    use agent_rt::PlanBuilder;
+    use serde_json::Value;
+    use std::str::FromStr;
+    use std::time::Duration;
+    use wd_tools::PFArc;
+
+    fn echo(version: u32) -> WorkflowDefinition {
+        let yaml = format!(
+            r#"
+name: echo
+version: {version}
+plan:
+  - code: start
+    service_type: flow_chart_var
+    goto_nodes: [end]
+  - code: end
+    service_type: flow_chart_var
+    cfg:
+      version: {version}
+      query: "{{{{start.query}}}}"
+"#
+        );
+        WorkflowDefinition::parse(yaml.as_bytes(), "yaml").unwrap()
+    }
+
+    //cargo test rt_node_service::workflow_registry::test::test_workflow_ref -- --nocapture
+    #[test]
+    fn test_workflow_ref() {
+        let r = WorkflowRef::from_str("echo@3").unwrap();
+        assert_eq!((r.name.as_str(), r.version), ("echo", Some(3)));
+        assert_eq!(WorkflowRef::from_str("echo@latest").unwrap().version, None);
+        assert_eq!(WorkflowRef::from_str("echo").unwrap().to_string(), "echo");
+        assert!(WorkflowRef::from_str("echo@v3").is_err());
+        assert!(WorkflowRef::from_str("@3").is_err());
+
+        let parse = WorkflowSourceFile::parse_file_name;
+        assert_eq!(parse("echo"), Some(("echo".into(), 0, "".into())));
+        assert_eq!(parse("echo@2.yml"), Some(("echo".into(), 2, "yml".into())));
+        assert_eq!(parse("a.b.json"), Some(("a.b".into(), 0, "json".into())));
+        assert_eq!(parse("echo@x.json"), None);
+        assert_eq!(parse(".echo.swp"), None);
+
+        let def = echo(1);
+        assert_eq!(def.plan[0].cfg, "");
+        let cfg = serde_json::from_str::<Value>(def.plan[1].cfg.as_str()).unwrap();
+        assert_eq!(
+            cfg,
+            serde_json::json!({"version":1,"query":"{{start.query}}"})
+        );
+    }
+
+    //cargo test rt_node_service::workflow_registry::test::test_workflow_registry -- --nocapture
+    #[tokio::test]
+    async fn test_workflow_registry() {
+        let source = WorkflowSourceMemory::default()
+            .workflow(echo(1))
+            .workflow(echo(2));
+        let service = WorkflowService::new(WorkflowRegistry::new(source.clone())).pin("echo", 1);
+        let rt = agent_rt::Runtime::default()
+            .register_service_layer("workflow", service)
+            .register_service_layer("flow_chart_var", VarFlowChartService::default())
+            .launch();
+
+        let call = |cfg: Value| {
+            let rt = rt.clone();
+            async move {
+                let cfg = serde_json::to_string(&cfg).unwrap();
+                rt.ctx(
+                    "test-wf-registry",
+                    PlanBuilder::single_node("workflow", cfg).build(),
+                )
+                .arc()
+                .block_on::<Value, _>(serde_json::json!({"query":"hello"}))
+                .await
+            }
+        };
+
+        //pin生效
+        let out = call(serde_json::json!({"workflow_name":"echo","query":"{{start.query}}"}))
+            .await
+            .unwrap();
+        assert_eq!(out, serde_json::json!({"version":1,"query":"hello"}));
+        let out = call(serde_json::json!({"workflow_name":"echo@latest"}))
+            .await
+            .unwrap();
+        assert_eq!(out["version"], 2);
+        let out = call(serde_json::json!({"workflow_name":"echo","workflow_version":2}))
+            .await
+            .unwrap();
+        assert_eq!(out["version"], 2);
+
+        //新版本写入后缓存失效
+        source.insert(echo(3));
+        let out = call(serde_json::json!({"workflow_name":"echo@latest"}))
+            .await
+            .unwrap();
+        assert_eq!(out["version"], 3);
+
+        let err = call(serde_json::json!({"workflow_name":"echo@9"}))
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("workflow[echo@9] load failed"));
+    }
+
+    //cargo test rt_node_service::workflow_registry::test::test_workflow_registry_file -- --nocapture
+    #[tokio::test]
+    async fn test_workflow_registry_file() {
+        let dir = std::env::temp_dir().join(format!("wd_agent_workflow_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let json = |version: u32| serde_json::to_vec(&echo(version)).unwrap();
+        std::fs::write(
+            dir.join("echo.yaml"),
+            serde_yaml::to_string(&echo(0)).unwrap(),
+        )
+        .unwrap();
+        std::fs::write(dir.join("echo@2.json"), json(2)).unwrap();
+
+        let registry = WorkflowRegistry::new(WorkflowSourceFile::new(dir.to_string_lossy()));
+        assert_eq!(registry.definition("echo").await.unwrap().version, 2);
+        assert_eq!(registry.definition("echo@0").await.unwrap().version, 0);
+        assert!(registry.definition("echo@1").await.is_err());
+
+        //文件变化后重新加载
+        std::fs::write(dir.join("echo@5.json"), json(5)).unwrap();
+        let mut version = 0;
+        for _ in 0..50 {
+            version = registry.definition("echo").await.unwrap().version;
+            if version == 5 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(version, 5);
+    }
+
+    //cargo test rt_node_service::workflow_registry::test::test_workflow_source_http -- --nocapture
+    #[tokio::test]
+    async fn test_workflow_source_http() {
+        let def = serde_json::to_value(echo(2)).unwrap();
+        let server =
+            MockServer::start(vec![MockServer::json(def.clone()), MockServer::json(def)]).await;
+        let source = WorkflowSourceHttp::new(format!("{}/workflows/", server.url))
+            .header("authorization", "Bearer token");
+
+        let def = source.fetch("echo", Some(2)).await.unwrap();
+        assert_eq!(2, def.version);
+        let def = source.fetch("a/b?c#d", None).await.unwrap();
+        assert_eq!("echo", def.name.as_str());
+
+        let requests = server.requests();
+        assert_eq!("/workflows/echo?version=2", requests[0].path.as_str());
+        assert_eq!("Bearer token", requests[0].header("authorization"));
+        assert_eq!("/workflows/a%2Fb%3Fc%23d", requests[1].path.as_str());
+    }
+}